@@ -0,0 +1,123 @@
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, Data, DeriveInput, Expr, Field, Fields};
+
+/// Derives `TensorConvertible` for a struct with named fields: its values are those of its fields, one after the
+/// other in declaration order. Their names are the field names, joined with dots.
+///
+/// A `Vec` field needs a fixed length to fit in a tensor, `#[tensor(pad = N)]` gives it one. Its items go last,
+/// after as many `Default` items as it takes to make `N`; items equal to the default are dropped when reading back,
+/// and those past `N` never make it into the values. With `#[tensor(pad = N, keep = key)]` the items that make it are
+/// the `N` with the largest `key(&item) -> f32`, still in their order, so that which ones are left out doesn't depend
+/// on the order they come in.
+///
+/// The generated code refers to `crate::tensor_conversion`, so the derive only works inside the brain crate.
+#[proc_macro_derive(TensorConvertible, attributes(tensor))]
+pub fn derive_tensor_convertible(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+enum FieldLayout {
+    // the field is `TensorConvertible` itself
+    Plain,
+    // a `Vec` of `TensorConvertible` items, padded to this many, keeping those with the largest key when there are
+    // more
+    Padded(Expr, Option<Expr>),
+}
+
+fn field_layout(field: &Field) -> syn::Result<FieldLayout> {
+    let mut pad = None;
+    let mut keep = None;
+    for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("tensor")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("pad") {
+                pad = Some(meta.value()?.parse()?);
+                Ok(())
+            } else if meta.path.is_ident("keep") {
+                keep = Some(meta.value()?.parse()?);
+                Ok(())
+            } else {
+                Err(meta.error("expected `pad = <length>` or `keep = <key>`"))
+            }
+        })?;
+    }
+    match (pad, keep) {
+        (Some(len), keep) => Ok(FieldLayout::Padded(len, keep)),
+        (None, None) => Ok(FieldLayout::Plain),
+        (None, Some(_)) => Err(syn::Error::new(field.span(), "`keep` only applies to `pad`ded fields")),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new(input.span(), "TensorConvertible needs named fields")),
+        },
+        _ => return Err(syn::Error::new(input.span(), "TensorConvertible can only be derived for structs")),
+    };
+
+    let conversion = quote! { crate::tensor_conversion };
+    let mut counts = Vec::new();
+    let mut iterators = Vec::new();
+    let mut readers = Vec::new();
+    let mut namers = Vec::new();
+    for field in fields {
+        let name = field.ident.as_ref().unwrap();
+        let ty = &field.ty;
+        let field_name = name.to_string();
+        match field_layout(field)? {
+            FieldLayout::Plain => {
+                counts.push(quote! { <#ty as #conversion::TensorConvertible>::VALUES_COUNT });
+                iterators.push(quote! { #conversion::TensorConvertible::iterate_values(&self.#name) });
+                readers.push(quote! { #name: <#ty as #conversion::TensorConvertible>::take_from_iter(&mut values_iter) });
+                namers.push(quote! {
+                    <#ty as #conversion::TensorConvertible>::value_names(&#conversion::join_name(prefix, #field_name), names)
+                });
+            }
+            FieldLayout::Padded(len, keep) => {
+                counts.push(quote! {
+                    (#len) * <<#ty as #conversion::PaddedItems>::Item as #conversion::TensorConvertible>::VALUES_COUNT
+                });
+                iterators.push(match keep {
+                    Some(key) => quote! { #conversion::padded_values_keeping(&self.#name, #len, #key) },
+                    None => quote! { #conversion::padded_values(&self.#name, #len) },
+                });
+                readers.push(quote! { #name: #conversion::take_padded(&mut values_iter, #len) });
+                namers.push(quote! {
+                    #conversion::padded_value_names::<<#ty as #conversion::PaddedItems>::Item>(
+                        &#conversion::join_name(prefix, #field_name), #len, names
+                    )
+                });
+            }
+        }
+    }
+
+    let ident = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    Ok(quote! {
+        impl #impl_generics #conversion::TensorConvertible for #ident #ty_generics #where_clause {
+            const VALUES_COUNT: usize = 0 #(+ #counts)*;
+
+            fn from_values(values: &[f32]) -> Self {
+                let mut values_iter = values.iter().cloned();
+                Self {
+                    #(#readers,)*
+                }
+            }
+
+            fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
+                ::std::iter::empty() #(.chain(#iterators))*
+            }
+
+            fn value_names(prefix: &str, names: &mut Vec<String>) {
+                #(#namers;)*
+            }
+        }
+    })
+}
@@ -0,0 +1,58 @@
+use std::net::SocketAddr;
+
+use clap::Parser;
+use tracing::{error, info};
+use walking_robot_brain::{
+    comm::{ConnectionRole, DEFAULT_SIMULATION_ADDR},
+    simulation::{biped::BipedSimConfig, mock::{MockSimulator, MockSimulatorConfig}},
+    types::protocol::WireFormat,
+};
+
+#[derive(Parser)]
+struct Args {
+    /// Address of the brain, or to listen on when it connects to the simulator
+    #[arg(long, default_value_t = DEFAULT_SIMULATION_ADDR)]
+    addr: SocketAddr,
+    /// Whether the simulator connects to the brain or waits for it
+    #[arg(long, value_enum, default_value_t = ConnectionRole::Connect)]
+    role: ConnectionRole,
+    #[arg(long, default_value_t = 0)]
+    seed: u64,
+    /// Length of the episodes in seconds, until the brain asks for another one
+    #[arg(long, default_value_t = BipedSimConfig::default().episode_duration)]
+    episode_duration: f32,
+    /// Wire formats offered to the brain, comma separated
+    #[arg(long, value_enum, value_delimiter = ',', default_values_t = [WireFormat::Json, WireFormat::Binary])]
+    wire_format: Vec<WireFormat>,
+}
+
+fn main() {
+    tokio
+    ::runtime
+    ::Builder
+    ::new_current_thread()
+    .enable_all()
+    .build()
+    .unwrap()
+    .block_on(async_main(Args::parse()))
+}
+
+async fn async_main(args: Args){
+    pretty_env_logger::init_timed();
+
+    info!("connecting to the brain");
+    let config = MockSimulatorConfig {
+        addr         : args.addr,
+        role         : args.role,
+        seed         : args.seed,
+        sim          : BipedSimConfig { episode_duration: args.episode_duration, ..Default::default() },
+        wire_formats : args.wire_format,
+    };
+    let result = match MockSimulator::connect(config).await {
+        Ok(simulator) => simulator.run(None).await,
+        Err(err) => Err(err),
+    };
+    if let Err(err) = result {
+        error!("lost the brain: {err}");
+    }
+}
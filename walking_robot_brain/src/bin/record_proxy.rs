@@ -0,0 +1,51 @@
+use std::{fs::File, io::BufWriter, net::{IpAddr, Ipv4Addr, SocketAddr}, path::PathBuf};
+
+use clap::Parser;
+use tokio::net::TcpListener;
+use tracing::{error, info};
+use walking_robot_brain::{
+    comm::{connect_retrying, DEFAULT_SIMULATION_ADDR},
+    recording::{relay, SessionWriter},
+};
+
+/// Sits between the simulation and the brain and records everything they say to each other
+#[derive(Parser)]
+struct Args {
+    /// Where the simulation connects to
+    #[arg(long, default_value_t = DEFAULT_SIMULATION_ADDR)]
+    listen: SocketAddr,
+    /// Where the brain listens, run it with `--addr` set to this
+    #[arg(long, default_value_t = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8081))]
+    brain: SocketAddr,
+    /// Session file to write
+    #[arg(long)]
+    output: PathBuf,
+}
+
+fn main() {
+    tokio
+    ::runtime
+    ::Builder
+    ::new_current_thread()
+    .enable_all()
+    .build()
+    .unwrap()
+    .block_on(async_main(Args::parse()))
+}
+
+async fn async_main(args: Args){
+    pretty_env_logger::init_timed();
+
+    let listener = TcpListener::bind(args.listen).await.expect("could not listen for the simulation");
+    info!("waiting for the simulation on {}", args.listen);
+    let (simulation, simulation_addr) = listener.accept().await.expect("could not accept the simulation");
+    info!("simulation connected from {simulation_addr}, connecting to the brain on {}", args.brain);
+    let brain = connect_retrying(args.brain).await;
+
+    let file = File::create(&args.output).expect("could not create the session file");
+    let mut session = SessionWriter::new(BufWriter::new(file)).expect("could not write the session file");
+    match relay(simulation, brain, &mut session).await {
+        Ok(()) => info!("session recorded to {}", args.output.display()),
+        Err(err) => error!("session stopped: {err}, what came before is in {}", args.output.display()),
+    }
+}
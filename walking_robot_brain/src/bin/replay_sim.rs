@@ -0,0 +1,62 @@
+use std::{fs::File, io::BufReader, net::SocketAddr, path::PathBuf};
+
+use clap::Parser;
+use tracing::{error, info, warn};
+use walking_robot_brain::{
+    comm::{ConnectionRole, DEFAULT_SIMULATION_ADDR},
+    recording::SessionReader,
+    simulation::replay::{ReplaySimulator, DEFAULT_ACTION_TOLERANCE},
+};
+
+/// Plays a recorded session to the brain in place of the simulation and reports where the brain answers differently
+#[derive(Parser)]
+struct Args {
+    /// Session file written by `record_proxy`
+    #[arg(long)]
+    session: PathBuf,
+    /// Address of the brain, or to listen on when it connects to the simulator
+    #[arg(long, default_value_t = DEFAULT_SIMULATION_ADDR)]
+    addr: SocketAddr,
+    /// Whether the simulator connects to the brain or waits for it
+    #[arg(long, value_enum, default_value_t = ConnectionRole::Connect)]
+    role: ConnectionRole,
+    /// Largest difference between a recorded action value and the brain's that is not a divergence
+    #[arg(long, default_value_t = DEFAULT_ACTION_TOLERANCE)]
+    tolerance: f32,
+}
+
+fn main() {
+    tokio
+    ::runtime
+    ::Builder
+    ::new_current_thread()
+    .enable_all()
+    .build()
+    .unwrap()
+    .block_on(async_main(Args::parse()))
+}
+
+async fn async_main(args: Args){
+    pretty_env_logger::init_timed();
+
+    let file = File::open(&args.session).expect("could not open the session file");
+    let frames = SessionReader::new(BufReader::new(file))
+        .and_then(|reader| reader.collect::<Result<Vec<_>, _>>())
+        .expect("could not read the session file");
+    info!("replaying {} frames from {}", frames.len(), args.session.display());
+
+    let result = match ReplaySimulator::connect(args.addr, args.role, frames).await {
+        Ok(simulator) => simulator.with_tolerance(args.tolerance).run().await,
+        Err(err) => Err(err),
+    };
+    match result {
+        Ok(divergences) if divergences.is_empty() => info!("the brain answered like in the recording"),
+        Ok(divergences) => {
+            for divergence in &divergences {
+                warn!("frame {}: recorded {}, received {}", divergence.frame, divergence.recorded, divergence.received);
+            }
+            warn!("the brain diverged {} times", divergences.len());
+        }
+        Err(err) => error!("replay stopped: {err}"),
+    }
+}
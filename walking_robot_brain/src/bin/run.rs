@@ -1,12 +1,25 @@
+use clap::Parser;
 use std::{
     path::PathBuf, str::FromStr
 };
 use burn::{
-    backend::{wgpu::WgpuDevice, Autodiff, Wgpu}, module::Module, record::{DefaultFileRecorder, FullPrecisionSettings}};
+    backend::{wgpu::WgpuDevice, Autodiff, Wgpu}, record::{DefaultFileRecorder, FullPrecisionSettings}};
 
-use walking_robot_brain::comm::SimulationConnector;
+use walking_robot_brain::comm::ConnectionArgs;
+use walking_robot_brain::procedures::run_simulation::RunEpisodesBatchedExt;
 use walking_robot_brain::models::a_selector::ASelectorConfig;
-use tracing::{info, warn};
+use walking_robot_brain::models::builders::load_saved;
+use walking_robot_brain::validation::{StateValidator, Validated};
+use tracing::{error, info, warn};
+
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+    /// How many simulations to run side by side
+    #[arg(long, default_value_t = 1)]
+    simulations: usize,
+}
 
 fn main() {
     tokio
@@ -16,10 +29,10 @@ fn main() {
     .enable_all()
     .build()
     .unwrap()
-    .block_on(async_main())
+    .block_on(async_main(Args::parse()))
 }
 
-async fn async_main(){
+async fn async_main(args: Args) {
     pretty_env_logger::init_timed();
     warn!("testing, baby");
     type B = Autodiff<Wgpu<f32, i32>>;
@@ -38,15 +51,24 @@ async fn async_main(){
                 [2000, 2000, 2000, 2000] 
             )
             .init::<B>(&dev);
-        model = model.load_file(&a_selector_model_path, &recorder, &dev).unwrap();
+        model = load_saved(model.clone(), &a_selector_model_path, &recorder, &dev).unwrap_or(model);
         model
     };
 
     info!("waiting for connection, baby");
     let rng = rand::rng();
-    let mut simulation = SimulationConnector::new().connect().await;
+    let mut simulations = args.connection.connector().connect_many(args.simulations).await.expect("could not connect to the simulations")
+        .into_iter()
+        .map(|simulation| Validated::new(simulation, StateValidator::default()))
+        .collect::<Vec<_>>();
     '_MAIN_LOOP: loop {
-        simulation.run_episode(&mut &a_selector).await;
+        if let Err(err) = simulations.run_episodes_batched(&mut &a_selector, &dev).await {
+            error!("lost the simulation: {err}");
+            return;
+        }
+        for (i, simulation) in simulations.iter().enumerate().filter(|(_, simulation)| simulation.counters().flagged_states > 0) {
+            info!("states of simulation {i}: {}", simulation.counters());
+        }
     }
 }
 
@@ -1,3 +1,4 @@
+use clap::Parser;
 use burn::{
     backend::{wgpu::WgpuDevice, Autodiff, Wgpu},
     grad_clipping::GradientClippingConfig,
@@ -7,18 +8,25 @@ use burn::{
     prelude::Backend,
     record::{CompactRecorder, DefaultFileRecorder, FullPrecisionSettings, PrettyJsonFileRecorder},
 };
-use rand::seq::IndexedRandom;
-use std::{iter, ops::Not, path::PathBuf, str::FromStr, sync::Mutex};
-use tracing::{info, warn};
+use std::{ops::Not, path::PathBuf, str::FromStr, sync::Mutex};
+use tracing::{error, info, warn};
 use walking_robot_brain::{
-    comm::SimulationConnector,
+    comm::ConnectionArgs,
+    procedures::run_simulation::RunEpisodeExt,
     models::{
         builders::{make_q_estimator, Q_ESTIMATOR_MODEL_PATH},
         q_estimator::{self, QEstimator},
     },
-    types::{history::TensorHistory, policy::q_estimator_policy::QEstimatorPolicy},
+    types::{history::TensorHistory, policy::q_estimator_policy::QEstimatorPolicy, prioritized_replay::PrioritizedReplayConfig},
+    validation::{StateValidator, Validated},
 };
 
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+}
+
 fn main() {
     tokio::runtime
         ::Builder
@@ -26,10 +34,10 @@ fn main() {
         .enable_all()
         .build()
         .unwrap()
-        .block_on(async_main())
+        .block_on(async_main(Args::parse()))
 }
 
-async fn async_main() {
+async fn async_main(args: Args) {
     pretty_env_logger::init_timed();
 
     warn!("yeah baby");
@@ -46,36 +54,51 @@ async fn async_main() {
     let mut optim = opt_config.clone().init();
     let mut loss_mod = MseLoss::new();
     let alpha = 0.99;
+    // steps kept across episodes, and transitions per minibatch
+    let mut replay = PrioritizedReplayConfig::new().init(50_000);
+    let batch_size = 256;
 
     info!("waiting for connection, baby");
 
     let mut rng = rand::rng();
-    let mut simulation = SimulationConnector::new().connect().await;
+    let simulation = args.connection.connector().connect().await.expect("could not connect to the simulation");
+    let mut simulation = Validated::new(simulation, StateValidator::default());
 
     loop {
         for _ in 0..10 {
             let mut policy = QEstimatorPolicy::new(&running_q_estimator, 100, &dev);
-            let mut histories = Vec::new();
+            let mut episodes = 0;
             for _ in 0..4 {
-                histories.push(
-                    simulation
-                    .run_episode(&mut policy)
-                    .await
-                );
+                match simulation.run_episode(&mut policy).await {
+                    Ok(history) if history.states.is_empty() => {}
+                    Ok(history) => {
+                        replay.push(&history);
+                        training_q_estimator = training_q_estimator.observe(&history.to_tensor_history(&dev));
+                        episodes += 1;
+                    }
+                    Err(err) => {
+                        error!("lost the simulation: {err}, saving estimator and stopping");
+                        training_q_estimator.clone().save_file(Q_ESTIMATOR_MODEL_PATH.as_path(), &recorder).unwrap();
+                        return;
+                    }
+                }
             }
-            let histories_len = histories.len();
-            for history in iter::from_fn(|| histories.choose(&mut rng)).take(histories_len * 8) {
-                training_q_estimator =
-                    training_q_estimator.train_monte_carlo(
-                        &history.to_tensor_history(&dev), 
+            for _ in 0..episodes * 8 {
+                let Some(sample) = replay.sample(batch_size, &mut rng, &dev) else { break };
+                let errors;
+                (training_q_estimator, errors) =
+                    training_q_estimator.train_td(
+                        &sample.batch, 
+                        &running_q_estimator,
                         alpha, 
                         lr, 
                         &mut optim, 
                         &mut loss_mod, 
-                        &dev
                     );
+                replay.update_priorities(&sample.transitions, &errors);
             }
         }
+        info!("states so far: {}", simulation.counters());
         info!("saving estimator");
         training_q_estimator.clone().save_file(Q_ESTIMATOR_MODEL_PATH.as_path(), &recorder).unwrap();
 
@@ -1,14 +1,26 @@
+use clap::Parser;
 use std::{
-    iter, ops::Not, path::PathBuf, str::FromStr
+    ops::Not, path::PathBuf, str::FromStr
 };
 use burn::{
     backend::{wgpu::WgpuDevice, Autodiff, Wgpu}, grad_clipping::GradientClippingConfig, module::Module, nn::loss::{HuberLoss, HuberLossConfig, MseLoss}, optim::{momentum::MomentumConfig, AdamConfig, AdamWConfig, SgdConfig}, prelude::Backend, record::{DefaultFileRecorder, FullPrecisionSettings}};
 
-use walking_robot_brain::{comm::SimulationConnector, models::builders::make_rs_estimator, types::policy::{noisy_policy::NoisyPolicy, FnPolicy}};
+use walking_robot_brain::{comm::ConnectionArgs, models::builders::{load_saved, make_rs_estimator}, procedures::{run_simulation::RunEpisodeExt, train::trainer::TrainerConfig}, types::policy::{noisy_policy::NoisyPolicy, FnPolicy}};
 use walking_robot_brain::models::{a_selector::ASelectorConfig, rs_estimator::{RsEstimator, RsEstimatorConfig}, v_estimator::VEstimatorConfig};
-use rand::{seq::IndexedRandom, Rng};
-use tracing::{info, warn};
+use rand::Rng;
+use tracing::{error, info, warn};
+use walking_robot_brain::dataset::{EpisodeDataset, EpisodeSource};
 use walking_robot_brain::types::{action::GameAction, state::GameState};
+use walking_robot_brain::validation::{StateValidator, Validated};
+
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+    /// Directory of the episodes collected so far, trained on at start and added to by this run
+    #[arg(long)]
+    dataset: Option<PathBuf>,
+}
 
 fn main() {
     tokio
@@ -18,10 +30,10 @@ fn main() {
     .enable_all()
     .build()
     .unwrap()
-    .block_on(async_main())
+    .block_on(async_main(Args::parse()))
 }
 
-async fn async_main(){
+async fn async_main(args: Args) {
     pretty_env_logger::init_timed();
     warn!("yeah baby");
     type B = Autodiff<Wgpu<f32, i32>>;
@@ -29,14 +41,17 @@ async fn async_main(){
     let dev = WgpuDevice::DefaultDevice;
     let recorder =  DefaultFileRecorder::<FullPrecisionSettings>::new();
     let base_path = PathBuf::from_str("./models/").unwrap();
-    let start_from_beggining = base_path.exists().not();
+    let mut start_from_beggining = base_path.exists().not();
 
     let rs_estimator_model_path = base_path.join("rs_estimator");
     let mut rs_estimator: RsEstimator<B> = {
         let mut model = make_rs_estimator(&dev);
 
         if !start_from_beggining{
-            model = model.load_file(&rs_estimator_model_path, &recorder, &dev).unwrap();
+            match load_saved(model.clone(), &rs_estimator_model_path, &recorder, &dev) {
+                Some(loaded) => model = loaded,
+                None => start_from_beggining = true,
+            }
         } 
         model
     };
@@ -49,34 +64,58 @@ async fn async_main(){
         ;
 
     let mut rs_est_opt = opt_config.clone().init();
+    let mut trainer = TrainerConfig::new().init();
+
+    let mut dataset = args.dataset.map(|dir| EpisodeDataset::open(dir).expect("could not open the dataset"));
+    // the stored episodes are part of the first batch, checked like the ones received
+    let mut validator = StateValidator::default();
+    let mut stored = match &dataset {
+        Some(dataset) => dataset.load_tensor_histories(&mut validator, &dev).expect("could not load the dataset"),
+        None => Vec::new(),
+    };
+    info!("{} stored episodes, {}", stored.len(), validator.counters());
+    // a saved model has seen them already
+    for history in stored.iter().filter(|_| start_from_beggining) {
+        rs_estimator = rs_estimator.observe(&history.states, &history.actions, &history.rewards);
+    }
 
     info!("waiting for connection, baby");
     let mut rng = rand::rng();
-    let mut simulation = SimulationConnector::new().connect().await;
+    let simulation = args.connection.connector().connect().await.expect("could not connect to the simulation");
+    let mut simulation = Validated::new(simulation, StateValidator::default());
 
     loop{
         info!("Starting a new batch"); 
 
-        let mut histories = Vec::new();
+        let mut histories = std::mem::take(&mut stored);
         for _i in 0..10{
             let policy = |_state: &GameState| {
                 GameAction::random(&mut rng)
             };
-            histories.push(simulation.run_episode(&mut FnPolicy(policy)).await.to_tensor_history(&dev));
+            let history = match simulation.run_episode(&mut FnPolicy(policy)).await {
+                Ok(history) => history,
+                Err(err) => {
+                    error!("lost the simulation: {err}, saving models and stopping");
+                    rs_estimator.clone().save_file(rs_estimator_model_path.clone(), &recorder).unwrap();
+                    return;
+                }
+            };
+            if history.states.is_empty() {
+                continue;
+            }
+            if let Some(dataset) = &mut dataset {
+                if let Err(err) = dataset.append(&history, &EpisodeSource::new("random")) {
+                    error!("could not store the episode: {err}");
+                }
+            }
+            let history = history.to_tensor_history(&dev);
+            rs_estimator = rs_estimator.observe(&history.states, &history.actions, &history.rewards);
+            histories.push(history);
         }
 
-        for history in iter::from_fn(||histories.choose(&mut rng)).take(30){
-            rs_estimator = rs_estimator.train(
-                &history.states, 
-                &history.actions, 
-                &history.rewards, 
-                rs_est_lr, 
-                &mut rs_est_opt, 
-                &mut MseLoss::new(),
-                &dev
-            );           
-        }            
+        (rs_estimator, _) = rs_estimator.train(&histories, &mut trainer, &mut rs_est_opt, &mut MseLoss::new(), rs_est_lr);
 
+        info!("states so far: {}", simulation.counters());
         info!("saving models...");
         rs_estimator.clone().save_file(rs_estimator_model_path.clone(), &recorder).unwrap();
     }
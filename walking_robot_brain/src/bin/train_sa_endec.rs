@@ -1,9 +1,21 @@
+use clap::Parser;
 use std::{iter, ops::Not, path::PathBuf, str::FromStr};
 
 use burn::{backend::{wgpu::WgpuDevice, Autodiff, Wgpu}, grad_clipping::GradientClippingConfig, module::Module, nn::loss::{HuberLossConfig, MseLoss}, optim::{AdamConfig, SgdConfig}, prelude::Backend, record::{DefaultFileRecorder, FullPrecisionSettings, PrettyJsonFileRecorder}};
 use rand::seq::IndexedRandom;
-use tracing::{info, warn};
-use walking_robot_brain::{comm::SimulationConnector, loss::LossMod, models::{builders::{make_sa_endec, SA_DEC_MODEL_PATH, SA_ENC_MODEL_PATH}, rs_estimator::{RsEstimator, RsEstimatorConfig}}, types::{action::GameAction, policy::FnPolicy, state::GameState}};
+use tracing::{error, info, warn};
+use walking_robot_brain::dataset::{EpisodeDataset, EpisodeSource};
+use walking_robot_brain::validation::{StateValidator, Validated};
+use walking_robot_brain::{comm::ConnectionArgs, loss::LossMod, procedures::run_simulation::RunEpisodeExt, models::{builders::{make_sa_endec, SA_DEC_MODEL_PATH, SA_ENC_MODEL_PATH}, rs_estimator::{RsEstimator, RsEstimatorConfig}}, types::{action::GameAction, policy::FnPolicy, state::GameState}};
+
+#[derive(Parser)]
+struct Args {
+    #[command(flatten)]
+    connection: ConnectionArgs,
+    /// Directory of the episodes collected so far, trained on at start and added to by this run
+    #[arg(long)]
+    dataset: Option<PathBuf>,
+}
 
 fn main() {
     tokio
@@ -13,10 +25,10 @@ fn main() {
     .enable_all()
     .build()
     .unwrap()
-    .block_on(async_main())
+    .block_on(async_main(Args::parse()))
 }
 
-async fn async_main(){
+async fn async_main(args: Args) {
     pretty_env_logger::init_timed();
     warn!("yeah baby");
     type B = Autodiff<Wgpu<f32, i32>>;
@@ -35,25 +47,53 @@ async fn async_main(){
 
     let mut optim = opt_config.clone().init();
 
+    let mut dataset = args.dataset.map(|dir| EpisodeDataset::open(dir).expect("could not open the dataset"));
+    // the stored episodes are part of the first batch, checked like the ones received
+    let mut validator = StateValidator::default();
+    let mut stored = match &dataset {
+        Some(dataset) => dataset.load_tensor_histories(&mut validator, &dev).expect("could not load the dataset"),
+        None => Vec::new(),
+    };
+    info!("{} stored episodes, {}", stored.len(), validator.counters());
+
     info!("waiting for connection, baby");
     let mut rng = rand::rng();
-    let mut simulation = SimulationConnector::new().connect().await;
+    let simulation = args.connection.connector().connect().await.expect("could not connect to the simulation");
+    let mut simulation = Validated::new(simulation, StateValidator::default());
 
     loop{
         info!("Starting a new batch"); 
 
-        let mut histories = Vec::new();
+        let mut histories = std::mem::take(&mut stored);
         for _i in 0..10{
             let policy = |_state: &GameState| {
                 GameAction::random(&mut rng)
             };
-            histories.push(simulation.run_episode(&mut FnPolicy(policy)).await.to_tensor_history(&dev));
+            let history = match simulation.run_episode(&mut FnPolicy(policy)).await {
+                Ok(history) => history,
+                Err(err) => {
+                    error!("lost the simulation: {err}, saving models and stopping");
+                    sa_endec.enc.clone().save_file(SA_ENC_MODEL_PATH.as_path(), &recorder).unwrap();
+                    sa_endec.dec.clone().save_file(SA_DEC_MODEL_PATH.as_path(), &recorder).unwrap();
+                    return;
+                }
+            };
+            if history.states.is_empty() {
+                continue;
+            }
+            if let Some(dataset) = &mut dataset {
+                if let Err(err) = dataset.append(&history, &EpisodeSource::new("random")) {
+                    error!("could not store the episode: {err}");
+                }
+            }
+            histories.push(history.to_tensor_history(&dev));
         }
 
         for history in iter::from_fn(||histories.choose(&mut rng)).take(100){
             sa_endec = sa_endec.train(history.states.clone(), history.actions.clone(), &mut MseLoss::new(), &mut optim, lr);           
         }            
 
+        info!("states so far: {}", simulation.counters());
         info!("saving models...");
         sa_endec.enc.clone().save_file(SA_ENC_MODEL_PATH.as_path(), &recorder).unwrap();
         sa_endec.dec.clone().save_file(SA_DEC_MODEL_PATH.as_path(), &recorder).unwrap();
@@ -1,221 +1,468 @@
-use std::str::from_utf8;
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
 
-use anyhow::{anyhow, bail};
-use itertools::Itertools;
-use json::JsonValue;
+use clap::{Args, ValueEnum};
+use serde::{de::DeserializeOwned, Serialize};
 use tokio::{
-    io::{AsyncReadExt, AsyncWriteExt},
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
     net::{TcpListener, TcpStream},
+    time::{sleep, timeout},
 };
-use tracing::debug;
+use tracing::{debug, info, warn};
 
 use crate::{
-    traits::{JsonExts, ToJson, TryFromJson},
+    error::SimulationError,
     types::{
-        action::{GameAction, LimbActivation},
-        state::{
-            AccelerometerReading, BipedalLimbsReading, Force, GameState, GameStateAndReward, GameUpdate, LimbReading, LinkReading, MotorReading, SensorsReading, TransformReading
-        },
+        action::GameAction,
+        environment::{Environment, EnvironmentError},
+        protocol::{HelloReply, SimulationCommand, SimulationHello, WireFormat},
+        state::{GameState, GameUpdate, StepFlags},
     },
+    wire::{decode_json, decode_update, encode_action},
 };
 
-pub struct SimulationConnector;
+pub const DEFAULT_SIMULATION_ADDR: SocketAddr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 8080);
+
+/// Which side of the TCP connection the brain takes. Unity connects to a listening brain by default.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum ConnectionRole {
+    Listen,
+    Connect,
+}
+
+#[derive(Clone, Debug)]
+pub struct SimulationConnector {
+    pub addr: SocketAddr,
+    pub role: ConnectionRole,
+    // used when the simulation supports it, json otherwise
+    pub wire_format: WireFormat,
+}
+impl Default for SimulationConnector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 impl SimulationConnector {
     pub fn new() -> Self {
-        Self
+        Self { addr: DEFAULT_SIMULATION_ADDR, role: ConnectionRole::Listen, wire_format: WireFormat::Json }
+    }
+    pub fn with_addr(mut self, addr: SocketAddr) -> Self {
+        self.addr = addr;
+        self
+    }
+    pub fn with_role(mut self, role: ConnectionRole) -> Self {
+        self.role = role;
+        self
+    }
+    pub fn with_wire_format(mut self, wire_format: WireFormat) -> Self {
+        self.wire_format = wire_format;
+        self
+    }
+    /// Waits for the simulation to be connected. The endpoint keeps the connector around, so that it can wait for
+    /// the simulation again after it disconnects.
+    pub async fn connect(self) -> Result<SimulationEndpoint, SimulationError> {
+        Ok(self.connect_many(1).await?.remove(0))
     }
-    pub async fn connect(self) -> SimulationEndpoint {
-        let stream = TcpListener::bind("127.0.0.1:8080")
-            .await
-            .unwrap()
-            .accept()
-            .await
-            .unwrap()
-            .0;
-        
-        SimulationEndpoint { stream }
+    /// Waits for `count` simulations, to run their episodes side by side. When listening they all share the
+    /// listener, so a simulation coming back after a disconnect may land on any endpoint that lost its own.
+    pub async fn connect_many(self, count: usize) -> Result<Vec<SimulationEndpoint>, SimulationError> {
+        let listener = match self.role {
+            ConnectionRole::Listen => Some(Arc::new(TcpListener::bind(self.addr).await?)),
+            ConnectionRole::Connect => None,
+        };
+        let mut endpoints = Vec::with_capacity(count);
+        for _ in 0..count {
+            let mut endpoint = SimulationEndpoint {
+                connector       : self.clone(),
+                listener        : listener.clone(),
+                stream          : None,
+                hello           : None,
+                wire_format     : WireFormat::Json,
+                episode_started : false,
+                last_state      : None,
+                owes_action     : false,
+                commands        : Vec::new(),
+                frames          : FrameBuffer::default(),
+            };
+            endpoint.reconnect().await?;
+            endpoints.push(endpoint);
+        }
+        Ok(endpoints)
     }
 }
-pub struct SimulationEndpoint {
-    stream: TcpStream,
+
+// command line options for the connection, flattened into the arguments of every binary
+#[derive(Clone, Debug, Args)]
+pub struct ConnectionArgs {
+    /// Address to listen on or to connect to
+    #[arg(long, default_value_t = DEFAULT_SIMULATION_ADDR)]
+    pub addr: SocketAddr,
+    /// Whether to wait for the simulation or to connect to it
+    #[arg(long, value_enum, default_value_t = ConnectionRole::Listen)]
+    pub role: ConnectionRole,
+    /// Encoding of states and actions, when the simulation supports it
+    #[arg(long, value_enum, default_value_t = WireFormat::Json)]
+    pub wire_format: WireFormat,
+}
+impl ConnectionArgs {
+    pub fn connector(&self) -> SimulationConnector {
+        SimulationConnector::new().with_addr(self.addr).with_role(self.role).with_wire_format(self.wire_format)
+    }
 }
 
-impl SimulationEndpoint {
-    async fn send_msg(&mut self, bytes: &[u8]) {
-        self.stream
-            .write_all(&bytes.len().to_be_bytes())
-            .await
-            .unwrap();
-        self.stream.write_all(bytes).await.unwrap();
-        self.stream.flush().await.unwrap();
-    }
-    async fn recv_msg(&mut self) -> Vec<u8> {
-        let mut len_buf = [0_u8; size_of::<usize>()];
-        self.stream.read_exact(&mut len_buf).await.unwrap();
-        let len = usize::from_be_bytes(len_buf);
-        debug!("message is {len} bytes long");
-
-        let mut msg_buf = vec![0_u8; len];
-        self.stream.read_exact(&mut msg_buf).await.unwrap();
-        debug!("received: \"{}\"", from_utf8(&msg_buf).unwrap());
-        msg_buf
-    }
-    pub async fn send_action(&mut self, action: &GameAction) {
-        let act_bytes = action.to_json().to_string().into_bytes();
-        self.send_msg(&act_bytes).await;
-    }
-
-    pub async fn recv_sim_update(&mut self) -> GameUpdate {
-        let msg = self.recv_msg().await;
-
-        if &msg == b"GAME STARTED" {
-            GameUpdate::GameStarted
-        } else {
-            let msg = from_utf8(&msg).unwrap();
-            debug!("msg is: {msg}");
-            let msg = json::parse(msg).unwrap();
-            let GameStateAndReward { game_state, reward } = msg.try_as().unwrap();
-            GameUpdate::GameStep {
-                state: game_state,
-                reward,
+/// Connects to `addr`, retrying until something listens there.
+pub async fn connect_retrying(addr: SocketAddr) -> TcpStream {
+    loop {
+        match TcpStream::connect(addr).await {
+            Ok(stream) => break stream,
+            Err(err) => {
+                debug!("nothing is listening on {addr} yet ({err}), retrying");
+                sleep(Duration::from_millis(100)).await;
             }
         }
     }
 }
 
-impl ToJson for GameAction {
-    fn to_json(&self) -> JsonValue {
-        json::object! {
-            LimbsActivation:{
-                Left: (self.limbs_activation.left.to_json()),
-                Right: (self.limbs_activation.right.to_json()),
+/// The simulation's end of the connection, `role` being the simulation's own.
+pub async fn open_simulation_stream(addr: SocketAddr, role: ConnectionRole) -> Result<TcpStream, SimulationError> {
+    match role {
+        ConnectionRole::Connect => Ok(connect_retrying(addr).await),
+        ConnectionRole::Listen => Ok(TcpListener::bind(addr).await?.accept().await?.0),
+    }
+}
+
+pub struct SimulationEndpoint {
+    connector: SimulationConnector,
+    // only when the brain is the one listening
+    listener: Option<Arc<TcpListener>>,
+    // `None` after the simulation disconnected, until `reset` waits for it again
+    stream: Option<TcpStream>,
+    // what the simulation said when it connected
+    hello: Option<SimulationHello>,
+    // what states and actions are encoded in on this connection
+    wire_format: WireFormat,
+    // an "EpisodeStart" was received but the first state of that episode was not yet
+    episode_started: bool,
+    last_state: Option<GameState>,
+    // unity sent a state and waits for an action before doing anything else
+    owes_action: bool,
+    // go out before the next action
+    commands: Vec<SimulationCommand>,
+    // what was read of the frame being received, a step that times out reads on from there
+    frames: FrameBuffer,
+}
+
+/// Frames announcing more than this are garbage, not something worth allocating for.
+pub const MAX_FRAME_LEN: usize = 16 << 20;
+
+/// Writes one message prefixed by its length, the way `AgentEndpoint.Send` does on the Unity side.
+pub async fn send_frame(stream: &mut (impl AsyncWrite + Unpin), bytes: &[u8]) -> Result<(), SimulationError> {
+    stream
+        .write_all(&bytes.len().to_be_bytes())
+        .await?;
+    stream.write_all(bytes).await?;
+    stream.flush().await?;
+    Ok(())
+}
+
+pub async fn recv_frame(stream: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>, SimulationError> {
+    let mut len_buf = [0_u8; size_of::<usize>()];
+    stream.read_exact(&mut len_buf).await?;
+    let len = usize::from_be_bytes(len_buf);
+    debug!("message is {len} bytes long");
+    if len > MAX_FRAME_LEN {
+        return Err(SimulationError::Framing { len, max: MAX_FRAME_LEN });
+    }
+
+    let mut msg_buf = vec![0_u8; len];
+    stream.read_exact(&mut msg_buf).await?;
+    debug!("received: \"{}\"", String::from_utf8_lossy(&msg_buf));
+    Ok(msg_buf)
+}
+
+/// Reads frames like `recv_frame`, but what was read of a frame stays here when the read is cancelled, so that reading
+/// under a `timeout` doesn't lose the start of a frame and take the rest for the next one.
+#[derive(Default)]
+struct FrameBuffer {
+    pending: Vec<u8>,
+}
+
+impl FrameBuffer {
+    const LEN_SIZE: usize = size_of::<usize>();
+
+    async fn recv_frame(&mut self, stream: &mut (impl AsyncRead + Unpin)) -> Result<Vec<u8>, SimulationError> {
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                return Ok(frame);
+            }
+            // unlike `read_exact`, nothing is lost when this is cancelled
+            if stream.read_buf(&mut self.pending).await? == 0 {
+                return Err(std::io::Error::from(std::io::ErrorKind::UnexpectedEof).into());
             }
         }
     }
-}
-impl ToJson for LimbActivation {
-    fn to_json(&self) -> JsonValue {
-        json::object! {
-            Shoulder: (self.shoulder_activation),
-            Thigh	: (self.thigh_activation),
-            Shin: (self.shin_activation),
+
+    fn take_frame(&mut self) -> Result<Option<Vec<u8>>, SimulationError> {
+        let Some(len_buf) = self.pending.get(..Self::LEN_SIZE) else { return Ok(None) };
+        let len = usize::from_be_bytes(len_buf.try_into().unwrap());
+        if len > MAX_FRAME_LEN {
+            return Err(SimulationError::Framing { len, max: MAX_FRAME_LEN });
         }
+        let Some(frame) = self.pending.get(Self::LEN_SIZE..Self::LEN_SIZE + len) else { return Ok(None) };
+        let frame = frame.to_vec();
+        self.pending.drain(..Self::LEN_SIZE + len);
+        debug!("received: \"{}\"", String::from_utf8_lossy(&frame));
+        Ok(Some(frame))
     }
-}
 
-impl TryFromJson for GameStateAndReward {
-    fn try_from_json(json: &JsonValue) -> Result<Self, anyhow::Error> {
-        let json = json.as_object().unwrap();
-        Ok(GameStateAndReward { 
-            game_state  : json["State"].try_as().unwrap(), 
-            reward      : json["Reward"].as_f32().unwrap()
-        })
+    fn clear(&mut self) {
+        self.pending.clear();
     }
 }
-impl TryFromJson for GameState{
-    fn try_from_json(json: &JsonValue) -> Result<Self, anyhow::Error>  {
-        let json = json.as_object().unwrap();
-        Ok(Self{
-            limbs_readings: json["LimbsReading"].try_as::<BipedalLimbsReading>().unwrap(),
-            sensors_reading:json["SensorsReading"].try_as().unwrap(),
-        })
+
+impl SimulationEndpoint {
+    pub fn is_connected(&self) -> bool {
+        self.stream.is_some()
     }
-}
-impl TryFromJson for BipedalLimbsReading{
-    fn try_from_json(json: &JsonValue) -> Result<Self, anyhow::Error>  {
-        let json = json.as_object().unwrap();
-        Ok(Self { 
-            left    : json["Left"].try_as().unwrap(), 
-            right   : json["Right"].try_as().unwrap(),
-        })
+    async fn reconnect(&mut self) -> Result<(), SimulationError> {
+        info!("waiting for the simulation on {} ({:?})", self.connector.addr, self.connector.role);
+        let stream = match &self.listener {
+            Some(listener) => listener.accept().await?.0,
+            None => connect_retrying(self.connector.addr).await,
+        };
+        self.stream = Some(stream);
+        self.frames.clear();
+        self.episode_started = false;
+        self.last_state = None;
+        self.owes_action = false;
+        self.hello = None;
+        self.wire_format = WireFormat::Json;
+
+        let hello = self.recv_json::<SimulationHello>().await?;
+        info!("simulation connected: {hello:?}");
+        if let Err(reason) = hello.check_compatible() {
+            warn!("rejecting the simulation: {reason}");
+            self.send_json(&HelloReply::Reject { reason: reason.clone() }).await?;
+            self.stream = None;
+            return Err(SimulationError::Incompatible { reason });
+        }
+        let wire_format = hello.negotiate(self.connector.wire_format);
+        info!("states and actions go as {wire_format:?}");
+        self.send_json(&HelloReply::Accept { wire_format }).await?;
+        self.wire_format = wire_format;
+        self.hello = Some(hello);
+        Ok(())
+    }
+    /// The handshake of the simulation currently connected.
+    pub fn hello(&self) -> Option<&SimulationHello> {
+        self.hello.as_ref()
+    }
+    fn forget_stream_on_disconnect<T>(&mut self, res: Result<T, SimulationError>) -> Result<T, SimulationError> {
+        if let Err(err) = &res {
+            if err.is_disconnect() {
+                warn!("simulation disconnected: {err}");
+                self.stream = None;
+            }
+        }
+        res
+    }
+    async fn send_msg(&mut self, bytes: &[u8]) -> Result<(), SimulationError> {
+        let stream = self.stream.as_mut().ok_or(SimulationError::Disconnected)?;
+        let res = send_frame(stream, bytes).await;
+        self.forget_stream_on_disconnect(res)
+    }
+    async fn recv_msg(&mut self) -> Result<Vec<u8>, SimulationError> {
+        let stream = self.stream.as_mut().ok_or(SimulationError::Disconnected)?;
+        let res = self.frames.recv_frame(stream).await;
+        self.forget_stream_on_disconnect(res)
+    }
+    async fn send_json(&mut self, message: &impl Serialize) -> Result<(), SimulationError> {
+        self.send_msg(&serde_json::to_vec(message)?).await
+    }
+    async fn recv_json<T: DeserializeOwned>(&mut self) -> Result<T, SimulationError> {
+        let msg = self.recv_msg().await?;
+        debug!("msg is: {}", String::from_utf8_lossy(&msg));
+        decode_json(&msg)
+    }
+    /// Queues `command` until the next action is sent, the simulation only listens once it sent a state.
+    pub fn queue_command(&mut self, command: SimulationCommand) {
+        self.commands.push(command);
+    }
+    pub async fn send_action(&mut self, action: &GameAction) -> Result<(), SimulationError> {
+        while !self.commands.is_empty() {
+            let command = self.commands.remove(0);
+            self.send_json(&command).await?;
+            // the simulation restarts without waiting for the action, what is left goes with the next one
+            if command == SimulationCommand::ResetEpisode {
+                self.owes_action = false;
+                return Ok(());
+            }
+        }
+        match self.wire_format {
+            WireFormat::Json => self.send_json(action).await?,
+            WireFormat::Binary => self.send_msg(&encode_action(action)).await?,
+        }
+        self.owes_action = false;
+        Ok(())
+    }
+
+    pub async fn recv_sim_update(&mut self) -> Result<GameUpdate, SimulationError> {
+        let update = decode_update(&self.recv_msg().await?)?;
+        self.owes_action = matches!(update, GameUpdate::GameStep { .. });
+        Ok(update)
     }
 }
 
-impl TryFromJson for SensorsReading{
-    fn try_from_json(json: &JsonValue) -> Result<Self, anyhow::Error>  {
-        let json = json.as_object().unwrap();
-        Ok(Self { 
-            target_pos      : json["TargetPos"].as_vector3().unwrap(), 
-            floor_distance  : json["FloorDist"].as_f32().unwrap(),
-            acc_reading     : json["AccelerometerReading"].try_as().unwrap(),
-            forces          : {
-                let JsonValue::Array(arr) = &json["Forces"] else {panic!()};
+impl Environment for SimulationEndpoint {
+    type Error = SimulationError;
 
-                arr.iter()
-                    .map(|el| el.try_as::<Force>().unwrap())
-                    .collect_vec()
+    async fn reset(&mut self) -> Result<GameState, SimulationError> {
+        loop {
+            if !self.is_connected() {
+                self.reconnect().await?;
+            }
+            // the episode ended on a terminal state, which unity still wants an action for
+            if self.owes_action {
+                match self.send_action(&Default::default()).await {
+                    Err(err) if !err.is_disconnect() => return Err(err),
+                    _ => continue,
+                }
             }
-        }) 
-    }
-}
-
-impl TryFromJson for LimbReading{
-	fn try_from_json(json: &json::JsonValue) -> Result<Self, anyhow::Error>  {
-		let json = json.as_object().unwrap();
-		Ok(LimbReading{
-			shoulder: json["ShoulderReading"]	.try_as().unwrap(),
-			thigh	: json["ThighReading"]		.try_as().unwrap(),
-			shin	: json["ShinReading"]		.try_as().unwrap(),
-			foot	: json["FootReading"]		.try_as().unwrap(),
-		})
-	}
-}
-impl TryFromJson for Force{
-    fn try_from_json(json: &JsonValue) -> Result<Self, anyhow::Error>  {
-        let json = json.as_object().unwrap();
-        Ok(Self{
-            pos  : json["Position"].as_vector3().unwrap(),
-            force: json["Force"].as_vector3().unwrap(),
-        })
-    }
-}
-
-impl TryFromJson for AccelerometerReading{
-    fn try_from_json(json: &JsonValue) -> Result<Self, anyhow::Error>  {
-        let json = json.as_object().unwrap();
-        Ok(AccelerometerReading{
-            linear_speed    : json["LinearSpeed"].as_vector3().unwrap(),
-            linear_acc      : json["LinearAcc"].as_vector3().unwrap(), 
-            angular_speed   : json["AngularSpeed"].as_vector3().unwrap(),
-            angular_acc     : json["AngularAcc"].as_vector3().unwrap(),
-            up              : json["UpOrientation"].as_vector3().unwrap(), 
-        })
-    }
-}
-
-impl TryFromJson for TransformReading {
-    fn try_from_json(json: &json::JsonValue) -> Result<Self, anyhow::Error> {
-        let json = json.as_object().unwrap();
-        Ok(Self {
-            linear_pos		: json["LinearPos"].as_vector3().unwrap(),
-            linear_speed	: json["LinearSpeed"].as_vector3().unwrap(),
-            linear_acc		: json["LinearAcc"].as_vector3().unwrap(),
-
-            angular_pos		: json["AngularPos"].as_quaternion().unwrap(), 
-            angular_speed	: json["AngularSpeed"].as_vector3().unwrap(),
-            angular_acc		: json["AngularAcc"].as_vector3().unwrap(),
-        })
-    }
-}
-
-impl TryFromJson for MotorReading {
-    fn try_from_json(json: &json::JsonValue) -> Result<Self, anyhow::Error> {
-        let json = json.as_object().unwrap();
-        Ok(Self {
-            acc: json["Acc"].as_f32().unwrap(),
-            pos: json["Pos"].as_f32().unwrap(),
-            speed: json["Speed"].as_f32().unwrap(),
-            torque: json["Torque"].as_f32().unwrap(),
-        })
-    }
-}
-
-impl TryFromJson for LinkReading{
-	fn try_from_json(json: &json::JsonValue) -> Result<Self, anyhow::Error>  {
-		let json = json.as_object().unwrap();
-		Ok(LinkReading { 
-			motor		: json["MotorReading"].try_as::<MotorReading>().unwrap(), 
-			transform	:  json["TransformReading"].try_as().unwrap(),
-		})
-	}
+            let update = match self.recv_sim_update().await {
+                Ok(update) => update,
+                Err(err) if err.is_disconnect() => continue,
+                Err(err) => return Err(err),
+            };
+            match update {
+                GameUpdate::EpisodeStarted => {
+                    self.episode_started = true;
+                }
+                GameUpdate::EpisodeEnded { .. } => {
+                    self.episode_started = false;
+                }
+                GameUpdate::GameStep { state, .. } if self.episode_started => {
+                    self.episode_started = false;
+                    // a queued reset throws this episode away, it goes out at the top of the loop
+                    if self.commands.contains(&SimulationCommand::ResetEpisode) {
+                        continue;
+                    }
+                    self.last_state = Some(state.clone());
+                    return Ok(state);
+                }
+                GameUpdate::GameStep { .. } => {
+                    // unity is in the middle of an episode and won't go on until it gets an action
+                    match self.send_action(&Default::default()).await {
+                        Err(err) if !err.is_disconnect() => return Err(err),
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    async fn step(&mut self, action: &GameAction) -> Result<(GameState, f32, StepFlags), SimulationError> {
+        self.send_action(action).await?;
+        let truncated = StepFlags { truncated: true, ..Default::default() };
+        loop {
+            match timeout(Duration::from_secs_f32(1.0), self.recv_sim_update()).await {
+                Ok(update) => match update? {
+                    GameUpdate::EpisodeEnded { .. } => {
+                        let last_state = self.last_state.clone().ok_or(SimulationError::NotReset)?;
+                        return Ok((last_state, 0.0, truncated));
+                    }
+                    // the end of the episode got lost, the simulation restarting says as much
+                    GameUpdate::EpisodeStarted => {
+                        self.episode_started = true;
+                        let last_state = self.last_state.clone().ok_or(SimulationError::NotReset)?;
+                        return Ok((last_state, 0.0, truncated));
+                    }
+                    GameUpdate::GameStep { state, reward, flags } => {
+                        self.last_state = Some(state.clone());
+                        return Ok((state, reward, flags));
+                    }
+                },
+                Err(_) => {
+                    self.send_action(&Default::default()).await?;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+
+    use serde_json::Value;
+    use tokio::{io::AsyncWriteExt, time::timeout};
+
+    use crate::{error::SimulationError, simulation::biped::BipedSim, types::state::GameStateAndReward};
+
+    use super::{send_frame, FrameBuffer};
+
+    fn state_json() -> Value {
+        let mut sim = BipedSim::new(Default::default(), 0);
+        sim.reset();
+        let (game_state, reward) = sim.step(&Default::default());
+        serde_json::to_value(GameStateAndReward { game_state, reward }).unwrap()
+    }
+
+    fn parse(json: &Value) -> Result<GameStateAndReward, SimulationError> {
+        crate::wire::decode_json(json.to_string().as_bytes())
+    }
+
+    #[test]
+    fn states_round_trip() {
+        let json = state_json();
+        assert_eq!(serde_json::to_value(parse(&json).unwrap()).unwrap(), json);
+    }
+
+    #[test]
+    fn missing_field_has_its_path() {
+        let mut json = state_json();
+        json["State"]["SensorsReading"]["Forces"][0].as_object_mut().unwrap().remove("Position");
+
+        // the path is that of the value the field is missing from, serde's message names the field
+        let err = parse(&json).unwrap_err();
+        assert_eq!(err.to_string(), "missing field `Position` at `State.SensorsReading.Forces[0]`");
+        let SimulationError::Field { path, .. } = err else { panic!() };
+        assert_eq!(path, "State.SensorsReading.Forces[0]");
+    }
+
+    #[test]
+    fn wrong_type_has_its_path() {
+        let mut json = state_json();
+        json["State"]["LimbsReading"]["Left"]["ShinReading"]["MotorReading"]["Torque"] = "a lot".into();
+
+        let Err(SimulationError::Field { path, message }) = parse(&json) else { panic!() };
+        assert_eq!(path, "State.LimbsReading.Left.ShinReading.MotorReading.Torque");
+        assert!(message.ends_with("expected a number"), "{message}");
+    }
+
+    #[test]
+    fn broken_json_is_not_a_field_error() {
+        assert!(matches!(crate::wire::decode_json::<GameStateAndReward>(b"{\"State\": "), Err(SimulationError::Json(_))));
+    }
+
+    #[tokio::test]
+    async fn frames_survive_a_timeout_in_their_middle() {
+        let (mut sim, mut brain) = tokio::io::duplex(64);
+        let mut frames = FrameBuffer::default();
+        let mut first = Vec::new();
+        send_frame(&mut first, b"first frame").await.unwrap();
+        let (start, rest) = first.split_at(10);
+
+        sim.write_all(start).await.unwrap();
+        assert!(timeout(Duration::from_millis(20), frames.recv_frame(&mut brain)).await.is_err());
+        sim.write_all(rest).await.unwrap();
+        send_frame(&mut sim, b"second").await.unwrap();
+        assert_eq!(frames.recv_frame(&mut brain).await.unwrap(), b"first frame");
+        assert_eq!(frames.recv_frame(&mut brain).await.unwrap(), b"second");
+
+        drop(sim);
+        assert!(matches!(frames.recv_frame(&mut brain).await, Err(SimulationError::Io(_))));
+    }
 }
@@ -0,0 +1,393 @@
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use burn::prelude::Backend;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::{
+    tensor_conversion::TensorConvertible,
+    types::{
+        action::GameAction,
+        history::{History, TensorHistory},
+        state::{GameState, GameUpdate, StepFlags},
+    },
+    validation::StateValidator,
+    wire::{decode_action, decode_step, encode_action, encode_step, BINARY_ACTION_LEN, BINARY_STEP_LEN},
+};
+
+/// First bytes of every episode file, once decompressed.
+pub const EPISODE_MAGIC: &[u8; 8] = b"WRBEPI01";
+
+const MANIFEST_FILE: &str = "manifest.jsonl";
+
+const TRUNCATED_BIT: u8 = 1;
+const SUCCESS_BIT: u8 = 2;
+
+#[derive(Debug, Error)]
+pub enum DatasetError {
+    #[error("i/o error in the dataset: {0}")]
+    Io(#[from] io::Error),
+    #[error("malformed manifest entry: {0}")]
+    Manifest(#[from] serde_json::Error),
+    #[error("{file} has {found} values per state but this build uses {expected}")]
+    Layout { file: String, found: usize, expected: usize },
+    #[error("{file} is not a valid episode file: {reason}")]
+    Corrupt { file: String, reason: String },
+}
+
+/// Where an episode came from, stored with it.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub struct EpisodeSource {
+    // what picked the actions, `random` or the name of a model for instance
+    pub policy      : String,
+    // the file of the model the policy used, when there was one
+    pub checkpoint  : Option<String>,
+}
+
+impl EpisodeSource {
+    pub fn new(policy: &str) -> Self {
+        Self { policy: policy.to_string(), checkpoint: None }
+    }
+
+    pub fn with_checkpoint(mut self, checkpoint: &Path) -> Self {
+        self.checkpoint = Some(checkpoint.display().to_string());
+        self
+    }
+}
+
+/// One line of the manifest.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct EpisodeEntry {
+    pub id          : u64,
+    // relative to the dataset directory
+    pub file        : String,
+    pub steps       : usize,
+    // sum of the rewards, not discounted
+    #[serde(rename = "return")]
+    pub total_reward: f32,
+    pub success     : bool,
+    pub truncated   : bool,
+    #[serde(flatten)]
+    pub source      : EpisodeSource,
+    // seconds since the unix epoch
+    pub timestamp   : u64,
+    pub state_values: usize,
+}
+
+/// A directory of episodes, one compressed file each, listed in an append-only manifest. Episodes are only ever
+/// added, so several runs can collect into the same dataset and pick up what the others left.
+///
+/// An episode file is gzipped: the magic, the number of values of a state and of an action as `u32`, a flags byte,
+/// then for every step its binary wire frames, the step with the state it starts from, its reward and whether it is
+/// terminal, then the action taken.
+pub struct EpisodeDataset {
+    dir     : PathBuf,
+    entries : Vec<EpisodeEntry>,
+}
+
+impl EpisodeDataset {
+    /// Opens the dataset in `dir`, creating it when there is none.
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self, DatasetError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        let mut entries = Vec::new();
+        match File::open(dir.join(MANIFEST_FILE)) {
+            Ok(file) => {
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_str(&line) {
+                        Ok(entry) => entries.push(entry),
+                        // a run stopped in the middle of appending, the episode file is there but not listed. The
+                        // runs after it append on a line of their own, so it needn't be the last
+                        Err(err) if err.is_eof() => warn!("ignoring a torn line of the manifest: {err}"),
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+        Ok(Self { dir, entries })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn episodes(&self) -> &[EpisodeEntry] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Writes `history` to a new episode file and lists it in the manifest.
+    pub fn append(&mut self, history: &History, source: &EpisodeSource) -> Result<&EpisodeEntry, DatasetError> {
+        // another run may have taken the next id since this one read the manifest, `create_new` tells
+        let mut id = self.entries.iter().map(|entry| entry.id + 1).max().unwrap_or(0);
+        let (file_name, file) = loop {
+            let file_name = format!("episode_{id:06}.wrbep.gz");
+            match OpenOptions::new().write(true).create_new(true).open(self.dir.join(&file_name)) {
+                Ok(file) => break (file_name, file),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => id += 1,
+                Err(err) => return Err(err.into()),
+            }
+        };
+        let mut encoder = GzEncoder::new(BufWriter::new(file), Compression::default());
+        write_episode(&mut encoder, history)?;
+        encoder.finish()?.flush()?;
+
+        let entry = EpisodeEntry {
+            id,
+            file        : file_name,
+            steps       : history.states.len(),
+            total_reward: history.rewards.iter().sum(),
+            success     : history.success,
+            truncated   : history.truncated,
+            source      : source.clone(),
+            timestamp   : SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |since| since.as_secs()),
+            state_values: GameState::VALUES_COUNT,
+        };
+        // one write per line, so that runs appending at the same time don't interleave
+        let mut line = serde_json::to_vec(&entry)?;
+        line.push(b'\n');
+        let mut manifest = OpenOptions::new().create(true).read(true).append(true).open(self.dir.join(MANIFEST_FILE))?;
+        if !ends_with_newline(&mut manifest)? {
+            line.insert(0, b'\n');
+        }
+        manifest.write_all(&line)?;
+
+        self.entries.push(entry);
+        Ok(self.entries.last().unwrap())
+    }
+
+    pub fn append_tensor_history<B: Backend>(&mut self, history: &TensorHistory<B>, source: &EpisodeSource) -> Result<&EpisodeEntry, DatasetError> {
+        self.append(&history.to_history(), source)
+    }
+
+    pub fn load(&self, entry: &EpisodeEntry) -> Result<History, DatasetError> {
+        let file = File::open(self.dir.join(&entry.file))?;
+        read_episode(&mut GzDecoder::new(BufReader::new(file)), &entry.file)
+    }
+
+    /// Every episode of the manifest, in the order they were added.
+    pub fn load_all(&self) -> impl Iterator<Item = Result<History, DatasetError>> + '_ {
+        self.entries.iter().map(|entry| self.load(entry))
+    }
+
+    /// Every episode with at least one step, ready for training. The states go through `validator` like those of a
+    /// live simulation, an episode it fails on is left out.
+    pub fn load_tensor_histories<B: Backend>(&self, validator: &mut StateValidator, dev: &<B as Backend>::Device) -> Result<Vec<TensorHistory<B>>, DatasetError> {
+        let mut histories = Vec::new();
+        for (entry, history) in self.entries.iter().zip(self.load_all()) {
+            match validator.validate_history(history?) {
+                Ok(history) if history.states.is_empty() => {}
+                Ok(history) => histories.push(history.to_tensor_history(dev)),
+                Err(err) => warn!("leaving out {}: {err}", entry.file),
+            }
+        }
+        Ok(histories)
+    }
+}
+
+// also true of an empty file, there is no line to end
+fn ends_with_newline(file: &mut File) -> io::Result<bool> {
+    if file.metadata()?.len() == 0 {
+        return Ok(true);
+    }
+    let mut last = [0_u8; 1];
+    file.seek(SeekFrom::End(-1))?;
+    file.read_exact(&mut last)?;
+    Ok(last[0] == b'\n')
+}
+
+fn write_episode(writer: &mut impl Write, history: &History) -> io::Result<()> {
+    writer.write_all(EPISODE_MAGIC)?;
+    writer.write_all(&(GameState::VALUES_COUNT as u32).to_le_bytes())?;
+    writer.write_all(&(GameAction::VALUES_COUNT as u32).to_le_bytes())?;
+    let truncated = if history.truncated { TRUNCATED_BIT } else { 0 };
+    let success = if history.success { SUCCESS_BIT } else { 0 };
+    writer.write_all(&[truncated | success])?;
+
+    let steps = history.states.iter().zip(&history.actions).zip(&history.rewards).zip(&history.terminals);
+    for (((state, action), &reward), &terminal) in steps {
+        writer.write_all(&encode_step(state, reward, StepFlags { terminal, ..Default::default() }))?;
+        writer.write_all(&encode_action(action))?;
+    }
+    Ok(())
+}
+
+fn read_episode(reader: &mut impl Read, file: &str) -> Result<History, DatasetError> {
+    let corrupt = |reason: String| DatasetError::Corrupt { file: file.to_string(), reason };
+    let mut header = [0_u8; 8 + 4 + 4 + 1];
+    reader.read_exact(&mut header)?;
+    if &header[..8] != EPISODE_MAGIC {
+        return Err(corrupt("wrong magic".to_string()));
+    }
+    let state_values = u32::from_le_bytes(header[8..12].try_into().unwrap()) as usize;
+    let action_values = u32::from_le_bytes(header[12..16].try_into().unwrap()) as usize;
+    if state_values != GameState::VALUES_COUNT {
+        return Err(DatasetError::Layout { file: file.to_string(), found: state_values, expected: GameState::VALUES_COUNT });
+    }
+    if action_values != GameAction::VALUES_COUNT {
+        return Err(corrupt(format!("{action_values} values per action")));
+    }
+
+    let mut history = History {
+        truncated   : header[16] & TRUNCATED_BIT != 0,
+        success     : header[16] & SUCCESS_BIT != 0,
+        ..Default::default()
+    };
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let step_len = BINARY_STEP_LEN + BINARY_ACTION_LEN;
+    if bytes.len() % step_len != 0 {
+        return Err(corrupt(format!("{} bytes of steps, not a multiple of {step_len}", bytes.len())));
+    }
+    for step in bytes.chunks_exact(step_len) {
+        let (step, action) = step.split_at(BINARY_STEP_LEN);
+        let Ok(GameUpdate::GameStep { state, reward, flags }) = decode_step(step) else {
+            return Err(corrupt("malformed step".to_string()));
+        };
+        let action = decode_action(action).map_err(|err| corrupt(err.to_string()))?;
+        history.states.push(state);
+        history.actions.push(action);
+        history.rewards.push(reward);
+        history.terminals.push(flags.terminal);
+    }
+    Ok(history)
+}
+
+#[cfg(test)]
+mod test {
+    use std::{fs, path::PathBuf};
+
+    use burn::backend::NdArray;
+
+    use crate::{
+        procedures::run_simulation::RunEpisodeExt,
+        simulation::biped::{BipedSim, BipedSimConfig},
+        types::{history::History, policy::nil_policy::NilPolicy},
+        validation::StateValidator,
+    };
+
+    use super::{DatasetError, EpisodeDataset, EpisodeSource, MANIFEST_FILE};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("{name}_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    async fn episode(seed: u64) -> History {
+        let config = BipedSimConfig { episode_duration: 0.5, ..Default::default() };
+        BipedSim::new(config, seed).run_episode(&mut NilPolicy).await.unwrap()
+    }
+
+    fn assert_same(loaded: &History, history: &History) {
+        assert_eq!(loaded.states, history.states);
+        assert_eq!(loaded.actions, history.actions);
+        assert_eq!(loaded.rewards, history.rewards);
+        assert_eq!(loaded.terminals, history.terminals);
+        assert_eq!((loaded.truncated, loaded.success), (history.truncated, history.success));
+    }
+
+    #[tokio::test]
+    async fn episodes_survive_reopening() {
+        let dir = temp_dir("episode_dataset");
+        let histories = [episode(0).await, episode(1).await];
+
+        let mut dataset = EpisodeDataset::open(&dir).unwrap();
+        let source = EpisodeSource::new("nil").with_checkpoint("models/a_selector".as_ref());
+        for history in &histories {
+            dataset.append(history, &source).unwrap();
+        }
+        // a second run adds to what the first one left
+        let mut dataset = EpisodeDataset::open(&dir).unwrap();
+        assert_eq!(dataset.len(), 2);
+        dataset.append(&histories[0], &EpisodeSource::new("random")).unwrap();
+
+        let dataset = EpisodeDataset::open(&dir).unwrap();
+        let entries = dataset.episodes();
+        assert_eq!(entries.iter().map(|entry| entry.id).collect::<Vec<_>>(), [0, 1, 2]);
+        assert_eq!(entries[1].steps, histories[1].states.len());
+        assert_eq!(entries[1].total_reward, histories[1].rewards.iter().sum::<f32>());
+        assert_eq!(entries[0].source.checkpoint.as_deref(), Some("models/a_selector"));
+        assert_eq!(entries[2].source.policy, "random");
+
+        let loaded = dataset.load_all().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_same(&loaded[0], &histories[0]);
+        assert_same(&loaded[1], &histories[1]);
+        assert_same(&loaded[2], &histories[0]);
+
+        let tensors = dataset.load_tensor_histories::<NdArray>(&mut StateValidator::default(), &Default::default()).unwrap();
+        assert_eq!(tensors[1].states.dims()[0], histories[1].states.len());
+        // the tensors don't keep how the episode ended
+        let back = tensors[1].to_history();
+        assert_eq!((back.states, back.actions, back.rewards), (loaded[1].states.clone(), loaded[1].actions.clone(), loaded[1].rewards.clone()));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn damaged_files_are_reported() {
+        let dir = temp_dir("damaged_dataset");
+        let mut dataset = EpisodeDataset::open(&dir).unwrap();
+        let entry = dataset.append(&episode(0).await, &EpisodeSource::new("nil")).unwrap().clone();
+
+        // a torn last line is what a run stopped in the middle of appending leaves
+        let manifest = dir.join(MANIFEST_FILE);
+        let mut lines = fs::read_to_string(&manifest).unwrap();
+        lines.push_str("{\"id\": 1, \"fi");
+        fs::write(&manifest, lines).unwrap();
+        let dataset = EpisodeDataset::open(&dir).unwrap();
+        assert_eq!(dataset.episodes(), [entry.clone()]);
+
+        fs::write(dir.join(&entry.file), b"not gzip").unwrap();
+        assert!(matches!(dataset.load(&entry), Err(DatasetError::Io(_))));
+
+        // anything else is not left out quietly
+        fs::write(&manifest, "{\"id\": \"one\"}\n").unwrap();
+        assert!(matches!(EpisodeDataset::open(&dir), Err(DatasetError::Manifest(_))));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn appending_after_a_torn_line_keeps_the_manifest_readable() {
+        let dir = temp_dir("torn_dataset");
+        let history = episode(0).await;
+        let mut dataset = EpisodeDataset::open(&dir).unwrap();
+        dataset.append(&history, &EpisodeSource::new("nil")).unwrap();
+
+        let manifest = dir.join(MANIFEST_FILE);
+        let mut lines = fs::read_to_string(&manifest).unwrap();
+        lines.push_str("{\"id\": 1, \"fi");
+        fs::write(&manifest, lines).unwrap();
+
+        for expected in [2, 3] {
+            let mut dataset = EpisodeDataset::open(&dir).unwrap();
+            dataset.append(&history, &EpisodeSource::new("nil")).unwrap();
+            let reopened = EpisodeDataset::open(&dir).unwrap();
+            assert_eq!(reopened.len(), expected);
+            assert_eq!(reopened.episodes(), dataset.episodes());
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,65 @@
+use serde_json::error::Category;
+use thiserror::Error;
+
+use crate::types::environment::EnvironmentError;
+
+/// Everything that can go wrong while talking to the simulation.
+#[derive(Debug, Error)]
+pub enum SimulationError {
+    #[error("i/o error on the simulation stream: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("the simulation is not connected")]
+    Disconnected,
+    #[error("the simulation is not compatible: {reason}")]
+    Incompatible { reason: String },
+    #[error("frame of {len} bytes is larger than the {max} bytes allowed")]
+    Framing { len: usize, max: usize },
+    #[error("malformed message: {0}")]
+    Json(#[from] serde_json::Error),
+    // the message is well formed but its values don't fit, `message` is serde's and only meant to be read
+    #[error("{message} at {}", display_path(.path))]
+    Field { path: String, message: String },
+    #[error("binary message has {len} bytes, {expected} were expected")]
+    BinaryLength { len: usize, expected: usize },
+    #[error("binary message starts with unknown tag {0}")]
+    UnknownTag(u8),
+    #[error("step called before any episode was reset")]
+    NotReset,
+}
+
+impl SimulationError {
+    /// The data errors of serde, like a missing field or a value of the wrong type, get the dotted path of where they
+    /// happened, like `State.SensorsReading.Forces[3]`, anything else stays a `Json` error.
+    pub fn from_json_at(err: serde_path_to_error::Error<serde_json::Error>) -> Self {
+        let path = match err.path().iter().next() {
+            Some(_) => err.path().to_string(),
+            None => String::new(),
+        };
+        let err = err.into_inner();
+        match err.classify() {
+            Category::Data => {
+                // the path says where, the position serde_json adds is of no more help
+                let message = err.to_string();
+                let position = format!(" at line {} column {}", err.line(), err.column());
+                let message = message.strip_suffix(&position).unwrap_or(&message).to_string();
+                Self::Field { path, message }
+            }
+            Category::Io | Category::Syntax | Category::Eof => Self::Json(err),
+        }
+    }
+}
+
+impl EnvironmentError for SimulationError {
+    // after a framing error there is no telling where the next frame starts, so the stream is as good as gone
+    fn is_disconnect(&self) -> bool {
+        matches!(self, Self::Io(_) | Self::Framing { .. } | Self::Disconnected)
+    }
+}
+
+fn display_path(path: &str) -> String {
+    if path.is_empty() {
+        "the message root".to_string()
+    } else {
+        format!("`{path}`")
+    }
+}
@@ -0,0 +1,228 @@
+use burn::{config::Config, nn::Gelu, prelude::Backend};
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use crate::{
+    modules::{contact_encoder::ContactEncoder, feature_projection::FeatureProjection, sequential::LinearSequentialConfig},
+    schema::ObservationSchema,
+    tensor_conversion::{TensorConvertible, FORCES_COUNT},
+    types::state::{Force, GameState},
+};
+
+/// Which values of the states a model gets to see, by their names in the `ObservationSchema`.
+///
+/// A path selects every value under it, `*` stands for any one step of the path and a step without an index covers
+/// all of them, so `exclude: ["sensors_reading.forces"]` drops the contact forces and
+/// `include: ["limbs_readings.*.*.motor.pos", "limbs_readings.*.*.motor.speed", "sensors_reading.acc_reading"]` keeps
+/// the motors' positions and speeds and the accelerometer.
+#[derive(Config, Debug, PartialEq)]
+pub struct FeatureSelector {
+    // every value when empty
+    #[config(default = "Vec::new()")]
+    pub include: Vec<String>,
+    // taken out of what `include` selected
+    #[config(default = "Vec::new()")]
+    pub exclude: Vec<String>,
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum FeatureSelectorError {
+    #[error("`{0}` matches none of the values")]
+    UnknownPath(String),
+    #[error("every value is left out")]
+    Empty,
+}
+
+impl FeatureSelector {
+    /// Offsets of the selected values among those of `schema`, in their order there.
+    pub fn indices_in(&self, schema: &ObservationSchema) -> Result<Vec<usize>, FeatureSelectorError> {
+        // a path that matches nothing is a typo, better find out before training
+        for path in self.include.iter().chain(&self.exclude) {
+            if !schema.entries().iter().any(|entry| matches(path, &entry.name)) {
+                return Err(FeatureSelectorError::UnknownPath(path.clone()));
+            }
+        }
+        let indices = schema
+            .entries()
+            .iter()
+            .filter(|entry| self.include.is_empty() || self.include.iter().any(|path| matches(path, &entry.name)))
+            .filter(|entry| !self.exclude.iter().any(|path| matches(path, &entry.name)))
+            .map(|entry| entry.offset)
+            .collect::<Vec<_>>();
+        if indices.is_empty() {
+            return Err(FeatureSelectorError::Empty);
+        }
+        Ok(indices)
+    }
+
+    pub fn state_indices(&self) -> Result<Vec<usize>, FeatureSelectorError> {
+        self.indices_in(ObservationSchema::game_state())
+    }
+
+    /// Offsets for inputs made of `window` states one after the other, then `rest` values that are all kept.
+    pub fn window_indices(&self, window: usize, rest: usize) -> Result<Vec<usize>, FeatureSelectorError> {
+        let state_indices = self.state_indices()?;
+        let states = (0..window).flat_map(|ix| state_indices.iter().map(move |index| ix * GameState::VALUES_COUNT + index));
+        let rest = (0..rest).map(|index| window * GameState::VALUES_COUNT + index);
+        Ok(states.chain(rest).collect())
+    }
+
+    /// Panics on a selector that doesn't fit `GameState`, like the models do on sizes that don't fit.
+    pub fn init<B: Backend>(&self, dev: &<B as Backend>::Device) -> FeatureProjection<B> {
+        self.init_windowed(1, 0, dev)
+    }
+
+    pub fn init_windowed<B: Backend>(&self, window: usize, rest: usize, dev: &<B as Backend>::Device) -> FeatureProjection<B> {
+        let indices = self.window_indices(window, rest).unwrap_or_else(|err| panic!("invalid feature selector: {err}"));
+        FeatureProjection::new(&indices, dev)
+    }
+}
+
+/// How a model sees the contact forces. The simulation reports the contacts in no particular order, so apart from
+/// `Padded` the encodings give the same values whatever the order, and the padded forces are left out of the features
+/// for them.
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize)]
+pub enum ContactEncoding {
+    // the forces as they come among the features, in the order they are reported
+    #[default]
+    Padded,
+    // the forces from the strongest to the weakest, then the padding. Like in the states, past `FORCES_COUNT` contacts
+    // the weakest are left out
+    SortedByMagnitude,
+    // for each foot the sum of the forces of the contacts closest to it, their center of pressure and their count
+    PerFoot,
+    // the same layers applied to every contact, then summed over the contacts
+    DeepSets { hidden: usize, width: usize },
+}
+
+impl ContactEncoding {
+    /// How many values the contacts of a state are encoded into.
+    pub fn len(&self) -> usize {
+        match self {
+            ContactEncoding::Padded | ContactEncoding::SortedByMagnitude => FORCES_COUNT * Force::VALUES_COUNT,
+            ContactEncoding::PerFoot => 2 * 7,
+            ContactEncoding::DeepSets { width, .. } => *width,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn replaces_forces(&self) -> bool {
+        *self != ContactEncoding::Padded
+    }
+
+    /// `selector` without the padded forces when this encoding stands in for them.
+    pub fn features(&self, selector: &FeatureSelector) -> FeatureSelector {
+        if !self.replaces_forces() {
+            return selector.clone();
+        }
+        let mut exclude = selector.exclude.clone();
+        exclude.push("sensors_reading.forces".to_string());
+        selector.clone().with_exclude(exclude)
+    }
+
+    pub fn init<B: Backend>(&self, dev: &<B as Backend>::Device) -> ContactEncoder<B> {
+        let per_contact = match self {
+            ContactEncoding::DeepSets { hidden, width } => {
+                Some(LinearSequentialConfig { sizes: vec![Force::VALUES_COUNT, *hidden, *width], act: Gelu }.init(dev))
+            }
+            _ => None,
+        };
+        ContactEncoder::new(self.clone(), per_contact, dev)
+    }
+}
+
+pub(crate) fn matches(path: &str, name: &str) -> bool {
+    let mut steps = name.split('.');
+    path.split('.').all(|pattern| steps.next().is_some_and(|step| step_matches(pattern, step)))
+}
+
+fn step_matches(pattern: &str, step: &str) -> bool {
+    pattern == "*" || pattern == step || step.strip_prefix(pattern).is_some_and(|index| index.starts_with('['))
+}
+
+#[cfg(test)]
+mod test {
+    use burn::{
+        backend::NdArray,
+        module::Module,
+        prelude::Tensor,
+        record::{BinFileRecorder, FullPrecisionSettings},
+    };
+
+    use crate::{
+        models::v_estimator::VEstimatorConfig,
+        schema::ObservationSchema,
+        tensor_conversion::{TensorConvertible, FORCES_COUNT},
+        types::state::GameState,
+    };
+
+    use super::{FeatureSelector, FeatureSelectorError};
+
+    fn names(selector: &FeatureSelector) -> Vec<&'static str> {
+        let schema = ObservationSchema::game_state();
+        selector.state_indices().unwrap().into_iter().map(|index| schema.name_of(index).unwrap()).collect()
+    }
+
+    #[test]
+    fn selects_by_path() {
+        assert_eq!(FeatureSelector::new().state_indices().unwrap().len(), GameState::VALUES_COUNT);
+
+        let without_forces = FeatureSelector::new().with_exclude(vec!["sensors_reading.forces".to_string()]);
+        assert_eq!(without_forces.state_indices().unwrap().len(), GameState::VALUES_COUNT - FORCES_COUNT * 6);
+
+        let motors = FeatureSelector::new().with_include(vec![
+            "limbs_readings.*.*.motor.pos".to_string(),
+            "limbs_readings.*.*.motor.speed".to_string(),
+            "sensors_reading.acc_reading".to_string(),
+        ]);
+        let names = names(&motors);
+        assert_eq!(names.len(), 2 * 3 * 2 + 5 * 3);
+        assert_eq!(names[0], "sensors_reading.acc_reading.up.x");
+        assert!(names.contains(&"limbs_readings.right.shin.motor.speed"));
+        assert!(!names.contains(&"limbs_readings.right.shin.motor.acc"));
+    }
+
+    #[test]
+    fn paths_matching_nothing_are_errors() {
+        let typo = FeatureSelector::new().with_exclude(vec!["sensors_reading.force".to_string()]);
+        assert_eq!(typo.state_indices(), Err(FeatureSelectorError::UnknownPath("sensors_reading.force".to_string())));
+
+        let nothing = FeatureSelector::new().with_exclude(vec!["*".to_string()]);
+        assert_eq!(nothing.state_indices(), Err(FeatureSelectorError::Empty));
+    }
+
+    #[test]
+    fn windows_repeat_the_selection() {
+        let selector = FeatureSelector::new().with_include(vec!["sensors_reading.floor_distance".to_string()]);
+        assert_eq!(selector.window_indices(3, 2).unwrap(), [
+            3,
+            3 + GameState::VALUES_COUNT,
+            3 + 2 * GameState::VALUES_COUNT,
+            3 * GameState::VALUES_COUNT,
+            3 * GameState::VALUES_COUNT + 1,
+        ]);
+    }
+
+    #[test]
+    fn selection_is_saved_with_the_weights() {
+        type B = NdArray;
+        let dev = Default::default();
+        let selector = FeatureSelector::new().with_exclude(vec!["sensors_reading.forces".to_string(), "limbs_readings.*.foot".to_string()]);
+        let config = |features| VEstimatorConfig::new([8, 8], [4], [4], [4]).with_features(features);
+        let model = config(selector.clone()).init::<B>(&dev);
+
+        let path = std::env::temp_dir().join(format!("feature_selector_{}", std::process::id()));
+        let recorder = BinFileRecorder::<FullPrecisionSettings>::new();
+        model.clone().save_file(&path, &recorder).unwrap();
+        // whatever the model is built with, the saved projection is the one used
+        let loaded = config(FeatureSelector::new()).init::<B>(&dev).load_file(&path, &recorder, &dev).unwrap();
+        std::fs::remove_file(path.with_extension("bin")).unwrap();
+
+        assert_eq!(loaded.features().indices(), selector.state_indices().unwrap());
+        let states = Tensor::<B, 2>::ones([2, GameState::VALUES_COUNT], &dev);
+        assert_eq!(loaded.forward(&states).into_data(), model.forward(&states).into_data());
+    }
+}
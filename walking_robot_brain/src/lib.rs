@@ -1,9 +1,16 @@
 pub mod comm;
+pub mod error;
 pub mod types;
-pub mod traits;
 pub mod tensor_conversion;
 pub mod tools;
 pub mod models;
 pub mod procedures;
 pub mod modules;
-pub mod loss;
\ No newline at end of file
+pub mod loss;
+pub mod simulation;
+pub mod recording;
+pub mod wire;
+pub mod schema;
+pub mod features;
+pub mod validation;
+pub mod dataset;
@@ -1,6 +1,6 @@
-use burn::{config::Config, module::Module, nn::{Gelu, InstanceNorm, InstanceNormConfig, Linear, LinearConfig, Tanh}, prelude::Backend, tensor::Tensor};
+use burn::{config::Config, module::Module, nn::{Gelu, Linear, LinearConfig, Tanh}, prelude::Backend, tensor::Tensor};
 
-use crate::{modules::forward_module::ForwardModule, tensor_conversion::TensorConvertible, tools::UsedInTrait, types::{action::GameAction, policy::{HasDevice, TensorPolicy}, state::GameState}};
+use crate::{features::{ContactEncoding, FeatureSelector}, modules::{contact_encoder::ContactEncoder, feature_projection::FeatureProjection, forward_module::ForwardModule, running_normalizer::{RunningNormalizer, RunningNormalizerConfig}}, tensor_conversion::TensorConvertible, tools::UsedInTrait, types::{action::GameAction, policy::{HasDevice, TensorPolicy}, state::GameState}};
 
 #[derive(Config)]
 pub struct ASelectorConfig{
@@ -10,14 +10,28 @@ pub struct ASelectorConfig{
     
     // pub recurrent_layers_size: [usize;3],
     pub end				: [usize;4],
+    // what the model sees of the states
+    #[config(default = "FeatureSelector::new()")]
+    pub features			: FeatureSelector,
+    // how the contact forces are fed to the model
+    #[config(default = "ContactEncoding::Padded")]
+    pub contacts			: ContactEncoding,
+    // scales the inputs with the statistics of the observed states
+    #[config(default = "RunningNormalizerConfig::new()")]
+    pub normalizer			: RunningNormalizerConfig,
 }
 
 impl ASelectorConfig{
    pub fn init<B: Backend>(&self, dev: &<B as Backend>::Device ) -> ASelector<B>{
+		let features = self.contacts.features(&self.features).init(dev);
+		let contacts = self.contacts.init(dev);
+		let input_size = features.len() + contacts.appended_len();
     	let a = 
         ASelector{
-			norm: InstanceNormConfig::new(GameState::VALUES_COUNT).init(dev),
-			linear_0: LinearConfig::new(GameState::VALUES_COUNT, self.linear_layers_size[0]).init(dev),
+			normalizer: self.normalizer.init(input_size, dev),
+			linear_0: LinearConfig::new(input_size, self.linear_layers_size[0]).init(dev),
+			features,
+			contacts,
 			act_0: Gelu,
 			linear_1: LinearConfig::new(self.linear_layers_size[0], self.linear_layers_size[1]).init(dev),
 			act_1: Gelu,
@@ -56,8 +70,9 @@ impl ASelectorConfig{
 }
 #[derive(Module, Debug)]
 pub struct ASelector<B: Backend>{
-	
-	norm: InstanceNorm<B>,
+	features: FeatureProjection<B>,
+	contacts: ContactEncoder<B>,
+	normalizer: RunningNormalizer<B>,
 
     linear_0: Linear<B>,
     act_0: Gelu,
@@ -117,10 +132,29 @@ impl<'a, B: Backend> TensorPolicy<B> for &'a ASelector<B>{
 
 
 impl<B: Backend> ASelector<B>{
+    pub fn features(&self) -> &FeatureProjection<B>{
+        &self.features
+    }
+    pub fn contacts(&self) -> &ContactEncoder<B>{
+        &self.contacts
+    }
+    pub fn normalizer(&self) -> &RunningNormalizer<B>{
+        &self.normalizer
+    }
+    // what the normalizer sees of the states
+    fn inputs(&self, states_tensor: &Tensor<B, 2>) -> Tensor<B, 2>{
+        self.contacts.append_to(self.features.forward(states_tensor.clone()), states_tensor.clone())
+    }
+    /// Adds the states to the statistics the inputs are normalized with.
+    pub fn observe(mut self, states_tensor: &Tensor<B, 2>) -> Self{
+        let inputs = self.inputs(states_tensor);
+        self.normalizer = self.normalizer.observe(inputs);
+        self
+    }
     pub fn forward(&self, states_tensor: &Tensor<B, 2>) -> Tensor<B, 2>{
         let linear_x = 
-		 	states_tensor.clone()
-			// .used_in(|x| self.norm.forward(x))
+		 	self.inputs(states_tensor)
+			.used_in(|x| self.normalizer.forward(x))
 			.used_in(|x| self.linear_0.forward(x))
 			.used_in(|x| self.act_0.forward(x))
 			.used_in(|x| self.linear_1.forward(x))
@@ -1,6 +1,6 @@
 use std::{
     ops::Deref,
-    path::PathBuf,
+    path::{Path, PathBuf},
     str::FromStr,
     sync::{LazyLock, Mutex},
 };
@@ -9,12 +9,14 @@ use burn::{
     module::Module,
     nn::Gelu,
     prelude::Backend,
-    record::{CompactRecorder, FullPrecisionSettings, PrettyJsonFileRecorder},
+    record::{CompactRecorder, FileRecorder, FullPrecisionSettings, PrettyJsonFileRecorder},
 };
 use rand::TryRngCore;
+use tracing::warn;
 
 use crate::{
-    modules::sequential::LinearSequentialConfig, tensor_conversion::TensorConvertible,
+    features::{ContactEncoding, FeatureSelector},
+    modules::{running_normalizer::RunningNormalizerConfig, sequential::LinearSequentialConfig}, tensor_conversion::TensorConvertible,
     types::{action::GameAction, state::GameState},
 };
 
@@ -37,6 +39,19 @@ pub const V_ESTIMATOR_MODEL_PATH: LazyLock<PathBuf> =
 pub static MODELS_RECORDER: LazyLock<Mutex<CompactRecorder>> =
     LazyLock::new(|| Mutex::new(CompactRecorder::new()));
 pub const WINDOW_SIZE: i64 = 5;
+
+/// `model` with the weights saved at `path`, `None` when they can't be loaded into it. Files saved before the models
+/// got their feature selection, contact encoding and normalizer don't fit them anymore, they are warned about and the
+/// model starts from new weights.
+pub fn load_saved<B: Backend, M: Module<B>>(model: M, path: &Path, recorder: &impl FileRecorder<B>, dev: &<B as Backend>::Device) -> Option<M> {
+    match model.load_file(path, recorder, dev) {
+        Ok(model) => Some(model),
+        Err(err) => {
+            warn!("could not load {}, starting from new weights: {err}", path.display());
+            None
+        }
+    }
+}
 pub const ENC_STATE_SIZE: usize = 128;
 pub fn make_a_selector<B: Backend>(dev: &<B as Backend>::Device) -> ASelector<B> {
     let mut model = ASelectorConfig {
@@ -44,17 +59,13 @@ pub fn make_a_selector<B: Backend>(dev: &<B as Backend>::Device) -> ASelector<B>
         logic_layers_size: [256, 512, 512],
         cut_through_layers_size: [1024, 1024],
         end: [1024; 4],
+        features: FeatureSelector::new(),
+        contacts: ContactEncoding::Padded,
+        normalizer: RunningNormalizerConfig::new(),
     }
     .init(dev);
     if A_SELECTOR_MODEL_PATH.exists() {
-        model = model
-            .clone()
-            .load_file(
-                &*A_SELECTOR_MODEL_PATH,
-                MODELS_RECORDER.lock().unwrap().deref(),
-                dev,
-            )
-            .unwrap();
+        model = load_saved(model.clone(), &A_SELECTOR_MODEL_PATH, MODELS_RECORDER.lock().unwrap().deref(), dev).unwrap_or(model);
     }
     model
 }
@@ -67,18 +78,15 @@ pub fn make_rs_estimator<B: Backend>(dev: &<B as Backend>::Device) -> RsEstimato
         logic: [1024],
         cut_through: [1024],
         end: [2048, 1024, 512, 256],
+        features: FeatureSelector::new(),
+        contacts: ContactEncoding::Padded,
+        normalizer: RunningNormalizerConfig::new(),
+        normalize_targets: false,
     }
     .init(dev);
 
     if RS_ESTIMATOR_MODEL_PATH.exists() {
-        model = model
-            .clone()
-            .load_file(
-                &*RS_ESTIMATOR_MODEL_PATH,
-                MODELS_RECORDER.lock().unwrap().deref(),
-                dev,
-            )
-            .unwrap();
+        model = load_saved(model.clone(), &RS_ESTIMATOR_MODEL_PATH, MODELS_RECORDER.lock().unwrap().deref(), dev).unwrap_or(model);
     }
     model
 }
@@ -89,18 +97,14 @@ pub fn make_v_estimator<B: Backend>(dev: &<B as Backend>::Device) -> VEstimator<
         logic: [512],
         cut_through: [1024],
         end: [2048],
+        features: FeatureSelector::new(),
+        contacts: ContactEncoding::Padded,
+        normalizer: RunningNormalizerConfig::new(),
     }
     .init(dev);
 
     if V_ESTIMATOR_MODEL_PATH.exists() {
-        model = model
-            .clone()
-            .load_file(
-                &*V_ESTIMATOR_MODEL_PATH,
-                MODELS_RECORDER.lock().unwrap().deref(),
-                dev,
-            )
-            .unwrap();
+        model = load_saved(model.clone(), &V_ESTIMATOR_MODEL_PATH, MODELS_RECORDER.lock().unwrap().deref(), dev).unwrap_or(model);
     }
     model
 }
@@ -127,7 +131,7 @@ pub fn make_sa_endec<B: Backend>(dev: &<B as Backend>::Device) -> SaEnDec<B> {
             }
             .init(dev); 
         if SA_ENC_MODEL_PATH.exists(){
-            model = model.load_file(SA_ENC_MODEL_PATH.as_path(), MODELS_RECORDER.lock().unwrap().deref(), dev).unwrap();
+            model = load_saved(model.clone(), &SA_ENC_MODEL_PATH, MODELS_RECORDER.lock().unwrap().deref(), dev).unwrap_or(model);
         }
         model
     };
@@ -148,7 +152,7 @@ pub fn make_sa_endec<B: Backend>(dev: &<B as Backend>::Device) -> SaEnDec<B> {
             .init(dev);
 
         if SA_DEC_MODEL_PATH.exists(){
-            model = model.load_file(SA_DEC_MODEL_PATH.as_path(),  MODELS_RECORDER.lock().unwrap().deref(), dev).unwrap();
+            model = load_saved(model.clone(), &SA_DEC_MODEL_PATH, MODELS_RECORDER.lock().unwrap().deref(), dev).unwrap_or(model);
         }
         model
     };
@@ -162,17 +166,15 @@ pub fn make_q_estimator<B: Backend>(dev: &<B as Backend>::Device) -> QEstimator<
                 initial     : vec![2048, 1024],
                 logic       : vec![1024, 512],
                 cut_through : vec![512, 512],
-                joint       : vec![1024, 1024,512,512,512, 256, 256, 256]
+                joint       : vec![1024, 1024,512,512,512, 256, 256, 256],
+                features    : FeatureSelector::new(),
+                contacts    : ContactEncoding::Padded,
+                normalizer  : RunningNormalizerConfig::new(),
             }
             .init(dev);
 
         if  Q_ESTIMATOR_MODEL_PATH.exists(){
-            model = model.load_file(
-                Q_ESTIMATOR_MODEL_PATH.as_path(),  
-                MODELS_RECORDER.lock().unwrap().deref(), 
-                dev
-            )
-            .unwrap();
+            model = load_saved(model.clone(), &Q_ESTIMATOR_MODEL_PATH, MODELS_RECORDER.lock().unwrap().deref(), dev).unwrap_or(model);
         }
         model
     };
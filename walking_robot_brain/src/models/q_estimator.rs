@@ -4,7 +4,7 @@ use burn::{module::Module, nn::{LeakyRelu, LeakyReluConfig, Linear, LinearConfig
 use itertools::Itertools;
 use rand::rng;
 
-use crate::{modules::{forward_module::ForwardModule, sequential::{LinearSequential, LinearSequentialConfig}}, tensor_conversion::TensorConvertible, types::{action::GameAction, policy::{nil_policy::NilPolicy, noisy_policy::NoisyPolicy, TensorPolicy}, state::GameState}};
+use crate::{features::{ContactEncoding, FeatureSelector}, modules::{contact_encoder::ContactEncoder, feature_projection::FeatureProjection, forward_module::ForwardModule, running_normalizer::{RunningNormalizer, RunningNormalizerConfig}, sequential::{LinearSequential, LinearSequentialConfig}}, procedures::train::q_estimator_monte_carlo::windowed_inputs, tensor_conversion::TensorConvertible, types::{action::GameAction, history::TensorHistory, policy::{nil_policy::NilPolicy, noisy_policy::NoisyPolicy, TensorPolicy}, state::GameState}};
 
 use super::builders::WINDOW_SIZE;
 pub struct QEstimatorConfig{
@@ -12,10 +12,19 @@ pub struct QEstimatorConfig{
 	pub logic		: Vec<usize>,
 	pub cut_through : Vec<usize>,
 	pub joint		: Vec<usize>,
+	// what the model sees of each state of the window, the actions are all kept
+	pub features	: FeatureSelector,
+	// how the contact forces of each state of the window are fed to the model
+	pub contacts	: ContactEncoding,
+	// scales the inputs with the statistics of the observed ones
+	pub normalizer	: RunningNormalizerConfig,
 }
 impl QEstimatorConfig{
 	pub fn init<B: Backend>(&self, dev: &<B as Backend>::Device) -> QEstimator<B>{
-		let input_size = WINDOW_SIZE as usize * (GameState::VALUES_COUNT + GameAction::VALUES_COUNT);
+		let window = WINDOW_SIZE as usize;
+		let features = self.contacts.features(&self.features).init_windowed(window, window * GameAction::VALUES_COUNT, dev);
+		let contacts = self.contacts.init(dev);
+		let input_size = features.len() + window * contacts.appended_len();
 
 		let initial = iter::once(input_size).chain(self.initial.iter().cloned()).collect_vec();
 		let logic = iter::once(initial.last().unwrap().clone()).chain(self.logic.iter().cloned()).collect_vec();
@@ -27,6 +36,9 @@ impl QEstimatorConfig{
 		let joint_to_output = joint.last().unwrap().clone();
 
 		QEstimator{
+			features,
+			contacts,
+			normalizer: self.normalizer.init(input_size, dev),
 			initial		: 
 				LinearSequentialConfig{
 					sizes:  initial,
@@ -62,6 +74,9 @@ impl QEstimatorConfig{
 	Module, Debug
 )]
 pub struct QEstimator<B: Backend>{
+	features	: FeatureProjection<B>,
+	contacts	: ContactEncoder<B>,
+	normalizer	: RunningNormalizer<B>,
 	initial		: LinearSequential<B, LeakyRelu>,
 	logic		: LinearSequential<B, Tanh>,
 	cut_through : LinearSequential<B, LeakyRelu>,
@@ -69,9 +84,30 @@ pub struct QEstimator<B: Backend>{
 	output		: Linear<B>
 }
 
+impl<B: Backend> QEstimator<B>{
+	pub fn contacts(&self) -> &ContactEncoder<B>{
+		&self.contacts
+	}
+	pub fn normalizer(&self) -> &RunningNormalizer<B>{
+		&self.normalizer
+	}
+	// what the normalizer sees of the windows
+	fn inputs(&self, input: Tensor<B,2>) -> Tensor<B,2>{
+		self.contacts.append_to_windows(self.features.forward(input.clone()), input, WINDOW_SIZE as usize)
+	}
+	/// Adds the steps of a history to the statistics the inputs are normalized with.
+	pub fn observe(mut self, history: &TensorHistory<B>) -> Self{
+		let inputs = self.inputs(windowed_inputs(history));
+		self.normalizer = self.normalizer.observe(inputs);
+		self
+	}
+}
+
 impl<B: Backend> ForwardModule<B> for QEstimator<B>{
 	fn forward(&self, input: Tensor<B,2>) -> Tensor<B,2> {
-		let x = self.initial.forward(input);
+		let x = self.inputs(input);
+		let x = self.normalizer.forward(x);
+		let x = self.initial.forward(x);
 		let logic  = self.logic.forward(x.clone());
 		let cut_through = self.cut_through.forward(x);
 		
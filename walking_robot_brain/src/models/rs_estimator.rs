@@ -4,7 +4,7 @@ use burn::{nn::{InstanceNorm, InstanceNormConfig, LeakyRelu, LeakyReluConfig, Li
 use itertools::Itertools;
 use tracing::warn;
 
-use crate::{modules::forward_module::ForwardModule,  tensor_conversion::TensorConvertible, tools::UsedInTrait, types::{action::GameAction, state::{GameState, Reward}}};
+use crate::{features::{ContactEncoding, FeatureSelector}, modules::{contact_encoder::ContactEncoder, feature_projection::FeatureProjection, forward_module::ForwardModule, running_normalizer::{RunningNormalizer, RunningNormalizerConfig}}, procedures::train::rs_estimator_train::training_windows,  tensor_conversion::TensorConvertible, tools::UsedInTrait, types::{action::GameAction, state::{GameState, Reward}}};
 
 use super::builders::WINDOW_SIZE;
 
@@ -16,15 +16,34 @@ pub struct RsEstimatorConfig{
     pub logic 					: [usize;1],
 	pub cut_through				: [usize;1],
     pub end						: [usize;4],
+	// what the model sees of each state of the window, it still predicts the whole next state
+	#[config(default = "FeatureSelector::new()")]
+	pub features				: FeatureSelector,
+	// how the contact forces of each state of the window are fed to the model
+	#[config(default = "ContactEncoding::Padded")]
+	pub contacts				: ContactEncoding,
+	// scales the states of the window with the statistics of the observed ones
+	#[config(default = "RunningNormalizerConfig::new()")]
+	pub normalizer				: RunningNormalizerConfig,
+	// also learn the rewards and next states scaled to the observed ones, the predictions are scaled back
+	#[config(default = false)]
+	pub normalize_targets		: bool,
 }
 
 impl RsEstimatorConfig{
 	pub fn init<B:Backend>(self, dev: &<B as Backend>::Device) -> RsEstimator<B>{
+		let state_features = self.contacts.features(&self.features).init_windowed(WINDOW_SIZE as usize, 0, dev);
+		let contacts = self.contacts.init(dev);
+		let state_size = state_features.len() + WINDOW_SIZE as usize * contacts.appended_len();
 		RsEstimator{
 			action_linear_0: LinearConfig::new(GameAction::VALUES_COUNT * WINDOW_SIZE as usize, self.action_layers_size[0]).init(dev),
 			action_act_0: LeakyReluConfig::new().init(),	
 
-			state_linear_0: LinearConfig::new(GameState::VALUES_COUNT * WINDOW_SIZE as usize, self.state_layers_size[0]).init(dev),
+			state_linear_0: LinearConfig::new(state_size, self.state_layers_size[0]).init(dev),
+			state_normalizer: self.normalizer.init(state_size, dev),
+			state_features,
+			contacts,
+			target_normalizer: self.normalize_targets.then(|| self.normalizer.init(GameState::VALUES_COUNT + 1, dev)),
 			state_act_0: LeakyReluConfig::new().init(),
 
 
@@ -54,6 +73,11 @@ impl RsEstimatorConfig{
 
 #[derive(Debug, Module)]
 pub struct RsEstimator<B: Backend>{
+    state_features	: FeatureProjection<B>,
+    contacts		: ContactEncoder<B>,
+    state_normalizer	: RunningNormalizer<B>,
+    // the rewards and next states are learnt scaled when there is one
+    target_normalizer	: Option<RunningNormalizer<B>>,
     state_linear_0	: Linear<B>,
     state_act_0		: LeakyRelu,
 
@@ -83,9 +107,56 @@ pub struct RsEstimator<B: Backend>{
 }
 
 impl<B: Backend> RsEstimator<B>{
+	pub fn state_features(&self) -> &FeatureProjection<B>{
+		&self.state_features
+	}
+	pub fn contacts(&self) -> &ContactEncoder<B>{
+		&self.contacts
+	}
+	pub fn state_normalizer(&self) -> &RunningNormalizer<B>{
+		&self.state_normalizer
+	}
+	pub fn target_normalizer(&self) -> Option<&RunningNormalizer<B>>{
+		self.target_normalizer.as_ref()
+	}
+	/// Adds the steps of a history to the statistics the states, and the targets if they are scaled, are normalized
+	/// with.
+	pub fn observe(mut self, states: &Tensor<B,2>, actions: &Tensor<B,2>, rewards: &Tensor<B,2>) -> Self {
+		let (input_tensor, target_output_tensor) = training_windows(states, actions, rewards);
+		let states_tensor = input_tensor.slice([None, Some((0_i64, GameState::VALUES_COUNT as i64 * WINDOW_SIZE))]);
+		let state_inputs = self.state_inputs(states_tensor);
+		self.state_normalizer = self.state_normalizer.observe(state_inputs);
+		self.target_normalizer = self.target_normalizer.map(|normalizer| normalizer.observe(target_output_tensor));
+		self
+	}
+	// what the normalizer sees of the windows of states
+	fn state_inputs(&self, states_tensor: Tensor<B, 2>) -> Tensor<B, 2> {
+		self.contacts.append_to_windows(self.state_features.forward(states_tensor.clone()), states_tensor, WINDOW_SIZE as usize)
+	}
+	/// The targets as the network learns them.
+	pub fn scaled_targets(&self, target_output_tensor: Tensor<B, 2>) -> Tensor<B, 2> {
+		match &self.target_normalizer {
+			Some(normalizer) => normalizer.normalize(target_output_tensor),
+			None => target_output_tensor,
+		}
+	}
 	pub fn forward(&self, states_tensor: &Tensor<B, 2>, actions_tensor: &Tensor<B,2> ) -> (Tensor<B, 1>, Tensor<B, 2>) {
+		let output = self.scaled_forward(states_tensor, actions_tensor);
+		let output = match &self.target_normalizer {
+			Some(normalizer) => normalizer.denormalize(output),
+			None => output,
+		};
+		let r_output = output.clone().slice([None, Some((0, 1))]).squeeze(1);
+		let s_output = output.clone().slice([None, Some((1, output.dims()[1] as i64))]);
+
+		(r_output, s_output)
+	}
+	// rewards and next states side by side, scaled like `scaled_targets`
+	fn scaled_forward(&self, states_tensor: &Tensor<B, 2>, actions_tensor: &Tensor<B,2> ) -> Tensor<B, 2> {
 			let states_x = 
 				states_tensor.clone()
+				.used_in(|x| self.state_inputs(x))
+				.used_in(|x| self.state_normalizer.forward(x))
 				.used_in(|x| self.state_linear_0.forward(x))
 				.used_in(|x| self.state_act_0.forward(x));
 
@@ -115,8 +186,7 @@ impl<B: Backend> RsEstimator<B>{
 
 			let end_x = Tensor::cat(vec![logic_x, cut_through_x], 1);
 
-			let output =  
-				end_x
+			end_x
 				.used_in(|x| self.end_linear_0.forward(x))
 				.used_in(|x| self.end_act_0.forward(x))
 				.used_in(|x| self.end_linear_1.forward(x))
@@ -126,14 +196,6 @@ impl<B: Backend> RsEstimator<B>{
 				.used_in(|x| self.end_linear_3.forward(x))
 				.used_in(|x| self.end_act_3.forward(x))
 				.used_in(|x| self.end_linear_4.forward(x))
-				
-				;
-
-
-			let r_output = output.clone().slice([None, Some((0, 1))]).squeeze(1);
-			let s_output = output.clone().slice([None, Some((1, output.dims()[1] as i64))]);
-
-			(r_output, s_output)
 		// })
 		// .unzip();
 		// (Tensor::cat(r_output, 0), Tensor::cat(s_output, 0))
@@ -147,7 +209,7 @@ impl<B: Backend> ForwardModule<B> for RsEstimator<B>{
 		let states_tensor = input.clone().slice([None, Some((0_i64, GameState::VALUES_COUNT as i64 * WINDOW_SIZE))]);
 		let actions_tensor = input.clone().slice([None, Some((GameState::VALUES_COUNT as i64 * WINDOW_SIZE , len as i64))]);
 		
-		let(rewards, next_states) = self.forward(&states_tensor, &actions_tensor);
-		Tensor::cat(vec![rewards.unsqueeze_dim(1), next_states], 1)
+		// trained against `scaled_targets`
+		self.scaled_forward(&states_tensor, &actions_tensor)
 	}
 }
@@ -1,7 +1,7 @@
-use burn::{config::Config, module::Module, nn::{Gelu, InstanceNorm, InstanceNormConfig, Linear, LinearConfig, Tanh}, prelude::Backend, tensor::Tensor};
+use burn::{config::Config, module::Module, nn::{Gelu, Linear, LinearConfig, Tanh}, prelude::Backend, tensor::Tensor};
 use itertools::Itertools;
 
-use crate::{modules::forward_module::ForwardModule, tensor_conversion::TensorConvertible, tools::UsedInTrait, types::state::GameState};
+use crate::{features::{ContactEncoding, FeatureSelector}, modules::{contact_encoder::ContactEncoder, feature_projection::FeatureProjection, forward_module::ForwardModule, running_normalizer::{RunningNormalizer, RunningNormalizerConfig}}, tensor_conversion::TensorConvertible, tools::UsedInTrait, types::state::GameState};
 
 
 #[derive( Config, )]
@@ -11,13 +11,27 @@ pub struct VEstimatorConfig{
     pub logic 			: [usize;1],
 	pub cut_through		: [usize;1],
     pub end				: [usize;1],
+	// what the model sees of the states
+	#[config(default = "FeatureSelector::new()")]
+	pub features		: FeatureSelector,
+	// how the contact forces are fed to the model
+	#[config(default = "ContactEncoding::Padded")]
+	pub contacts		: ContactEncoding,
+	// scales the inputs with the statistics of the observed states
+	#[config(default = "RunningNormalizerConfig::new()")]
+	pub normalizer		: RunningNormalizerConfig,
 }
 
 impl VEstimatorConfig{
 	pub fn init<B:Backend>(self, dev: &<B as Backend>::Device) -> VEstimator<B>{
+		let features = self.contacts.features(&self.features).init(dev);
+		let contacts = self.contacts.init(dev);
+		let input_size = features.len() + contacts.appended_len();
 		VEstimator{
-
-			linear_0: LinearConfig::new(GameState::VALUES_COUNT, self.initial[0]).init(dev),
+			normalizer: self.normalizer.init(input_size, dev),
+			linear_0: LinearConfig::new(input_size, self.initial[0]).init(dev),
+			features,
+			contacts,
 			act_0: Gelu,
 			linear_1: LinearConfig::new(self.initial[0], self.initial[1]).init(dev),
 			act_1: Gelu,
@@ -37,6 +51,9 @@ impl VEstimatorConfig{
 
 #[derive(Debug, Module)]
 pub struct VEstimator<B: Backend>{
+    features: FeatureProjection<B>,
+    contacts: ContactEncoder<B>,
+    normalizer: RunningNormalizer<B>,
     linear_0: Linear<B>,
     act_0: Gelu,
     linear_1: Linear<B>,
@@ -62,9 +79,29 @@ impl<B: Backend> VEstimator<B>{
 		let values = self.forward(&states_tensor);
 		values.into_data().to_vec().unwrap()
 	}
+	pub fn features(&self) -> &FeatureProjection<B>{
+		&self.features
+	}
+	pub fn contacts(&self) -> &ContactEncoder<B>{
+		&self.contacts
+	}
+	pub fn normalizer(&self) -> &RunningNormalizer<B>{
+		&self.normalizer
+	}
+	// what the normalizer sees of the states
+	fn inputs(&self, states_tensor: &Tensor<B, 2>) -> Tensor<B, 2>{
+		self.contacts.append_to(self.features.forward(states_tensor.clone()), states_tensor.clone())
+	}
+	/// Adds the states to the statistics the inputs are normalized with.
+	pub fn observe(mut self, states_tensor: &Tensor<B, 2>) -> Self{
+		let inputs = self.inputs(states_tensor);
+		self.normalizer = self.normalizer.observe(inputs);
+		self
+	}
 	pub fn forward(&self, states_tensor: &Tensor<B, 2>,) -> Tensor<B, 1>{
 		let states_x = 
-		 	states_tensor.clone()
+		 	self.inputs(states_tensor)
+			.used_in(|x| self.normalizer.forward(x))
 			.used_in(|x| self.linear_0.forward(x))
 			.used_in(|x| self.act_0.forward(x))
 			.used_in(|x| self.linear_1.forward(x))
@@ -0,0 +1,396 @@
+use std::{fmt, sync::LazyLock};
+
+use burn::{
+    module::{AutodiffModule, Content, Devices, Module, ModuleDisplay, ModuleDisplayDefault, ModuleMapper, ModuleVisitor, Param, ParamId},
+    nn::Gelu,
+    prelude::{Backend, Int, Tensor},
+    tensor::backend::AutodiffBackend,
+};
+
+use crate::{
+    features::ContactEncoding,
+    schema::ObservationSchema,
+    tensor_conversion::{TensorConvertible, FORCES_COUNT},
+    types::state::{Force, GameState},
+};
+
+use super::{forward_module::ForwardModule, sequential::LinearSequential};
+
+// where the forces and the feet positions are among the values of a state
+struct ContactColumns {
+    forces  : usize,
+    feet    : [usize; 2],
+}
+
+static CONTACT_COLUMNS: LazyLock<ContactColumns> = LazyLock::new(|| {
+    let schema = ObservationSchema::game_state();
+    let column = |name: &str| schema.index_of(name).unwrap_or_else(|| panic!("no `{name}` in the states"));
+    ContactColumns {
+        forces  : column("sensors_reading.forces[0].pos.x"),
+        feet    : [
+            column("limbs_readings.left.foot.linear_pos.x"),
+            column("limbs_readings.right.foot.linear_pos.x"),
+        ],
+    }
+});
+
+// the encoding as it is saved: its kind, then the sizes of the deep sets layers
+fn encoding_codes(encoding: &ContactEncoding) -> [i64; 3] {
+    match *encoding {
+        ContactEncoding::Padded => [0, 0, 0],
+        ContactEncoding::SortedByMagnitude => [1, 0, 0],
+        ContactEncoding::PerFoot => [2, 0, 0],
+        ContactEncoding::DeepSets { hidden, width } => [3, hidden as i64, width as i64],
+    }
+}
+
+fn encoding_of(codes: &[i64]) -> ContactEncoding {
+    match *codes {
+        [0, ..] => ContactEncoding::Padded,
+        [1, ..] => ContactEncoding::SortedByMagnitude,
+        [2, ..] => ContactEncoding::PerFoot,
+        [3, hidden, width] => ContactEncoding::DeepSets { hidden: hidden as usize, width: width as usize },
+        _ => panic!("unknown contact encoding {codes:?}"),
+    }
+}
+
+/// What of a `ContactEncoder` is saved, its encoding and the deep sets layers when it has them.
+#[derive(Debug, Module)]
+pub struct SavedContactEncoder<B: Backend> {
+    encoding    : Param<Tensor<B, 1, Int>>,
+    per_contact : Option<LinearSequential<B, Gelu>>,
+}
+
+/// Turns the contact forces of a batch of states into values that don't depend on the order the simulation reports
+/// the contacts in, see `ContactEncoding`. The encoding is part of the record like the columns of a
+/// `FeatureProjection`, a model loaded from a file encodes the contacts like the model that was saved, with the deep
+/// sets layers of the saved sizes.
+#[derive(Clone, Debug)]
+pub struct ContactEncoder<B: Backend> {
+    saved       : SavedContactEncoder<B>,
+    // read back from `saved` once, not on every forward
+    encoding    : ContactEncoding,
+}
+
+impl<B: Backend> ContactEncoder<B> {
+    pub fn new(encoding: ContactEncoding, per_contact: Option<LinearSequential<B, Gelu>>, dev: &<B as Backend>::Device) -> Self {
+        let codes = Tensor::from_ints(encoding_codes(&encoding), dev);
+        Self { saved: SavedContactEncoder { encoding: Param::initialized(ParamId::new(), codes), per_contact }, encoding }
+    }
+
+    pub fn encoding(&self) -> &ContactEncoding {
+        &self.encoding
+    }
+
+    /// How many values `append_to` adds to the features.
+    pub fn appended_len(&self) -> usize {
+        if self.encoding.replaces_forces() { self.encoding.len() } else { 0 }
+    }
+
+    /// `features` followed by the encoding of the contacts of `states`, unless the forces are left among the features.
+    pub fn append_to(&self, features: Tensor<B, 2>, states: Tensor<B, 2>) -> Tensor<B, 2> {
+        if self.encoding.replaces_forces() {
+            Tensor::cat(vec![features, self.forward(states)], 1)
+        } else {
+            features
+        }
+    }
+
+    /// Like `append_to` for `window` states side by side, the encodings of the states follow each other.
+    pub fn append_to_windows(&self, features: Tensor<B, 2>, windows: Tensor<B, 2>, window: usize) -> Tensor<B, 2> {
+        if !self.encoding.replaces_forces() {
+            return features;
+        }
+        let [batch, _] = windows.dims();
+        let states = windows.slice([0..batch, 0..window * GameState::VALUES_COUNT]).reshape([batch * window, GameState::VALUES_COUNT]);
+        let encoded = self.forward(states);
+        let width = encoded.dims()[1];
+        Tensor::cat(vec![features, encoded.reshape([batch, window * width])], 1)
+    }
+
+    // [batch, contact, value of the force]
+    fn forces(states: &Tensor<B, 2>) -> Tensor<B, 3> {
+        let [batch, _] = states.dims();
+        let start = CONTACT_COLUMNS.forces;
+        states
+            .clone()
+            .slice([0..batch, start..start + FORCES_COUNT * Force::VALUES_COUNT])
+            .reshape([batch, FORCES_COUNT, Force::VALUES_COUNT])
+    }
+
+    fn sorted_by_magnitude(forces: Tensor<B, 3>, magnitudes: Tensor<B, 3>) -> Tensor<B, 3> {
+        let (_, order) = magnitudes.sort_descending_with_indices(1);
+        forces.gather(1, order.repeat_dim(2, Force::VALUES_COUNT))
+    }
+
+    // total force, center of pressure and number of contacts of each foot
+    fn per_foot(states: &Tensor<B, 2>, forces: Tensor<B, 3>, magnitudes: Tensor<B, 3>) -> Tensor<B, 2> {
+        let [batch, _, _] = forces.dims();
+        let positions = forces.clone().slice([0..batch, 0..FORCES_COUNT, 0..3]);
+        let vectors = forces.slice([0..batch, 0..FORCES_COUNT, 3..6]);
+        let distance_to = |foot: usize| {
+            let foot = states.clone().slice([0..batch, foot..foot + 3]).unsqueeze_dim::<3>(1);
+            squared(positions.clone() - foot).sum_dim(2)
+        };
+        // padding has no force, each real contact goes to the closest foot
+        let real = magnitudes.clone().greater_elem(0.0).float();
+        let on_left = distance_to(CONTACT_COLUMNS.feet[0]).lower_equal(distance_to(CONTACT_COLUMNS.feet[1])).float() * real.clone();
+        let on_right = real - on_left.clone();
+
+        let feet = [on_left, on_right].map(|on_foot| {
+            let weights = magnitudes.clone().sqrt() * on_foot.clone();
+            let total = (vectors.clone() * on_foot.clone()).sum_dim(1);
+            let center = (positions.clone() * weights.clone()).sum_dim(1) / weights.sum_dim(1).clamp_min(f32::EPSILON);
+            Tensor::cat(vec![total, center, on_foot.sum_dim(1)], 2).reshape([batch, 7])
+        });
+        Tensor::cat(feet.to_vec(), 1)
+    }
+
+    // the same layers on every contact, summed over the real ones
+    fn deep_sets(&self, forces: Tensor<B, 3>, magnitudes: Tensor<B, 3>) -> Tensor<B, 2> {
+        let per_contact = self.saved.per_contact.as_ref().expect("deep sets encoders are built with their layers");
+        let [batch, _, _] = forces.dims();
+        let encoded = per_contact.forward(forces.reshape([batch * FORCES_COUNT, Force::VALUES_COUNT]));
+        let width = encoded.dims()[1];
+        let real = magnitudes.greater_elem(0.0).float();
+        (encoded.reshape([batch, FORCES_COUNT, width]) * real).sum_dim(1).reshape([batch, width])
+    }
+}
+
+fn squared<B: Backend>(tensor: Tensor<B, 3>) -> Tensor<B, 3> {
+    tensor.clone() * tensor
+}
+
+impl<B: Backend> ForwardModule<B> for ContactEncoder<B> {
+    /// The encoding of the contacts of a batch of states, `ContactEncoding::len` values per state.
+    fn forward(&self, states: Tensor<B, 2>) -> Tensor<B, 2> {
+        let [batch, _] = states.dims();
+        let forces = Self::forces(&states);
+        let magnitudes = squared(forces.clone().slice([0..batch, 0..FORCES_COUNT, 3..6])).sum_dim(2);
+        match self.encoding {
+            ContactEncoding::Padded => forces.reshape([batch, FORCES_COUNT * Force::VALUES_COUNT]),
+            ContactEncoding::SortedByMagnitude => {
+                Self::sorted_by_magnitude(forces, magnitudes).reshape([batch, FORCES_COUNT * Force::VALUES_COUNT])
+            }
+            ContactEncoding::PerFoot => Self::per_foot(&states, forces, magnitudes),
+            ContactEncoding::DeepSets { .. } => self.deep_sets(forces, magnitudes),
+        }
+    }
+}
+
+impl<B: Backend> Module<B> for ContactEncoder<B> {
+    type Record = <SavedContactEncoder<B> as Module<B>>::Record;
+
+    fn collect_devices(&self, devices: Devices<B>) -> Devices<B> {
+        self.saved.collect_devices(devices)
+    }
+
+    fn fork(self, device: &B::Device) -> Self {
+        Self { saved: self.saved.fork(device), encoding: self.encoding }
+    }
+
+    fn to_device(self, device: &B::Device) -> Self {
+        Self { saved: self.saved.to_device(device), encoding: self.encoding }
+    }
+
+    fn visit<Visitor: ModuleVisitor<B>>(&self, visitor: &mut Visitor) {
+        self.saved.visit(visitor)
+    }
+
+    fn map<Mapper: ModuleMapper<B>>(self, mapper: &mut Mapper) -> Self {
+        Self { saved: self.saved.map(mapper), encoding: self.encoding }
+    }
+
+    // the layers this encoder was built with may not fit the saved encoding, they are built again to its sizes
+    fn load_record(self, record: Self::Record) -> Self {
+        let codes = record.encoding.val().into_data().iter::<i64>().collect::<Vec<_>>();
+        let encoding = encoding_of(&codes);
+        let encoder = encoding.init::<B>(&self.saved.encoding.device());
+        Self { saved: encoder.saved.load_record(record), encoding }
+    }
+
+    fn into_record(self) -> Self::Record {
+        self.saved.into_record()
+    }
+}
+
+impl<B: AutodiffBackend> AutodiffModule<B> for ContactEncoder<B> {
+    type InnerModule = ContactEncoder<B::InnerBackend>;
+
+    fn valid(&self) -> Self::InnerModule {
+        ContactEncoder { saved: self.saved.valid(), encoding: self.encoding.clone() }
+    }
+}
+
+impl<B: Backend> ModuleDisplayDefault for ContactEncoder<B> {
+    fn content(&self, content: Content) -> Option<Content> {
+        self.saved.content(content)
+    }
+}
+
+impl<B: Backend> ModuleDisplay for ContactEncoder<B> {}
+
+impl<B: Backend> fmt::Display for ContactEncoder<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.encoding)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use burn::{
+        backend::NdArray,
+        module::Module,
+        prelude::Tensor,
+        record::{BinFileRecorder, FullPrecisionSettings},
+    };
+    use nalgebra::Vector3;
+
+    use crate::{
+        features::{ContactEncoding, FeatureSelector},
+        models::{builders::WINDOW_SIZE, rs_estimator::RsEstimatorConfig, v_estimator::VEstimatorConfig},
+        modules::forward_module::ForwardModule,
+        simulation::biped::{BipedSim, BipedSimConfig},
+        tensor_conversion::{TensorConvertible, FORCES_COUNT},
+        types::{action::GameAction, state::{Force, GameState}},
+    };
+
+    type B = NdArray;
+
+    fn state_with(forces: Vec<Force>) -> GameState {
+        let mut sim = BipedSim::new(BipedSimConfig::default(), 0);
+        sim.reset();
+        let (mut state, _) = sim.step(&GameAction::default());
+        state.limbs_readings.left.foot.linear_pos = Vector3::new(-1.0, -1.0, 0.0);
+        state.limbs_readings.right.foot.linear_pos = Vector3::new(1.0, -1.0, 0.0);
+        state.sensors_reading.forces = forces;
+        state
+    }
+
+    fn force(x: f32, up: f32) -> Force {
+        Force { pos: Vector3::new(x, -1.0, 0.0), force: Vector3::new(0.0, up, 0.0) }
+    }
+
+    fn encode(encoding: &ContactEncoding, state: &GameState) -> Vec<f32> {
+        let dev = Default::default();
+        let encoder = encoding.init::<B>(&dev);
+        let encoded = encoder.forward(state.to_tensor::<B>(&dev).unsqueeze_dim(0));
+        assert_eq!(encoded.dims(), [1, encoding.len()]);
+        encoded.into_data().to_vec().unwrap()
+    }
+
+    #[test]
+    fn contact_order_doesnt_matter() {
+        let forces = vec![force(-1.2, 1.0), force(0.9, 3.0), force(-0.8, 2.0)];
+        let mut shuffled = forces.clone();
+        shuffled.rotate_left(1);
+        let (state, shuffled) = (state_with(forces), state_with(shuffled));
+
+        assert_ne!(encode(&ContactEncoding::Padded, &state), encode(&ContactEncoding::Padded, &shuffled));
+        for encoding in [ContactEncoding::SortedByMagnitude, ContactEncoding::PerFoot, ContactEncoding::DeepSets { hidden: 8, width: 4 }] {
+            // the deep sets layers are random, the same ones have to encode both
+            let dev = Default::default();
+            let encoder = encoding.init::<B>(&dev);
+            let states = Tensor::stack::<2>(vec![state.to_tensor::<B>(&dev), shuffled.to_tensor(&dev)], 0);
+            let encoded = encoder.forward(states).into_data().to_vec::<f32>().unwrap();
+            let (first, second) = encoded.split_at(encoding.len());
+            assert!(first.iter().zip(second).all(|(a, b)| (a - b).abs() < 1e-5), "{encoding:?}: {first:?} != {second:?}");
+        }
+    }
+
+    #[test]
+    fn strongest_contacts_come_first() {
+        let state = state_with(vec![force(0.0, 1.0), force(0.0, 3.0), force(0.0, 2.0)]);
+        let ups = encode(&ContactEncoding::SortedByMagnitude, &state).chunks(6).map(|force| force[4]).collect::<Vec<_>>();
+        assert_eq!(ups[..4], [3.0, 2.0, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn the_strongest_contacts_make_it_into_the_states() {
+        // more contacts than slots, each with its own strength
+        let forces = (0..FORCES_COUNT + 5).map(|index| force(if index % 2 == 0 { -1.0 } else { 1.0 }, (index * 7 % 25) as f32 + 1.0)).collect::<Vec<_>>();
+        let mut reversed = forces.clone();
+        reversed.reverse();
+        let (state, reversed) = (state_with(forces), state_with(reversed));
+
+        let ups = encode(&ContactEncoding::SortedByMagnitude, &state).chunks(6).map(|force| force[4]).collect::<Vec<_>>();
+        assert_eq!(ups, (6..=25).rev().map(|up| up as f32).collect::<Vec<_>>());
+        for encoding in [ContactEncoding::SortedByMagnitude, ContactEncoding::PerFoot, ContactEncoding::DeepSets { hidden: 8, width: 4 }] {
+            let dev = Default::default();
+            let encoder = encoding.init::<B>(&dev);
+            let states = Tensor::stack::<2>(vec![state.to_tensor::<B>(&dev), reversed.to_tensor(&dev)], 0);
+            let encoded = encoder.forward(states).into_data().to_vec::<f32>().unwrap();
+            let (first, second) = encoded.split_at(encoding.len());
+            assert!(first.iter().zip(second).all(|(a, b)| (a - b).abs() < 1e-4), "{encoding:?}: {first:?} != {second:?}");
+        }
+    }
+
+    #[test]
+    fn contacts_are_summed_per_foot() {
+        let state = state_with(vec![force(-1.25, 1.0), force(1.0, 3.0), force(-0.75, 1.0)]);
+        let feet = encode(&ContactEncoding::PerFoot, &state);
+        // total force, center of pressure, count
+        assert_eq!(feet[..7], [0.0, 2.0, 0.0, -1.0, -1.0, 0.0, 2.0]);
+        assert_eq!(feet[7..], [0.0, 3.0, 0.0, 1.0, -1.0, 0.0, 1.0]);
+
+        assert_eq!(encode(&ContactEncoding::PerFoot, &state_with(Vec::new())), [0.0; 14]);
+    }
+
+    #[test]
+    fn encodings_replace_the_padded_forces() {
+        let dev = Default::default();
+        let config = VEstimatorConfig::new([8, 8], [4], [4], [4])
+            .with_features(FeatureSelector::new().with_exclude(vec!["limbs_readings.*.foot".to_string()]))
+            .with_contacts(ContactEncoding::PerFoot);
+        let padded = config.features.state_indices().unwrap().len();
+        let model = config.init::<B>(&dev);
+        assert_eq!(model.features().len(), padded - FORCES_COUNT * 6);
+        assert_eq!(model.contacts().appended_len(), 14);
+
+        let states = Tensor::<B, 2>::ones([3, GameState::VALUES_COUNT], &dev);
+        assert_eq!(model.forward(&states).dims(), [3]);
+
+        // every state of a window gets its encoding
+        let window = WINDOW_SIZE as usize;
+        let model = RsEstimatorConfig::new([8], [4], [8, 8], [4], [4], [4, 4, 4, 4]).with_contacts(ContactEncoding::PerFoot).init::<B>(&dev);
+        assert_eq!(model.state_normalizer().len(), window * (GameState::VALUES_COUNT - FORCES_COUNT * 6 + 14));
+        let inputs = Tensor::<B, 2>::ones([3, window * (GameState::VALUES_COUNT + GameAction::VALUES_COUNT)], &dev);
+        assert_eq!(ForwardModule::forward(&model, inputs).dims(), [3, GameState::VALUES_COUNT + 1]);
+    }
+
+    #[test]
+    fn encoding_is_saved_with_the_weights() {
+        let dev = Default::default();
+        let config = |contacts| VEstimatorConfig::new([8, 8], [4], [4], [4]).with_contacts(contacts);
+        let model = config(ContactEncoding::PerFoot).init::<B>(&dev);
+
+        let path = std::env::temp_dir().join(format!("contact_encoding_{}", std::process::id()));
+        let recorder = BinFileRecorder::<FullPrecisionSettings>::new();
+        model.clone().save_file(&path, &recorder).unwrap();
+        // whatever the model is built with, the saved encoding is the one used
+        let loaded = config(ContactEncoding::SortedByMagnitude).init::<B>(&dev).load_file(&path, &recorder, &dev).unwrap();
+        std::fs::remove_file(path.with_extension("bin")).unwrap();
+
+        assert_eq!(loaded.contacts().encoding(), &ContactEncoding::PerFoot);
+        let states = Tensor::<B, 2>::ones([2, GameState::VALUES_COUNT], &dev);
+        assert_eq!(loaded.forward(&states).into_data(), model.forward(&states).into_data());
+    }
+
+    #[test]
+    fn deep_sets_layers_come_with_the_record() {
+        let dev = Default::default();
+        let config = |contacts| VEstimatorConfig::new([8, 8], [4], [4], [4]).with_contacts(contacts);
+        let model = config(ContactEncoding::DeepSets { hidden: 8, width: 4 }).init::<B>(&dev);
+
+        let path = std::env::temp_dir().join(format!("deep_sets_encoding_{}", std::process::id()));
+        let recorder = BinFileRecorder::<FullPrecisionSettings>::new();
+        model.clone().save_file(&path, &recorder).unwrap();
+        // built without the layers the record has
+        let loaded = config(ContactEncoding::Padded).init::<B>(&dev).load_file(&path, &recorder, &dev).unwrap();
+        std::fs::remove_file(path.with_extension("bin")).unwrap();
+
+        assert_eq!(loaded.contacts().encoding(), &ContactEncoding::DeepSets { hidden: 8, width: 4 });
+        let states = Tensor::<B, 2>::ones([2, GameState::VALUES_COUNT], &dev);
+        assert_eq!(loaded.forward(&states).into_data(), model.forward(&states).into_data());
+    }
+}
@@ -0,0 +1,39 @@
+use burn::{
+    module::{Module, Param, ParamId},
+    prelude::{Backend, Int, Tensor},
+};
+
+use super::forward_module::ForwardModule;
+
+/// Keeps some columns of its input and drops the others. The columns are part of the record, so a model loaded from
+/// a file projects its input like the model that was saved.
+#[derive(Debug, Module)]
+pub struct FeatureProjection<B: Backend> {
+    indices: Param<Tensor<B, 1, Int>>,
+}
+
+impl<B: Backend> FeatureProjection<B> {
+    pub fn new(indices: &[usize], dev: &<B as Backend>::Device) -> Self {
+        let indices = indices.iter().map(|&index| index as i64).collect::<Vec<_>>();
+        Self { indices: Param::initialized(ParamId::new(), Tensor::from_ints(indices.as_slice(), dev)) }
+    }
+
+    /// How many columns come out.
+    pub fn len(&self) -> usize {
+        self.indices.dims()[0]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn indices(&self) -> Vec<usize> {
+        self.indices.val().into_data().iter::<i64>().map(|index| index as usize).collect()
+    }
+}
+
+impl<B: Backend> ForwardModule<B> for FeatureProjection<B> {
+    fn forward(&self, input: Tensor<B, 2>) -> Tensor<B, 2> {
+        input.select(1, self.indices.val())
+    }
+}
@@ -1,2 +1,5 @@
 pub mod sequential;
 pub mod forward_module;
+pub mod feature_projection;
+pub mod contact_encoder;
+pub mod running_normalizer;
\ No newline at end of file
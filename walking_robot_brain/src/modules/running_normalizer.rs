@@ -0,0 +1,233 @@
+use burn::{
+    config::Config,
+    module::{Module, RunningState},
+    prelude::{Backend, Tensor},
+};
+
+use super::forward_module::ForwardModule;
+
+#[derive(Config, Debug)]
+pub struct RunningNormalizerConfig {
+    // normalized values are clipped to [-clip, clip]
+    #[config(default = 5.0)]
+    pub clip    : f32,
+    // added to the variance so that constant columns don't blow up
+    #[config(default = 1e-8)]
+    pub epsilon : f32,
+}
+
+impl RunningNormalizerConfig {
+    pub fn init<B: Backend>(&self, len: usize, dev: &<B as Backend>::Device) -> RunningNormalizer<B> {
+        let constant = |value: f32| RunningState::new(Tensor::full([len], value, dev));
+        RunningNormalizer {
+            count   : constant(0.0),
+            mean    : constant(0.0),
+            variance: constant(1.0),
+            clip    : self.clip,
+            epsilon : self.epsilon,
+        }
+    }
+}
+
+/// Shifts and scales every column to zero mean and unit variance, with the mean and variance of all the values it
+/// observed. A column that hasn't observed anything yet is passed through as it is. The statistics are running state
+/// like that of a `BatchNorm`: part of the record, so they follow the weights when the model is saved, but not
+/// parameters the optimizers could move.
+#[derive(Debug, Module)]
+pub struct RunningNormalizer<B: Backend> {
+    // how many finite values each column has seen
+    count   : RunningState<Tensor<B, 1>>,
+    mean    : RunningState<Tensor<B, 1>>,
+    variance: RunningState<Tensor<B, 1>>,
+    clip    : f32,
+    epsilon : f32,
+}
+
+impl<B: Backend> RunningNormalizer<B> {
+    pub fn len(&self) -> usize {
+        self.mean.value().dims()[0]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn count(&self) -> Vec<f32> {
+        self.count.value().into_data().to_vec().unwrap()
+    }
+
+    pub fn mean(&self) -> Vec<f32> {
+        self.mean.value().into_data().to_vec().unwrap()
+    }
+
+    pub fn variance(&self) -> Vec<f32> {
+        self.variance.value().into_data().to_vec().unwrap()
+    }
+
+    /// Merges the rows of `values` into the statistics. Values that aren't finite are not counted, they are clipped
+    /// like the others when normalized.
+    pub fn observe(self, values: Tensor<B, 2>) -> Self {
+        let [_, len] = values.dims();
+        assert_eq!(len, self.len(), "observed values don't match the normalizer");
+        let values = values.into_data().to_vec::<f32>().unwrap();
+        let (mut count, mut mean, mut variance) = (self.count(), self.mean(), self.variance());
+
+        for column in 0..len {
+            let finite = values.iter().skip(column).step_by(len).copied().filter(|value| value.is_finite());
+            let (batch_count, batch_mean, batch_m2) = finite.fold((0.0f64, 0.0f64, 0.0f64), |(n, mean, m2), value| {
+                let n = n + 1.0;
+                let delta = value as f64 - mean;
+                let mean = mean + delta / n;
+                (n, mean, m2 + delta * (value as f64 - mean))
+            });
+            if batch_count == 0.0 {
+                continue;
+            }
+            // Chan et al., merging the moments of the batch into those seen so far
+            let seen = count[column] as f64;
+            let total = seen + batch_count;
+            let delta = batch_mean - mean[column] as f64;
+            let seen_m2 = if seen > 0.0 { variance[column] as f64 * seen } else { 0.0 };
+            let m2 = seen_m2 + batch_m2 + delta * delta * seen * batch_count / total;
+            count[column] = total as f32;
+            mean[column] = (mean[column] as f64 + delta * batch_count / total) as f32;
+            variance[column] = (m2 / total) as f32;
+        }
+
+        let dev = self.mean.value().device();
+        let replaced = |values: Vec<f32>| RunningState::new(Tensor::from_floats(values.as_slice(), &dev));
+        Self {
+            count   : replaced(count),
+            mean    : replaced(mean),
+            variance: replaced(variance),
+            ..self
+        }
+    }
+
+    fn mean_and_deviation(&self) -> (Tensor<B, 2>, Tensor<B, 2>) {
+        let mean = self.mean.value().unsqueeze_dim(0);
+        let deviation = (self.variance.value() + self.epsilon).sqrt().unsqueeze_dim(0);
+        (mean, deviation)
+    }
+
+    pub fn normalize(&self, values: Tensor<B, 2>) -> Tensor<B, 2> {
+        let [batch, _] = values.dims();
+        let (mean, deviation) = self.mean_and_deviation();
+        let normalized = ((values.clone() - mean) / deviation).clamp(-self.clip, self.clip);
+        // without statistics there is no telling what is out of range
+        let seen = self.count.value().greater_elem(0.0).unsqueeze_dim::<2>(0).repeat_dim(0, batch);
+        values.mask_where(seen, normalized)
+    }
+
+    /// Back from the normalized values to the observed ones, for outputs trained on normalized targets.
+    pub fn denormalize(&self, values: Tensor<B, 2>) -> Tensor<B, 2> {
+        let (mean, deviation) = self.mean_and_deviation();
+        values * deviation + mean
+    }
+}
+
+impl<B: Backend> ForwardModule<B> for RunningNormalizer<B> {
+    fn forward(&self, input: Tensor<B, 2>) -> Tensor<B, 2> {
+        self.normalize(input)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use burn::{
+        backend::{Autodiff, NdArray},
+        module::{AutodiffModule, Module},
+        optim::{GradientsParams, Optimizer, SgdConfig},
+        prelude::Tensor,
+        record::{BinFileRecorder, FullPrecisionSettings},
+    };
+
+    use crate::{models::a_selector::ASelectorConfig, tensor_conversion::TensorConvertible, types::state::GameState};
+
+    use super::RunningNormalizerConfig;
+
+    type B = NdArray;
+
+    fn close(actual: &[f32], expected: &[f32]) -> bool {
+        actual.iter().zip(expected).all(|(a, e)| (a - e).abs() < 1e-4)
+    }
+
+    #[test]
+    fn batches_add_up_to_the_whole() {
+        let dev = Default::default();
+        let rows = [[1.0, 10.0], [2.0, 20.0], [3.0, 60.0], [6.0, f32::INFINITY], [8.0, 10.0]];
+        let whole = RunningNormalizerConfig::new().init::<B>(2, &dev).observe(Tensor::from_floats(rows, &dev));
+        let batched = RunningNormalizerConfig::new()
+            .init::<B>(2, &dev)
+            .observe(Tensor::from_floats([rows[0], rows[1]], &dev))
+            .observe(Tensor::from_floats([rows[2], rows[3], rows[4]], &dev));
+
+        // the infinite value is left out of the second column
+        assert_eq!(whole.count(), [5.0, 4.0]);
+        assert!(close(&whole.mean(), &[4.0, 25.0]));
+        assert!(close(&whole.variance(), &[6.8, 425.0]));
+        assert_eq!(batched.count(), whole.count());
+        assert!(close(&batched.mean(), &whole.mean()));
+        assert!(close(&batched.variance(), &whole.variance()));
+    }
+
+    #[test]
+    fn normalized_values_are_clipped() {
+        let dev = Default::default();
+        let normalizer = RunningNormalizerConfig::new()
+            .with_clip(2.0)
+            .init::<B>(1, &dev)
+            .observe(Tensor::from_floats([[1.0], [3.0]], &dev));
+        let normalized = normalizer.normalize(Tensor::from_floats([[2.0], [3.0], [100.0], [f32::INFINITY]], &dev));
+        assert!(close(&normalized.clone().into_data().to_vec::<f32>().unwrap(), &[0.0, 1.0, 2.0, 2.0]));
+        let back = normalizer.denormalize(Tensor::from_floats([[0.0], [1.0]], &dev));
+        assert!(close(&back.into_data().to_vec::<f32>().unwrap(), &[2.0, 3.0]));
+    }
+
+    #[test]
+    fn unobserved_columns_are_not_clipped() {
+        let dev = Default::default();
+        let normalizer = RunningNormalizerConfig::new().init::<B>(2, &dev);
+        let raw = Tensor::<B, 2>::from_floats([[12.0, -300.0], [0.5, 9.81]], &dev);
+        assert_eq!(normalizer.normalize(raw.clone()).into_data(), raw.clone().into_data());
+
+        // only the columns that have seen values are scaled
+        let normalizer = normalizer.observe(Tensor::from_floats([[1.0, f32::NAN], [3.0, f32::NAN]], &dev));
+        let normalized = normalizer.normalize(raw).into_data().to_vec::<f32>().unwrap();
+        assert!(close(&normalized, &[5.0, -300.0, -1.5, 9.81]), "{normalized:?}");
+    }
+
+    #[test]
+    fn optimizers_leave_the_statistics_alone() {
+        type A = Autodiff<NdArray>;
+        let dev = Default::default();
+        let normalizer = RunningNormalizerConfig::new()
+            .init::<A>(2, &dev)
+            .observe(Tensor::from_floats([[1.0, 2.0], [3.0, 6.0]], &dev));
+        let mean = normalizer.mean();
+        let loss = normalizer.normalize(Tensor::from_floats([[5.0, 5.0]], &dev)).sum();
+        let grads = GradientsParams::from_grads(loss.backward(), &normalizer);
+        let normalizer = SgdConfig::new().init().step(1.0, normalizer, grads);
+        assert_eq!(normalizer.mean(), mean);
+        assert_eq!(normalizer.valid().mean(), mean);
+    }
+
+    #[test]
+    fn statistics_are_saved_with_the_weights() {
+        let dev = Default::default();
+        let config = ASelectorConfig::new([4; 4], [4; 3], [4; 2], [4; 4]);
+        let range = burn::tensor::Distribution::Uniform(-3.0, 3.0);
+        let states = Tensor::<B, 2>::random([6, GameState::VALUES_COUNT], range, &dev);
+        let model = config.init::<B>(&dev).observe(&states);
+
+        let path = std::env::temp_dir().join(format!("running_normalizer_{}", std::process::id()));
+        let recorder = BinFileRecorder::<FullPrecisionSettings>::new();
+        model.clone().save_file(&path, &recorder).unwrap();
+        let loaded = config.init::<B>(&dev).load_file(&path, &recorder, &dev).unwrap();
+        std::fs::remove_file(path.with_extension("bin")).unwrap();
+
+        assert_eq!(loaded.normalizer().count(), vec![6.0; loaded.normalizer().len()]);
+        assert_eq!(loaded.normalizer().mean(), model.normalizer().mean());
+        assert_eq!(loaded.forward(&states).into_data(), model.forward(&states).into_data());
+    }
+}
@@ -1,3 +1,4 @@
 pub mod train;
 pub mod run_simulation;
-pub mod sa_tree_expansion;
\ No newline at end of file
+pub mod sa_tree_expansion;
+pub mod returns;
\ No newline at end of file
@@ -0,0 +1,184 @@
+use burn::{prelude::Backend, tensor::Tensor};
+
+use crate::types::history::TensorHistory;
+
+/// Returns and advantages of the steps of a history, a row per step, on the device of the history. The sums are
+/// worked out on the host in a pass over the steps, so in time linear in them. Nothing is carried over a terminal
+/// step. The state a history stopped at isn't part of it, so a history that doesn't end on a terminal step is taken to
+/// be worth nothing past its last step.
+///
+/// The `values` are those of the states of the history, as `VEstimator::forward` gives them.
+impl<B: Backend> TensorHistory<B> {
+    /// Sum of the rewards from each step on, discounted by `gamma`.
+    pub fn monte_carlo_returns(&self, gamma: f32) -> Tensor<B, 2> {
+        self.discounted_sums(self.rewards.clone(), gamma, None)
+    }
+
+    /// The rewards of the next `n` steps, then the discounted value of the state reached, unless the episode ended
+    /// before.
+    pub fn n_step_returns(&self, values: &Tensor<B, 1>, gamma: f32, n: usize) -> Tensor<B, 2> {
+        assert!(n > 0, "n-step returns need at least one step");
+        let steps = self.len();
+        let rewards = self.discounted_sums(self.rewards.clone(), gamma, Some(n));
+        if n >= steps {
+            return rewards;
+        }
+        let dev = self.rewards.device();
+        let episodes = self.episodes_before();
+        // the value and the episode of the state `n` steps later, past the end is worth nothing
+        let later = |column: Tensor<B, 2>| Tensor::cat(vec![column.slice([n..steps, 0..1]), Tensor::zeros([n, 1], &dev)], 0);
+        let reached = later(values.clone().unsqueeze_dim(1));
+        let same_episode = later(episodes.clone()).equal(episodes).float();
+        rewards + reached * same_episode * gamma.powi(n as i32)
+    }
+
+    /// Generalized advantage estimation, the temporal differences discounted by `gamma * lambda`.
+    pub fn gae_advantages(&self, values: &Tensor<B, 1>, gamma: f32, lambda: f32) -> Tensor<B, 2> {
+        let steps = self.len();
+        let values = values.clone().unsqueeze_dim(1);
+        if steps == 0 {
+            return values;
+        }
+        let next_values = Tensor::cat(vec![values.clone().slice([1..steps, 0..1]), Tensor::zeros([1, 1], &values.device())], 0);
+        let continues = self.terminals.clone().neg().add_scalar(1.0);
+        let differences = self.rewards.clone() + next_values * continues * gamma - values;
+        self.discounted_sums(differences, gamma * lambda, None)
+    }
+
+    /// TD(λ) returns, the advantages of `gae_advantages` on top of the values.
+    pub fn lambda_returns(&self, values: &Tensor<B, 1>, gamma: f32, lambda: f32) -> Tensor<B, 2> {
+        self.gae_advantages(values, gamma, lambda) + values.clone().unsqueeze_dim(1)
+    }
+
+    fn len(&self) -> usize {
+        self.rewards.dims()[0]
+    }
+
+    fn terminal_steps(&self) -> Vec<bool> {
+        self.terminals.clone().into_data().convert::<f32>().to_vec::<f32>().unwrap().into_iter().map(|terminal| terminal > 0.5).collect()
+    }
+
+    // how many terminal steps come before each step, two steps are of the same episode when they have as many
+    fn episodes_before(&self) -> Tensor<B, 2> {
+        let episodes = self
+            .terminal_steps()
+            .into_iter()
+            .scan(0.0, |before, terminal| {
+                let episode = *before;
+                *before += if terminal { 1.0 } else { 0.0 };
+                Some(episode)
+            })
+            .collect::<Vec<f32>>();
+        Tensor::<B, 1>::from_floats(episodes.as_slice(), &self.terminals.device()).unsqueeze_dim(1)
+    }
+
+    // each row the sum of `values` from its step to the end of its episode, or over `horizon` steps, discounted by
+    // `factor`. Going backwards each step adds to the sum of the next one, with a horizon the sums are cut at `horizon`
+    // steps instead
+    fn discounted_sums(&self, values: Tensor<B, 2>, factor: f32, horizon: Option<usize>) -> Tensor<B, 2> {
+        let [steps, columns] = values.dims();
+        let dev = values.device();
+        let values = values.into_data().convert::<f32>().to_vec::<f32>().unwrap();
+        let terminals = self.terminal_steps();
+        let mut sums = vec![0.0; steps * columns];
+        match horizon.filter(|&horizon| horizon < steps) {
+            None => {
+                for step in (0..steps).rev() {
+                    for column in 0..columns {
+                        let next = if step + 1 < steps && !terminals[step] { sums[(step + 1) * columns + column] } else { 0.0 };
+                        sums[step * columns + column] = values[step * columns + column] + factor * next;
+                    }
+                }
+            }
+            Some(horizon) => {
+                for step in 0..steps {
+                    let mut discount = 1.0;
+                    for later in step..(step + horizon).min(steps) {
+                        for column in 0..columns {
+                            sums[step * columns + column] += discount * values[later * columns + column];
+                        }
+                        if terminals[later] {
+                            break;
+                        }
+                        discount *= factor;
+                    }
+                }
+            }
+        }
+        Tensor::<B, 1>::from_floats(sums.as_slice(), &dev).reshape([steps, columns])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use burn::{backend::NdArray, prelude::Tensor};
+
+    use crate::types::history::TensorHistory;
+
+    type B = NdArray;
+
+    // two episodes, the first ends on its third step and the second is cut after two
+    fn history() -> TensorHistory<B> {
+        let dev = Default::default();
+        let column = |values: [f32; 5]| Tensor::<B, 1>::from_floats(values, &dev).unsqueeze_dim(1);
+        TensorHistory {
+            states      : Tensor::zeros([5, 1], &dev),
+            actions     : Tensor::zeros([5, 1], &dev),
+            rewards     : column([1.0, 2.0, 4.0, 8.0, 16.0]),
+            terminals   : column([0.0, 0.0, 1.0, 0.0, 0.0]),
+        }
+    }
+
+    fn values() -> Tensor<B, 1> {
+        Tensor::from_floats([10.0, 20.0, 30.0, 40.0, 50.0], &Default::default())
+    }
+
+    fn assert_close(actual: Tensor<B, 2>, expected: [f32; 5]) {
+        let actual = actual.into_data().to_vec::<f32>().unwrap();
+        assert!(actual.iter().zip(expected).all(|(a, e)| (a - e).abs() < 1e-4), "{actual:?} != {expected:?}");
+    }
+
+    #[test]
+    fn returns_stop_at_terminals() {
+        let history = history();
+        assert_close(history.monte_carlo_returns(0.5), [1.0 + 1.0 + 1.0, 2.0 + 2.0, 4.0, 8.0 + 8.0, 16.0]);
+        assert_close(history.monte_carlo_returns(1.0), [7.0, 6.0, 4.0, 24.0, 16.0]);
+    }
+
+    #[test]
+    fn n_step_returns_bootstrap_within_the_episode() {
+        let history = history();
+        // the value two steps later, unless a terminal or the end comes first
+        assert_close(history.n_step_returns(&values(), 0.5, 2), [1.0 + 1.0 + 0.25 * 30.0, 2.0 + 2.0, 4.0, 8.0 + 8.0, 16.0]);
+        assert_close(history.n_step_returns(&values(), 0.5, 1), [1.0 + 10.0, 2.0 + 15.0, 4.0, 8.0 + 25.0, 16.0]);
+        assert_close(history.n_step_returns(&values(), 0.5, 10), history.monte_carlo_returns(0.5).into_data().to_vec::<f32>().unwrap().try_into().unwrap());
+    }
+
+    #[test]
+    fn lambda_spans_td_to_monte_carlo() {
+        let history = history();
+        let td = history.n_step_returns(&values(), 0.5, 1).into_data().to_vec::<f32>().unwrap();
+        let monte_carlo = history.monte_carlo_returns(0.5).into_data().to_vec::<f32>().unwrap();
+        assert_close(history.lambda_returns(&values(), 0.5, 0.0), td.try_into().unwrap());
+        assert_close(history.lambda_returns(&values(), 0.5, 1.0), monte_carlo.clone().try_into().unwrap());
+
+        let values = values().into_data().to_vec::<f32>().unwrap();
+        let advantages = monte_carlo.iter().zip(&values).map(|(g, v)| g - v).collect::<Vec<_>>();
+        assert_close(history.gae_advantages(&self::values(), 0.5, 1.0), advantages.try_into().unwrap());
+    }
+
+    #[test]
+    fn empty_histories_have_no_returns() {
+        let dev = Default::default();
+        let history = TensorHistory::<B> {
+            states      : Tensor::zeros([0, 1], &dev),
+            actions     : Tensor::zeros([0, 1], &dev),
+            rewards     : Tensor::zeros([0, 1], &dev),
+            terminals   : Tensor::zeros([0, 1], &dev),
+        };
+        let values = Tensor::<B, 1>::zeros([0], &dev);
+        assert_eq!(history.monte_carlo_returns(0.5).dims(), [0, 1]);
+        assert_eq!(history.n_step_returns(&values, 0.5, 2).dims(), [0, 1]);
+        assert_eq!(history.lambda_returns(&values, 0.5, 0.9).dims(), [0, 1]);
+    }
+}
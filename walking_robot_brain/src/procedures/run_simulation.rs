@@ -1,55 +1,178 @@
-use std::time::Duration;
+use std::future::Future;
 
-use tokio::time::timeout;
-use tracing::{debug, info, trace};
+use burn::prelude::Backend;
+use futures::future::join_all;
+use itertools::Itertools;
+use tracing::{debug, info, trace, warn};
 
-use crate::{comm::SimulationEndpoint, types::{history::History, policy::Policy, state::GameUpdate}};
+use crate::{
+	tensor_conversion::{TensorConvertible, TensorConvertibleIterExts},
+	types::{action::GameAction, environment::{Environment, EnvironmentError}, history::History, policy::{Policy, TensorPolicy}},
+};
 
-impl SimulationEndpoint{
-	pub async fn run_episode<'this, 'state>(&'this mut self,  policy: &mut impl Policy) -> History{
+pub trait RunEpisodeExt: Environment{
+	fn run_episode(&mut self, policy: &mut impl Policy) -> impl Future<Output = Result<History, Self::Error>>;
+}
+
+impl<E: Environment> RunEpisodeExt for E{
+	async fn run_episode(&mut self, policy: &mut impl Policy) -> Result<History, E::Error>{
         info!("Running episode");
         let mut history = History::default();
 
-        let (mut previous_state, _previous_reward) = 'WAIT_FIRST_STATE: loop  {match self.recv_sim_update().await {
-            GameUpdate::GameStarted => {continue 'WAIT_FIRST_STATE;},
-            GameUpdate::GameStep  {state: previous_state, reward}  => break 'WAIT_FIRST_STATE (previous_state, reward) ,
-        }};
+        let mut previous_state = self.reset().await?;
 
 	    debug!("picking action");
         let mut previous_action = Default::default();
 
-	    debug!("sending action");
-        self.send_action(&previous_action).await;
-
         'SIMULATION_LOOP: loop{
+	        debug!("sending action");
             debug!("waiting for update");
-            let (state, reward) = match timeout(Duration::from_secs_f32(1.0), self.recv_sim_update()).await {
-                Ok(GameUpdate::GameStarted) => {
-                   break 'SIMULATION_LOOP;
-                },
-                Ok(GameUpdate::GameStep  {state: previous_state, reward})  => (previous_state, reward) ,
-                Err(_) => {
-                    self.send_action(&Default::default()).await;
-                    continue 'SIMULATION_LOOP
+            let (state, reward, flags) = match self.step(&previous_action).await {
+                Ok(step) => step,
+                Err(err) if err.is_disconnect() => {
+                    warn!("environment disconnected in the middle of the episode: {err}");
+                    history.truncated = true;
+                    break 'SIMULATION_LOOP;
                 },
+                Err(err) => return Err(err),
             };
+            if flags.truncated {
+                history.truncated = true;
+                break 'SIMULATION_LOOP;
+            }
 
             history.states.push( previous_state);
             history.actions.push( previous_action);
             history.rewards.push( reward);
+            history.terminals.push( flags.terminal);
+            if flags.terminal {
+                history.success = flags.success;
+                break 'SIMULATION_LOOP;
+            }
 
             debug!("picking action");
             let action = policy.select_action(&state);
             trace!("action is: {action:?}");
 
-	        debug!("sending action");
-            self.send_action(&action).await;
-
             previous_action = action;
             previous_state = state;
-            // simulation_loop += 1;
-      
 		}
-		history
+		Ok(history)
+	}
+}
+
+/// Runs one episode in each environment at the same time. The environments are stepped together, and the states of
+/// those still running go through a single `select_action_tensor` call per tick.
+pub trait RunEpisodesBatchedExt{
+	type Error;
+	fn run_episodes_batched<B: Backend>(
+		&mut self, 
+		policy	: &mut impl TensorPolicy<B>, 
+		dev		: &<B as Backend>::Device
+	) -> impl Future<Output = Result<Vec<History>, Self::Error>>;
+}
+
+impl<E: Environment> RunEpisodesBatchedExt for [E]{
+	type Error = E::Error;
+
+	async fn run_episodes_batched<B: Backend>(
+		&mut self, 
+		policy	: &mut impl TensorPolicy<B>, 
+		dev		: &<B as Backend>::Device
+	) -> Result<Vec<History>, E::Error>{
+		info!("Running {} episodes side by side", self.len());
+		let mut histories = self.iter().map(|_| History::default()).collect_vec();
+
+		// `None` once the episode of that environment is over
+		let mut states = join_all(self.iter_mut().map(|env| env.reset()))
+			.await
+			.into_iter()
+			.map(|state| state.map(Some))
+			.collect::<Result<Vec<_>, _>>()?;
+		let mut actions = self.iter().map(|_| GameAction::default()).collect_vec();
+
+		loop {
+			let running = states.iter().positions(Option::is_some).collect_vec();
+			debug!("stepping {} environments", running.len());
+			let steps = join_all(
+				self.iter_mut()
+					.zip(&states)
+					.zip(&actions)
+					.filter(|((_, state), _)| state.is_some())
+					.map(|((env, _), action)| env.step(action))
+			).await;
+
+			for (i, step) in running.into_iter().zip(steps) {
+				let (state, reward, flags) = match step {
+					Ok(step) => step,
+					Err(err) if err.is_disconnect() => {
+						warn!("environment {i} disconnected in the middle of the episode: {err}");
+						histories[i].truncated = true;
+						states[i] = None;
+						continue;
+					},
+					Err(err) => return Err(err),
+				};
+				if flags.truncated {
+					histories[i].truncated = true;
+					states[i] = None;
+					continue;
+				}
+				histories[i].states.push(states[i].replace(state).unwrap());
+				histories[i].actions.push(std::mem::take(&mut actions[i]));
+				histories[i].rewards.push(reward);
+				histories[i].terminals.push(flags.terminal);
+				if flags.terminal {
+					histories[i].success = flags.success;
+					states[i] = None;
+				}
+			}
+
+			let running = states.iter().positions(Option::is_some).collect_vec();
+			if running.is_empty() {
+				break;
+			}
+			debug!("picking actions for {} environments", running.len());
+			let states_tensor = running.iter().map(|&i| states[i].as_ref().unwrap()).many_to_tensor(dev);
+			let new_actions = GameAction::many_from_tensor(policy.select_action_tensor(states_tensor));
+			for (i, action) in running.into_iter().zip(new_actions) {
+				trace!("action for environment {i} is: {action:?}");
+				actions[i] = action;
+			}
+		}
+		Ok(histories)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use burn::{backend::NdArray, prelude::Tensor};
+
+	use crate::{
+		simulation::biped::{BipedSim, BipedSimConfig},
+		tensor_conversion::TensorConvertible,
+		types::{action::GameAction, policy::{nil_policy::NilPolicy, TensorFnPolicy}},
+	};
+
+	use super::{RunEpisodeExt, RunEpisodesBatchedExt};
+
+	#[tokio::test]
+	async fn batched_episodes_pick_actions_once_per_tick() {
+		let configs = [0.5, 1.0, 1.0].map(|episode_duration| BipedSimConfig { episode_duration, ..Default::default() });
+		let mut sims = configs.iter().enumerate().map(|(seed, config)| BipedSim::new(config.clone(), seed as u64)).collect::<Vec<_>>();
+
+		let mut batch_sizes = Vec::new();
+		let mut policy = TensorFnPolicy(|states: Tensor<NdArray, 2>| {
+			batch_sizes.push(states.dims()[0]);
+			Tensor::zeros([states.dims()[0], GameAction::VALUES_COUNT], &states.device())
+		});
+		let histories = sims.run_episodes_batched(&mut policy, &Default::default()).await.unwrap();
+
+		assert_eq!(batch_sizes, [3, 3, 3, 3, 2, 2, 2, 2, 2]);
+		for ((history, config), seed) in histories.iter().zip(&configs).zip(0..) {
+			let expected = BipedSim::new(config.clone(), seed).run_episode(&mut NilPolicy).await.unwrap();
+			assert_eq!(history.states, expected.states);
+			assert_eq!(history.rewards, expected.rewards);
+		}
 	}
-}
\ No newline at end of file
+}
@@ -1,34 +1,46 @@
-use burn::{nn::loss::{ Reduction}, optim::{GradientsParams, Optimizer}, prelude::Backend, tensor::backend::AutodiffBackend};
+use burn::{optim::{GradientsParams, Optimizer}, prelude::Backend, tensor::backend::AutodiffBackend};
 use tracing::info;
 
-use crate::{loss::LossMod, models::a_selector::ASelector, tensor_conversion::TensorConvertibleIterExts, types::history::History};
+use crate::{loss::LossMod, models::a_selector::ASelector, tensor_conversion::TensorConvertibleIterExts, types::{history::History, replay_buffer::ReplayBatch}};
+
+use super::{execute_training::sample_errors, trainer::{Trainer, TrainingReport}};
 
 
 impl<B: AutodiffBackend> ASelector<B>{
 	pub fn train_from_history(
-		mut self,  
+		self,  
 		history	: &History,
+		trainer	: &mut Trainer,
 
 		optim	: &mut impl Optimizer<ASelector<B>, B>,
 		loss_mod 	: &mut LossMod,
 	
 		lr		: f64,
 		dev  	: &<B as Backend>::Device, 
-	) -> Self {
-		let count = history.states.len();
+	) -> (Self, TrainingReport) {
         info!("training a_selector from history");
 		let states = history.states.iter().many_to_tensor(dev);
 		let target_outputs = history.actions.iter().many_to_tensor(dev);		
-		for _ in 0..count/10{
-			let pred_outputs = self.forward(&states);
-
-			let loss = loss_mod.forward_no_reduction(pred_outputs.clone(), target_outputs.clone());
-			let red_loss = loss_mod.forward(pred_outputs, target_outputs.clone(), Reduction::Auto);
-			// inform_loss( red_loss.clone());
-			let grads = GradientsParams::from_grads(loss.backward(), &self);
-			self = optim.step(lr, self, grads);
-		}
-		self
+		trainer.fit(self, states, target_outputs, loss_mod, optim, lr)
+	}
+	/// One step towards the actions of the transitions, from the states they were taken in. Also returns the error of
+	/// each transition, see `sample_errors`.
+	pub fn train_from_batch(
+		self,  
+		batch	: &ReplayBatch<B>,
+		optim	: &mut impl Optimizer<ASelector<B>, B>,
+		loss_mod: &mut LossMod,
+		lr		: f64,
+	) -> (Self, Vec<f32>) {
+        info!("training a_selector from a batch of {}", batch.len());
+		let pred_outputs = self.forward(&batch.last_states());
+		let sample_errors = sample_errors(pred_outputs.clone(), batch.last_actions());
+		let loss = loss_mod.forward_no_reduction(pred_outputs, batch.last_actions());
+		let loss = match &batch.weights {
+			Some(weights) => loss * weights.clone(),
+			None => loss,
+		};
+		let grads = GradientsParams::from_grads(loss.backward(), &self);
+		(optim.step(lr, self, grads), sample_errors)
 	}
 }
-
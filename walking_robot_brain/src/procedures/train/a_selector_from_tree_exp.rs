@@ -1,14 +1,17 @@
-use burn::{nn::loss::{Reduction}, optim::{GradientsParams, Optimizer}, prelude::Backend, tensor::{backend::AutodiffBackend, Tensor}};
+use burn::{optim::Optimizer, prelude::Backend, tensor::{backend::AutodiffBackend, Tensor}};
 use rand::rng;
 use tracing::info;
 
 use crate::{loss::LossMod, models::{a_selector::ASelector, rs_estimator::RsEstimator, v_estimator::VEstimator}, procedures::sa_tree_expansion::TreeExpander, tensor_conversion::TensorConvertibleIterExts, tools::UsedInTrait, types::{history::History, policy::noisy_policy::NoisyPolicy}};
 
+use super::trainer::{Trainer, TrainingReport};
+
 
 impl<B: AutodiffBackend> ASelector<B>{
 	pub fn train_from_tree_exp(
-		mut self,  
+		self,  
 		history	: &History,
+		trainer	: &mut Trainer,
 		rs_estimator: &RsEstimator<B>,
 		v_estimator : &VEstimator<B>, 
 		optim	: &mut impl Optimizer<ASelector<B>, B>,
@@ -21,9 +24,8 @@ impl<B: AutodiffBackend> ASelector<B>{
 		alpha	: f32,
 		lr		: f64,
 		dev  	: &<B as Backend>::Device, 
-	) -> Self {
+	) -> (Self, TrainingReport) {
 		info!("training a_selector from tree_expansion");
-		let count = history.states.len();
 		let states = history.states.iter().many_to_tensor(dev);
 
 
@@ -48,17 +50,7 @@ impl<B: AutodiffBackend> ASelector<B>{
 				.detach()
 		};
 
-		for _ in 0..count/10{
-			let pred_outputs = self.forward(&states);
-
-			let loss = loss_mod.forward_no_reduction(pred_outputs.clone(), target_outputs.clone());
-			let red_loss = loss_mod.forward(pred_outputs, target_outputs.clone(), Reduction::Auto);
-			// inform_loss(red_loss.clone());
-			let grads = GradientsParams::from_grads(loss.backward(), &self);
-			self = optim.step(lr, self, grads);
-		}
-
-		self	
+		trainer.fit(self, states, target_outputs, loss_mod, optim, lr)
 	}
 }
 
@@ -7,13 +7,27 @@ use tracing::{debug, info};
 use crate::{ loss::LossMod, modules::forward_module::ForwardModule, tensor_conversion::TensorConvertible};
 
 pub fn execute_training<B: Backend + AutodiffBackend, M: AutodiffModule<B> + ForwardModule<B>>(
-    mut module		: M,
+    module			: M,
     input			: Tensor<B, 2>,
     target_output	: Tensor<B, 2>,
     loss_mod		: &mut LossMod,
     optim			: &mut impl Optimizer<M, B>,
 	lr				: f64,
 ) -> M {
+	execute_weighted_training(module, input, target_output, None, loss_mod, optim, lr).0
+}
+
+/// Like `execute_training` with the loss of each row scaled by its weight, when there are some. Also returns the
+/// error of each row before the step, see `sample_errors`, to prioritize replayed transitions with.
+pub fn execute_weighted_training<B: Backend + AutodiffBackend, M: AutodiffModule<B> + ForwardModule<B>>(
+    mut module		: M,
+    input			: Tensor<B, 2>,
+    target_output	: Tensor<B, 2>,
+    weights			: Option<Tensor<B, 2>>,
+    loss_mod		: &mut LossMod,
+    optim			: &mut impl Optimizer<M, B>,
+	lr				: f64,
+) -> (M, Vec<f32>) {
 	debug!("input is: {input}", );
 	debug!("target output is: {target_output}", );
 	let pred_out = module.forward(input.clone());
@@ -21,9 +35,14 @@ pub fn execute_training<B: Backend + AutodiffBackend, M: AutodiffModule<B> + For
 
 	let loss = loss_mod.forward_no_reduction(pred_out.clone(), target_output.clone());	
 	let red_loss = loss.clone().mean();
+	let sample_errors = sample_errors(pred_out, target_output.clone());
 
 	info!("mean loss before training is {}", f32::from_tensor(red_loss));
 
+	let loss = match weights {
+		Some(weights) => loss * weights,
+		None => loss,
+	};
 	let grads = GradientsParams::from_grads(loss.backward(), &module);
 
 	module = optim.step(lr, module, grads);
@@ -32,5 +51,26 @@ pub fn execute_training<B: Backend + AutodiffBackend, M: AutodiffModule<B> + For
 	let new_loss  = loss_mod.forward(new_pred_out, target_output.clone(), Reduction::Mean);	
 	info!("mean loss after training is {}", f32::from_tensor(new_loss));
 
-	module
+	(module, sample_errors)
+}
+
+/// The mean absolute difference of each row to its target. Not the loss, which is already squared and would be raised
+/// to the power of the priorities twice.
+pub fn sample_errors<B: Backend>(pred_out: Tensor<B, 2>, target_output: Tensor<B, 2>) -> Vec<f32> {
+	(pred_out.detach() - target_output.detach()).abs().mean_dim(1).into_data().to_vec::<f32>().unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use burn::{backend::NdArray, prelude::Tensor};
+
+    use super::sample_errors;
+
+    #[test]
+    fn errors_are_not_squared() {
+        let dev = Default::default();
+        let pred = Tensor::<NdArray, 2>::from_floats([[1.0, -3.0], [0.5, 0.5]], &dev);
+        let target = Tensor::<NdArray, 2>::zeros([2, 2], &dev);
+        assert_eq!(sample_errors(pred, target), [2.0, 0.5]);
+    }
 }
@@ -8,5 +8,7 @@ pub mod a_selector_from_tree_exp;
 pub mod execute_training;
 pub mod s_endec_train;
 pub mod q_estimator_monte_carlo;
+pub mod q_estimator_td;
+pub mod trainer;
 
 
@@ -1,42 +1,36 @@
 use burn::{optim::Optimizer, prelude::Backend, tensor::{backend::AutodiffBackend, Tensor}};
 
-use crate::{loss::LossMod, models::{builders::WINDOW_SIZE, q_estimator::QEstimator}, tools::WindowsExt, types::history::{History, TensorHistory}};
+use crate::{loss::LossMod, models::{builders::WINDOW_SIZE, q_estimator::QEstimator}, tools::WindowsExt, types::history::TensorHistory};
 
-use super::execute_training::{self, execute_training};
+use super::trainer::{Trainer, TrainingReport};
 
 impl<B: AutodiffBackend> QEstimator<B>{
+	/// Fits the windows of a history to the discounted rewards that follow them, see `Trainer::fit`.
 	pub fn train_monte_carlo(
-		mut self,  
+		self,  
 		history		: &TensorHistory<B>,
+		trainer		: &mut Trainer,
 		alpha		: f32,
 		lr			: f64,
 		optim		: &mut impl Optimizer<Self, B>,
 		loss_mod 	: &mut LossMod,
-		dev  		: &<B as Backend>::Device, 
-	) -> Self{
-		let inputs =
-			Tensor::cat(
-				vec![
-					history.states.clone().windows(WINDOW_SIZE),
-					history.actions.clone().windows(WINDOW_SIZE)
-				],
-				1
-			);
+	) -> (Self, TrainingReport){
+		let inputs = windowed_inputs(history);
 
-		let target_output = {
-			let mut values = Vec::new();
-			let mut value: Tensor<B, 2> = Tensor::zeros([1,1], dev);
-			let mut tensor_rewards =  history.rewards.clone().iter_dim(0).collect::<Vec<_>>();
-			tensor_rewards.reverse();
-			for r in tensor_rewards{
-				value = value.mul_scalar(alpha) + r;
-				values.push(value.clone());
-			}
-			values.reverse();
-			let count = values.len();
-			Tensor::cat(values, 0).slice([(WINDOW_SIZE - 1, count as i64)])
-		};
+		let count = history.rewards.dims()[0];
+		let target_output = history.monte_carlo_returns(alpha).slice([WINDOW_SIZE as usize - 1..count, 0..1]);
 
-		execute_training(self, inputs, target_output, loss_mod, optim, lr)
+		trainer.fit(self, inputs, target_output, loss_mod, optim, lr)
 	}
+}
+
+/// The windows of states and actions of a history, laid out like the inputs of the `QEstimator`.
+pub fn windowed_inputs<B: Backend>(history: &TensorHistory<B>) -> Tensor<B, 2> {
+	Tensor::cat(
+		vec![
+			history.states.clone().windows(WINDOW_SIZE),
+			history.actions.clone().windows(WINDOW_SIZE)
+		],
+		1
+	)
 }
\ No newline at end of file
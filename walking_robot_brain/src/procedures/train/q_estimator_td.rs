@@ -0,0 +1,25 @@
+use burn::{optim::Optimizer, tensor::backend::AutodiffBackend};
+
+use crate::{loss::LossMod, models::q_estimator::QEstimator, modules::forward_module::ForwardModule, types::replay_buffer::ReplayBatch};
+
+use super::execute_training::execute_weighted_training;
+
+impl<B: AutodiffBackend> QEstimator<B>{
+	/// One step towards the reward of each transition plus the discounted value `target` gives its next window. Also
+	/// returns the error of each transition, see `execute_weighted_training`.
+	pub fn train_td(
+		self,  
+		batch		: &ReplayBatch<B>,
+		target		: &QEstimator<B>,
+		alpha		: f32,
+		lr			: f64,
+		optim		: &mut impl Optimizer<Self, B>,
+		loss_mod 	: &mut LossMod,
+	) -> (Self, Vec<f32>){
+		// nothing comes after a terminal state
+		let next_values = target.forward(batch.next_inputs()).detach() * batch.dones.clone().neg().add_scalar(1.0);
+		let target_output = batch.rewards.clone() + next_values.mul_scalar(alpha);
+
+		execute_weighted_training(self, batch.inputs(), target_output, batch.weights.clone(), loss_mod, optim, lr)
+	}
+}
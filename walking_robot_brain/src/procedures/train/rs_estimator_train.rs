@@ -1,33 +1,61 @@
 use burn::{nn::loss::{HuberLoss}, optim::Optimizer, prelude::Backend, tensor::{backend::AutodiffBackend, Tensor}};
 use tracing::info;
 
-use crate::{loss::LossMod, models::{builders::WINDOW_SIZE, rs_estimator::RsEstimator}, procedures::train::execute_training::execute_training, tensor_conversion::TensorConvertibleIterExts, tools::WindowsExt, types::history::History};
+use crate::{loss::LossMod, models::{builders::WINDOW_SIZE, rs_estimator::RsEstimator}, procedures::train::execute_training::execute_weighted_training, tensor_conversion::TensorConvertibleIterExts, tools::WindowsExt, types::{history::{History, TensorHistory}, replay_buffer::ReplayBatch}};
+
+use super::trainer::{Trainer, TrainingReport};
 
 impl<B: AutodiffBackend> RsEstimator<B>{
+	/// Fits the windows of all the histories at once, see `Trainer::fit`.
 	pub fn train(
-		mut self,  
-		states 		: &Tensor<B,2>,
-		actions		: &Tensor<B,2>,
-		rewards		: &Tensor<B,2>,
-		lr			: f64,
+		self,  
+		histories	: &[TensorHistory<B>],
+		trainer		: &mut Trainer,
+
 		optim		: &mut impl Optimizer<RsEstimator<B>, B>,
 		loss_mod 	: &mut LossMod,
-		dev  		: &<B as Backend>::Device, 
-	) -> Self {
-        info!("training rs_estimator ");
-		let count =  states.dims()[0] as i64;
-		let stacked_states = states.clone().slice([Some((0, count-1)),None]).windows(WINDOW_SIZE);
-		let stacked_actions = actions.clone().slice([Some((0, count-1)),None]).windows(WINDOW_SIZE);
 
-		let input_tensor = Tensor::cat(vec![stacked_states, stacked_actions], 1);
+		lr			: f64,
+	) -> (Self, TrainingReport) {
+        info!("training rs_estimator on {} histories", histories.len());
+		// a history needs a whole window and the state after it to be learnt from
+		let (inputs, targets): (Vec<_>, Vec<_>) = histories
+			.iter()
+			.filter(|history| history.states.dims()[0] > WINDOW_SIZE as usize)
+			.map(|history| training_windows(&history.states, &history.actions, &history.rewards))
+			.unzip();
+		if inputs.is_empty() {
+			return (self, TrainingReport::default());
+		}
+		let target_output_tensor = self.scaled_targets(Tensor::cat(targets, 0));
+		trainer.fit(self, Tensor::cat(inputs, 0), target_output_tensor, loss_mod, optim, lr)
+	}
+	/// One step like `train` on transitions of a `ReplayBuffer`, sampled with `sample_continuing` or from a buffer that is
+	/// `continuing_only` since terminal ones have no next state to learn.
+	pub fn train_batch(
+		self,  
+		batch 		: &ReplayBatch<B>,
+		lr			: f64,
+		optim		: &mut impl Optimizer<RsEstimator<B>, B>,
+		loss_mod 	: &mut LossMod,
+	) -> (Self, Vec<f32>) {
+        info!("training rs_estimator on a batch of {}", batch.len());
+		let target_output_tensor = self.scaled_targets(batch.rs_targets());
+		execute_weighted_training(self, batch.inputs(), target_output_tensor, batch.weights.clone(), loss_mod, optim, lr)
+	}
+}
 
-		let output_states = states.clone().slice([Some((WINDOW_SIZE , count)), None]);
-		let rewards_tensor = rewards.clone().slice([Some((WINDOW_SIZE - 1, count-1)), None]);
+/// The windows of states and actions of a history, and the reward and state that follow each of them.
+pub fn training_windows<B: Backend>(states: &Tensor<B,2>, actions: &Tensor<B,2>, rewards: &Tensor<B,2>) -> (Tensor<B,2>, Tensor<B,2>) {
+	let count =  states.dims()[0] as i64;
+	let stacked_states = states.clone().slice([Some((0, count-1)),None]).windows(WINDOW_SIZE);
+	let stacked_actions = actions.clone().slice([Some((0, count-1)),None]).windows(WINDOW_SIZE);
 
-		let target_output_tensor = Tensor::cat(vec![rewards_tensor, output_states], 1);
+	let input_tensor = Tensor::cat(vec![stacked_states, stacked_actions], 1);
 
-		self = execute_training(self, input_tensor, target_output_tensor, loss_mod, optim, lr);
+	let output_states = states.clone().slice([Some((WINDOW_SIZE , count)), None]);
+	let rewards_tensor = rewards.clone().slice([Some((WINDOW_SIZE - 1, count-1)), None]);
 
-		self
-	}
+	let target_output_tensor = Tensor::cat(vec![rewards_tensor, output_states], 1);
+	(input_tensor, target_output_tensor)
 }
@@ -0,0 +1,192 @@
+use burn::{
+    config::Config,
+    module::AutodiffModule,
+    nn::loss::Reduction,
+    optim::{GradientsParams, Optimizer},
+    tensor::{backend::AutodiffBackend, Int, Tensor},
+};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use tracing::info;
+
+use crate::{loss::LossMod, modules::forward_module::ForwardModule, tensor_conversion::TensorConvertible};
+
+#[derive(Config, Debug)]
+pub struct TrainerConfig {
+    #[config(default = 10)]
+    pub epochs          : usize,
+    #[config(default = 64)]
+    pub batch_size      : usize,
+    // share of the rows held out to measure the loss on, never trained on
+    #[config(default = 0.1)]
+    pub validation_split: f32,
+    // epochs without improvement before stopping, the validation loss when there is one, never stops without
+    #[config(default = "None")]
+    pub patience        : Option<usize>,
+    // how much lower the loss has to get to count as an improvement
+    #[config(default = 0.0)]
+    pub min_improvement : f32,
+    // go back to the module of the best epoch at the end
+    #[config(default = true)]
+    pub restore_best    : bool,
+    #[config(default = "None")]
+    pub seed            : Option<u64>,
+}
+
+impl TrainerConfig {
+    pub fn init(&self) -> Trainer {
+        let rng = match self.seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_rng(&mut rand::rng()),
+        };
+        Trainer { config: self.clone(), rng }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug)]
+pub struct EpochLosses {
+    // mean over the training rows, as they were trained on during the epoch
+    pub train       : f32,
+    // after the epoch, `None` when there are no validation rows
+    pub validation  : Option<f32>,
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct TrainingReport {
+    pub epochs      : Vec<EpochLosses>,
+    // the epoch with the lowest loss, the module it ended with is the one returned when `restore_best` is set
+    pub best_epoch  : Option<usize>,
+    pub stopped_early: bool,
+}
+
+/// Trains a module on rows of inputs and targets, for epochs of shuffled minibatches, one optimizer step per
+/// minibatch.
+pub struct Trainer {
+    config  : TrainerConfig,
+    rng     : StdRng,
+}
+
+impl Trainer {
+    pub fn config(&self) -> &TrainerConfig {
+        &self.config
+    }
+
+    pub fn fit<B, M>(
+        &mut self,
+        mut module      : M,
+        inputs          : Tensor<B, 2>,
+        targets         : Tensor<B, 2>,
+        loss_mod        : &mut LossMod,
+        optim           : &mut impl Optimizer<M, B>,
+        lr              : f64,
+    ) -> (M, TrainingReport)
+    where
+        B: AutodiffBackend,
+        M: AutodiffModule<B> + ForwardModule<B>,
+        M::InnerModule: ForwardModule<B::InnerBackend>,
+    {
+        let rows = inputs.dims()[0];
+        assert_eq!(rows, targets.dims()[0], "as many targets as inputs");
+        let dev = inputs.device();
+        let select = |tensor: &Tensor<B, 2>, indices: &[usize]| {
+            let indices = indices.iter().map(|&index| index as i64).collect::<Vec<_>>();
+            tensor.clone().select(0, Tensor::<B, 1, Int>::from_ints(indices.as_slice(), &dev))
+        };
+
+        let mut indices = (0..rows).collect::<Vec<_>>();
+        indices.shuffle(&mut self.rng);
+        // at least one row is trained on
+        let held_out = ((rows as f32 * self.config.validation_split).round() as usize).min(rows.saturating_sub(1));
+        let (validation, mut training) = (indices[..held_out].to_vec(), indices[held_out..].to_vec());
+        let validation = (!validation.is_empty()).then(|| (select(&inputs, &validation).inner(), select(&targets, &validation).inner()));
+
+        let mut report = TrainingReport::default();
+        let mut best: Option<(f32, M)> = None;
+        let mut since_best = 0;
+        for epoch in 0..self.config.epochs {
+            training.shuffle(&mut self.rng);
+            let mut train_loss = 0.0;
+            for batch in training.chunks(self.config.batch_size.max(1)) {
+                let output = module.forward(select(&inputs, batch));
+                let loss = loss_mod.forward(output, select(&targets, batch), Reduction::Mean);
+                train_loss += f32::from_tensor(loss.clone().detach()) * batch.len() as f32;
+                let grads = GradientsParams::from_grads(loss.backward(), &module);
+                module = optim.step(lr, module, grads);
+            }
+            let train = train_loss / training.len().max(1) as f32;
+            let validation = validation.as_ref().map(|(inputs, targets)| {
+                let output = module.valid().forward(inputs.clone());
+                f32::from_tensor(loss_mod.forward(output, targets.clone(), Reduction::Mean))
+            });
+            info!("epoch {epoch}: train loss {train}, validation loss {validation:?}");
+            report.epochs.push(EpochLosses { train, validation });
+
+            let loss = validation.unwrap_or(train);
+            if best.as_ref().is_none_or(|(best, _)| loss < best - self.config.min_improvement) {
+                best = Some((loss, module.clone()));
+                report.best_epoch = Some(epoch);
+                since_best = 0;
+            } else {
+                since_best += 1;
+            }
+            if self.config.patience.is_some_and(|patience| since_best >= patience) {
+                info!("no improvement for {since_best} epochs, stopping");
+                report.stopped_early = true;
+                break;
+            }
+        }
+
+        if let (true, Some((_, best))) = (self.config.restore_best, best) {
+            module = best;
+        }
+        (module, report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use burn::{
+        backend::{Autodiff, NdArray},
+        nn::{loss::MseLoss, LinearConfig},
+        optim::{AdamConfig, SgdConfig},
+        prelude::Tensor,
+        tensor::Distribution,
+    };
+
+    use super::TrainerConfig;
+
+    type B = Autodiff<NdArray>;
+
+    // y = 2x - 1
+    fn line(rows: usize) -> (Tensor<B, 2>, Tensor<B, 2>) {
+        let inputs = Tensor::<B, 2>::random([rows, 1], Distribution::Uniform(-1.0, 1.0), &Default::default());
+        let targets = inputs.clone() * 2.0 - 1.0;
+        (inputs, targets)
+    }
+
+    #[test]
+    fn epochs_bring_both_losses_down() {
+        let dev = Default::default();
+        let (inputs, targets) = line(200);
+        let mut trainer = TrainerConfig::new().with_epochs(30).with_batch_size(16).with_validation_split(0.25).with_seed(Some(1)).init();
+        let (_, report) = trainer.fit(LinearConfig::new(1, 1).init::<B>(&dev), inputs, targets, &mut MseLoss::new(), &mut AdamConfig::new().init(), 0.05);
+
+        assert_eq!(report.epochs.len(), 30);
+        let (first, last) = (&report.epochs[0], report.epochs.last().unwrap());
+        assert!(last.train < first.train / 10.0, "{report:?}");
+        assert!(last.validation.unwrap() < first.validation.unwrap() / 10.0, "{report:?}");
+        assert!(!report.stopped_early);
+    }
+
+    #[test]
+    fn stops_when_the_validation_loss_stalls() {
+        let dev = Default::default();
+        let (inputs, targets) = line(50);
+        let mut trainer = TrainerConfig::new().with_epochs(30).with_patience(Some(3)).init();
+        // nothing is learnt with a null learning rate
+        let (_, report) = trainer.fit(LinearConfig::new(1, 1).init::<B>(&dev), inputs, targets, &mut MseLoss::new(), &mut SgdConfig::new().init(), 0.0);
+
+        assert!(report.stopped_early);
+        assert_eq!(report.epochs.len(), 4);
+        assert_eq!(report.best_epoch, Some(0));
+    }
+}
@@ -3,7 +3,7 @@ use burn::{nn::loss::{HuberLoss}, optim::Optimizer, prelude::Backend, tensor::ba
 use rand::seq::IndexedRandom;
 use tracing::info;
 
-use crate::{loss::LossMod, models::{rs_estimator::RsEstimator, v_estimator::VEstimator}, procedures::{sa_tree_expansion::TreeExpander, train::execute_training::execute_training}, tensor_conversion::TensorConvertibleIterExts, types::{history::History, policy::MultiActionTensorPolicy}};
+use crate::{loss::LossMod, models::{rs_estimator::RsEstimator, v_estimator::VEstimator}, procedures::{sa_tree_expansion::TreeExpander, train::execute_training::{execute_training, execute_weighted_training}}, tensor_conversion::TensorConvertibleIterExts, types::{history::History, policy::MultiActionTensorPolicy, replay_buffer::ReplayBatch}};
 
 
 impl<B: AutodiffBackend> VEstimator<B>{
@@ -27,9 +27,11 @@ impl<B: AutodiffBackend> VEstimator<B>{
 			let mut expander = TreeExpander::new(rs_estimator, &self, policy, alpha);
 			let trees = expander.expand_states_tensor(states_tensor.clone(), expansion_depth, expansion_breadth);
 
+			// the estimators know nothing about the target, a step that really reached it is worth its reward alone
 			trees
 				.into_iter()
-				.map( |(mut frontier, _tree)| frontier.take_best().1)
+				.zip(history.rewards.iter().zip(&history.terminals))
+				.map( |((mut frontier, _tree), (&reward, &terminal))| if terminal { reward } else { frontier.take_best().1 })
 				.collect::<Vec<_>>()
 				.iter()
 				.many_to_tensor(dev)
@@ -37,4 +39,20 @@ impl<B: AutodiffBackend> VEstimator<B>{
 		self = execute_training(self, states_tensor, target_output, loss_mod, optim, lr);
 		self
 	}
-}
\ No newline at end of file
+	/// One step towards the reward of each transition plus the discounted value of the state it reached, as the
+	/// estimator sees it now. Also returns the error of each transition.
+	pub fn td_train_batch(
+		self,  
+		batch		: &ReplayBatch<B>,
+		alpha 		: f32,
+		lr			: f64,
+		optim		: &mut impl Optimizer<VEstimator<B>, B>,
+		loss_mod 	: &mut LossMod,
+	) -> (Self, Vec<f32>) {
+		info!("training v_estimator with td on a batch of {}", batch.len());
+		// nothing comes after a terminal state
+		let next_values = self.forward(&batch.reached_states()).detach().unsqueeze_dim(1) * batch.dones.clone().neg().add_scalar(1.0);
+		let target_output = batch.rewards.clone() + next_values.mul_scalar(alpha);
+		execute_weighted_training(self, batch.last_states(), target_output, batch.weights.clone(), loss_mod, optim, lr)
+	}
+}
@@ -0,0 +1,257 @@
+use std::{
+    io::{self, Read, Write},
+    time::Duration,
+};
+
+use tokio::{
+    net::{
+        tcp::{OwnedReadHalf, OwnedWriteHalf},
+        TcpStream,
+    },
+    sync::mpsc::{unbounded_channel, UnboundedSender},
+    task::JoinSet,
+    time::Instant,
+};
+use tracing::{info, warn};
+
+use crate::{
+    comm::{recv_frame, send_frame, MAX_FRAME_LEN},
+    error::SimulationError,
+    types::environment::EnvironmentError,
+};
+
+/// First bytes of every session file.
+pub const SESSION_MAGIC: &[u8; 8] = b"WRBREC01";
+
+/// Which way a frame went.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Direction {
+    ToBrain,
+    ToSimulation,
+}
+
+/// One framed message as it went through the connection, without its length prefix.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct RecordedFrame {
+    // since the session started
+    pub elapsed     : Duration,
+    pub direction   : Direction,
+    pub payload     : Vec<u8>,
+}
+
+/// Writes a session file: the magic, then for every frame its time in microseconds, its direction and its
+/// length-prefixed payload, all big-endian like the frames themselves.
+pub struct SessionWriter<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> SessionWriter<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(SESSION_MAGIC)?;
+        Ok(Self { writer })
+    }
+
+    pub fn write_frame(&mut self, frame: &RecordedFrame) -> io::Result<()> {
+        let direction: u8 = match frame.direction {
+            Direction::ToBrain => 0,
+            Direction::ToSimulation => 1,
+        };
+        self.writer.write_all(&(frame.elapsed.as_micros() as u64).to_be_bytes())?;
+        self.writer.write_all(&[direction])?;
+        self.writer.write_all(&(frame.payload.len() as u64).to_be_bytes())?;
+        self.writer.write_all(&frame.payload)
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+/// Reads back what a `SessionWriter` wrote, one frame at a time.
+pub struct SessionReader<R: Read> {
+    reader: R,
+}
+
+impl<R: Read> SessionReader<R> {
+    pub fn new(mut reader: R) -> io::Result<Self> {
+        let mut magic = [0_u8; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != SESSION_MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a session file"));
+        }
+        Ok(Self { reader })
+    }
+
+    fn read_frame(&mut self) -> io::Result<Option<RecordedFrame>> {
+        let mut elapsed = [0_u8; 8];
+        // the session may end anywhere between two frames, but not inside one
+        match self.reader.read_exact(&mut elapsed) {
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+            res => res?,
+        }
+        let mut direction = [0_u8; 1];
+        self.reader.read_exact(&mut direction)?;
+        let direction = match direction[0] {
+            0 => Direction::ToBrain,
+            1 => Direction::ToSimulation,
+            other => return Err(io::Error::new(io::ErrorKind::InvalidData, format!("unknown direction {other}"))),
+        };
+        let mut len = [0_u8; 8];
+        self.reader.read_exact(&mut len)?;
+        let len = u64::from_be_bytes(len);
+        if len > MAX_FRAME_LEN as u64 {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("frame of {len} bytes is larger than the {MAX_FRAME_LEN} bytes allowed")));
+        }
+        let mut payload = vec![0_u8; len as usize];
+        self.reader.read_exact(&mut payload)?;
+
+        Ok(Some(RecordedFrame {
+            elapsed: Duration::from_micros(u64::from_be_bytes(elapsed)),
+            direction,
+            payload,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for SessionReader<R> {
+    type Item = io::Result<RecordedFrame>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+/// Passes frames between the simulation and the brain untouched, writing each of them to `session`. Returns once
+/// either side disconnects.
+pub async fn relay<W: Write>(
+    simulation  : TcpStream,
+    brain       : TcpStream,
+    session     : &mut SessionWriter<W>,
+) -> Result<(), SimulationError> {
+    let (simulation_read, simulation_write) = simulation.into_split();
+    let (brain_read, brain_write) = brain.into_split();
+    let (frames_tx, mut frames_rx) = unbounded_channel();
+    let start = Instant::now();
+
+    let mut pumps = JoinSet::new();
+    pumps.spawn(pump(simulation_read, brain_write, Direction::ToBrain, frames_tx.clone(), start));
+    pumps.spawn(pump(brain_read, simulation_write, Direction::ToSimulation, frames_tx, start));
+
+    loop {
+        tokio::select! {
+            Some(frame) = frames_rx.recv() => session.write_frame(&frame)?,
+            Some(ended) = pumps.join_next() => {
+                pumps.abort_all();
+                while let Ok(frame) = frames_rx.try_recv() {
+                    session.write_frame(&frame)?;
+                }
+                session.flush()?;
+                return ended.expect("relay pumps don't panic");
+            }
+        }
+    }
+}
+
+// forwards frames one way until that side hangs up
+async fn pump(
+    mut from    : OwnedReadHalf,
+    mut to      : OwnedWriteHalf,
+    direction   : Direction,
+    frames      : UnboundedSender<RecordedFrame>,
+    start       : Instant,
+) -> Result<(), SimulationError> {
+    loop {
+        let payload = match recv_frame(&mut from).await {
+            Ok(payload) => payload,
+            Err(err) if err.is_disconnect() => {
+                info!("{direction:?} side closed: {err}");
+                return Ok(());
+            }
+            Err(err) => return Err(err),
+        };
+        // recorded before it's forwarded, so that the answer can't be recorded first
+        let frame = RecordedFrame { elapsed: start.elapsed(), direction, payload };
+        let _ = frames.send(frame.clone());
+        if let Err(err) = send_frame(&mut to, &frame.payload).await {
+            warn!("could not forward a frame {direction:?}: {err}");
+            return if err.is_disconnect() { Ok(()) } else { Err(err) };
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, net::SocketAddr, str::from_utf8, time::Duration};
+
+    use tokio::net::TcpListener;
+
+    use crate::{
+        comm::{connect_retrying, SimulationConnector},
+        procedures::run_simulation::RunEpisodeExt,
+        simulation::{biped::BipedSimConfig, mock::{MockSimulator, MockSimulatorConfig}},
+        types::policy::nil_policy::NilPolicy,
+    };
+
+    use super::{relay, Direction, RecordedFrame, SessionReader, SessionWriter, SESSION_MAGIC};
+
+    #[test]
+    fn session_files_read_back() {
+        let frames = [
+            RecordedFrame { elapsed: Duration::from_micros(3), direction: Direction::ToBrain, payload: b"{}".to_vec() },
+            RecordedFrame { elapsed: Duration::from_millis(5), direction: Direction::ToSimulation, payload: Vec::new() },
+        ];
+        let mut writer = SessionWriter::new(Vec::new()).unwrap();
+        for frame in &frames {
+            writer.write_frame(frame).unwrap();
+        }
+        let bytes = writer.into_inner();
+
+        let read = SessionReader::new(bytes.as_slice()).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(read, frames);
+        // a frame cut in the middle is an error, not the end of the session
+        assert!(SessionReader::new(&bytes[..bytes.len() - 3]).unwrap().any(|frame| frame.is_err()));
+
+        // so is a length no frame could have
+        let mut corrupt = bytes.clone();
+        corrupt[SESSION_MAGIC.len() + 9..SESSION_MAGIC.len() + 17].copy_from_slice(&u64::MAX.to_be_bytes());
+        let err = SessionReader::new(corrupt.as_slice()).unwrap().next().unwrap().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[tokio::test]
+    async fn relay_records_both_directions() {
+        let simulation_addr = SocketAddr::from(([127, 0, 0, 1], 18091));
+        let brain_addr = SocketAddr::from(([127, 0, 0, 1], 18092));
+        let sim = BipedSimConfig { episode_duration: 1.0, ..Default::default() };
+        let steps = sim.steps_per_episode();
+
+        let proxy = tokio::spawn(async move {
+            let listener = TcpListener::bind(simulation_addr).await.unwrap();
+            let simulation = listener.accept().await.unwrap().0;
+            let brain = connect_retrying(brain_addr).await;
+            let mut session = SessionWriter::new(Vec::new()).unwrap();
+            relay(simulation, brain, &mut session).await.unwrap();
+            session.into_inner()
+        });
+        let connection = tokio::spawn(SimulationConnector::new().with_addr(brain_addr).connect());
+        let config = MockSimulatorConfig { addr: simulation_addr, sim, ..Default::default() };
+        tokio::spawn(async move { MockSimulator::connect(config).await.unwrap().run(Some(1)).await });
+
+        let mut simulation = connection.await.unwrap().unwrap();
+        simulation.run_episode(&mut NilPolicy).await.unwrap();
+        drop(simulation);
+
+        let frames = SessionReader::new(proxy.await.unwrap().as_slice()).unwrap().collect::<Result<Vec<_>, _>>().unwrap();
+        let to_brain = frames.iter().filter(|frame| frame.direction == Direction::ToBrain).count();
+        let to_simulation = frames.iter().filter(|frame| frame.direction == Direction::ToSimulation).count();
+        // hello, episode start, the states and episode end; the accept and one action per state
+        assert_eq!(to_brain, steps + 3);
+        assert_eq!(to_simulation, steps + 1);
+        assert!(from_utf8(&frames[0].payload).unwrap().contains("Hello"));
+        assert!(frames.windows(2).all(|pair| pair[0].elapsed <= pair[1].elapsed));
+    }
+}
@@ -0,0 +1,158 @@
+use std::{collections::HashMap, sync::LazyLock};
+
+use crate::{
+    tensor_conversion::TensorConvertible,
+    types::{action::GameAction, state::GameState},
+};
+
+/// One column of the tensors a `TensorConvertible` type turns into.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SchemaEntry {
+    // dotted path of the value, like `limbs_readings.left.shin.motor.speed`
+    pub name    : String,
+    pub offset  : usize,
+    // the path of what holds the value, like `limbs_readings.left.shin.motor`
+    pub group   : String,
+}
+
+/// Names of the values of a `TensorConvertible` type, so that columns can be told apart without counting through
+/// `iterate_values` by hand.
+#[derive(Clone, Debug)]
+pub struct ObservationSchema {
+    entries : Vec<SchemaEntry>,
+    offsets : HashMap<String, usize>,
+}
+
+static GAME_STATE_SCHEMA: LazyLock<ObservationSchema> = LazyLock::new(ObservationSchema::of::<GameState>);
+static GAME_ACTION_SCHEMA: LazyLock<ObservationSchema> = LazyLock::new(ObservationSchema::of::<GameAction>);
+
+impl ObservationSchema {
+    pub fn of<T: TensorConvertible>() -> Self {
+        let mut names = Vec::with_capacity(T::VALUES_COUNT);
+        T::value_names("", &mut names);
+        assert_eq!(names.len(), T::VALUES_COUNT, "value names don't match the values");
+
+        let entries = names
+            .into_iter()
+            .enumerate()
+            .map(|(offset, name)| SchemaEntry { group: group_of(&name).to_string(), name, offset })
+            .collect::<Vec<_>>();
+        let offsets = entries.iter().map(|entry| (entry.name.clone(), entry.offset)).collect();
+        Self { entries, offsets }
+    }
+
+    pub fn game_state() -> &'static Self {
+        &GAME_STATE_SCHEMA
+    }
+
+    pub fn game_action() -> &'static Self {
+        &GAME_ACTION_SCHEMA
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn entries(&self) -> &[SchemaEntry] {
+        &self.entries
+    }
+
+    pub fn index_of(&self, name: &str) -> Option<usize> {
+        self.offsets.get(name).copied()
+    }
+
+    pub fn name_of(&self, index: usize) -> Option<&str> {
+        self.entries.get(index).map(|entry| entry.name.as_str())
+    }
+
+    /// Offsets of every value under `path`, `limbs_readings.left` or `sensors_reading.forces[0]` for instance.
+    pub fn indices_under<'a>(&'a self, path: &'a str) -> impl Iterator<Item = usize> + 'a {
+        self.entries
+            .iter()
+            .filter(move |entry| is_under(&entry.name, path))
+            .map(|entry| entry.offset)
+    }
+
+    /// The groups in the order they first appear.
+    pub fn groups(&self) -> Vec<&str> {
+        let mut groups = Vec::<&str>::new();
+        for entry in &self.entries {
+            if groups.last() != Some(&entry.group.as_str()) {
+                groups.push(&entry.group);
+            }
+        }
+        groups
+    }
+
+    /// Pairs `values`, one row of a tensor, with the names of its columns.
+    pub fn labelled<'a>(&'a self, values: &'a [f32]) -> impl Iterator<Item = (&'a str, f32)> + 'a {
+        self.entries.iter().map(|entry| entry.name.as_str()).zip(values.iter().copied())
+    }
+}
+
+fn group_of(name: &str) -> &str {
+    name.rsplit_once('.').map_or("", |(group, _)| group)
+}
+
+fn is_under(name: &str, path: &str) -> bool {
+    path.is_empty() || name.strip_prefix(path).is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '[']))
+}
+
+#[cfg(test)]
+mod test {
+    use itertools::Itertools;
+
+    use crate::{
+        simulation::biped::{BipedSim, BipedSimConfig},
+        tensor_conversion::{TensorConvertible, FORCES_COUNT},
+        types::{action::GameAction, state::GameState},
+    };
+
+    use super::ObservationSchema;
+
+    #[test]
+    fn every_value_has_its_own_name() {
+        let schema = ObservationSchema::game_state();
+        assert_eq!(schema.len(), GameState::VALUES_COUNT);
+        assert!(schema.entries().iter().map(|entry| &entry.name).all_unique());
+        for (index, entry) in schema.entries().iter().enumerate() {
+            assert_eq!(schema.index_of(&entry.name), Some(index));
+            assert_eq!(schema.name_of(index), Some(entry.name.as_str()));
+        }
+    }
+
+    #[test]
+    fn names_point_at_their_values() {
+        let mut sim = BipedSim::new(BipedSimConfig::default(), 0);
+        sim.reset();
+        let (mut state, _) = sim.step(&GameAction::default());
+        state.limbs_readings.left.shin.motor.speed = 1234.0;
+        state.sensors_reading.acc_reading.up.z = -1234.0;
+
+        let schema = ObservationSchema::game_state();
+        let values = state.iterate_values().collect_vec();
+        assert_eq!(values[schema.index_of("limbs_readings.left.shin.motor.speed").unwrap()], 1234.0);
+        assert_eq!(values[schema.index_of("sensors_reading.acc_reading.up.z").unwrap()], -1234.0);
+        assert_eq!(schema.index_of("sensors_reading.target_pos.x"), Some(0));
+        assert_eq!(schema.name_of(GameState::VALUES_COUNT), None);
+    }
+
+    #[test]
+    fn values_can_be_looked_up_by_group() {
+        let schema = ObservationSchema::game_state();
+        let forces = format!("sensors_reading.forces[{}]", FORCES_COUNT - 1);
+        assert_eq!(schema.indices_under(&forces).count(), 6);
+        assert_eq!(schema.indices_under("limbs_readings.left").count(), schema.indices_under("limbs_readings.right").count());
+        // `forces[1]` is not under `forces[10]` nor the other way around
+        assert_eq!(schema.indices_under("sensors_reading.forces[1]").count(), 6);
+        assert!(schema.groups().contains(&"limbs_readings.right.foot.angular_pos"));
+
+        let action = ObservationSchema::game_action();
+        assert_eq!(action.name_of(4), Some("limbs_activation.right.thigh_activation"));
+        assert_eq!(action.entries()[4].group, "limbs_activation.right");
+    }
+}
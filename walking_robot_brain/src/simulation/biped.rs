@@ -0,0 +1,690 @@
+use std::{convert::Infallible, f32::consts::FRAC_PI_2, future::{ready, Future}};
+
+use nalgebra::{Rotation2, UnitQuaternion, Vector2, Vector3};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::types::{
+    action::{GameAction, LimbActivation},
+    environment::Environment,
+    state::{
+        AccelerometerReading, BipedalLimbsReading, Force, GameState, LimbReading, LinkReading, MotorReading, Reward, SensorsReading, StepFlags, TransformReading
+    },
+};
+
+/// Simplified planar biped. The torso is a rigid body moving in the sagittal plane (x forward, y up), the legs are
+/// light chains of shoulder, thigh and shin links ending in a foot, each joint driven by a rate limited motor like
+/// Motor.cs. Ground contacts are springs with Coulomb friction, and their reactions move the torso.
+#[derive(Clone, Debug)]
+pub struct BipedSimConfig {
+    pub physics_delta_time  : f32,
+    pub fixed_delta_time    : f32,
+    pub control_period      : f32,
+    pub episode_duration    : f32,
+
+    pub gravity             : f32,
+    pub torso_mass          : f32,
+    pub torso_half_size     : Vector2<f32>,
+    pub hip_width           : f32,
+    // shoulder, thigh, shin
+    pub link_lengths        : [f32; 3],
+    pub foot_back           : f32,
+    pub foot_front          : f32,
+
+    pub motor_max_angles    : [f32; 3],
+    pub motor_jerk          : f32,
+    pub motor_stiffness     : f32,
+    pub motor_damping       : f32,
+    pub motor_max_torque    : f32,
+    pub joint_inertia       : f32,
+
+    pub ground_stiffness    : f32,
+    pub ground_damping      : f32,
+    pub ground_friction     : f32,
+    pub ground_grip_stiffness: f32,
+    pub ground_grip_damping : f32,
+
+    pub target_distance     : f32,
+    pub target_spread       : f32,
+    pub target_radius       : f32,
+    pub initial_pose_noise  : f32,
+
+    pub reward_for_not_standing         : f32,
+    pub reward_for_hitting_head         : f32,
+    pub reward_per_meter_closer_to_target: f32,
+    pub correct_distance_to_floor       : f32,
+    pub max_distance_to_floor_diff      : f32,
+    pub hitting_head_distance           : f32,
+}
+
+impl Default for BipedSimConfig {
+    fn default() -> Self {
+        Self {
+            physics_delta_time  : 0.0005,
+            fixed_delta_time    : 0.02,
+            control_period      : 0.1,
+            episode_duration    : 10.0,
+
+            gravity             : 9.81,
+            torso_mass          : 5.0,
+            torso_half_size     : Vector2::new(0.15, 0.1),
+            hip_width           : 0.2,
+            link_lengths        : [0.05, 0.25, 0.25],
+            foot_back           : 0.08,
+            foot_front          : 0.12,
+
+            motor_max_angles    : [20f32.to_radians(), 60f32.to_radians(), 60f32.to_radians()],
+            motor_jerk          : 300f32.to_radians(),
+            motor_stiffness     : 200.0,
+            motor_damping       : 4.0,
+            motor_max_torque    : 50.0,
+            joint_inertia       : 0.05,
+
+            ground_stiffness    : 10_000.0,
+            ground_damping      : 200.0,
+            ground_friction     : 0.8,
+            ground_grip_stiffness: 10_000.0,
+            ground_grip_damping : 200.0,
+
+            target_distance     : 5.0,
+            target_spread       : 1.0,
+            target_radius       : 0.3,
+            initial_pose_noise  : 0.02,
+
+            reward_for_not_standing         : -100.0,
+            reward_for_hitting_head         : -1000.0,
+            reward_per_meter_closer_to_target: 100.0,
+            correct_distance_to_floor       : 0.6,
+            max_distance_to_floor_diff      : 0.65,
+            hitting_head_distance           : 0.35,
+        }
+    }
+}
+
+impl BipedSimConfig {
+    pub fn steps_per_episode(&self) -> usize {
+        (self.episode_duration / self.control_period).round() as usize
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct Body {
+    pos     : Vector2<f32>,
+    angle   : f32,
+    vel     : Vector2<f32>,
+    ang_vel : f32,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Joint {
+    input   : f32,
+    target  : f32,
+    angle   : f32,
+    speed   : f32,
+    acc     : f32,
+    torque  : f32,
+}
+
+impl Joint {
+    fn drive(&mut self, dt: f32, max_angle: f32, config: &BipedSimConfig) {
+        let max_delta = dt * config.motor_jerk;
+        let distance_to_target = self.input * max_angle - self.target;
+        self.target += distance_to_target.clamp(-max_delta, max_delta);
+
+        self.torque =
+            (config.motor_stiffness * (self.target - self.angle) - config.motor_damping * self.speed)
+            .clamp(-config.motor_max_torque, config.motor_max_torque);
+    }
+
+    fn integrate(&mut self, dt: f32, external_torque: f32, config: &BipedSimConfig) {
+        self.acc = (self.torque + external_torque) / config.joint_inertia;
+        self.speed += self.acc * dt;
+        self.angle += self.speed * dt;
+    }
+}
+
+#[derive(Clone, Debug)]
+struct LegPose {
+    // hip, shoulder end, knee, ankle
+    points      : [Vector2<f32>; 4],
+    // orientation of shoulder, thigh and shin, relative to the torso
+    rel_angles  : [f32; 3],
+    heel        : Vector2<f32>,
+    toe         : Vector2<f32>,
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ContactPoint {
+    world   : Vector2<f32>,
+    // leg index and how many of its joints hold this point
+    leg     : Option<(usize, usize)>,
+}
+
+#[derive(Clone, Debug)]
+struct TransformTracker {
+    prev_pos    : Vector2<f32>,
+    prev_angle  : f32,
+    reading     : TransformReading,
+}
+
+impl TransformTracker {
+    fn new(world_pos: Vector2<f32>, world_angle: f32, z: f32, torso: &Body) -> Self {
+        let mut tracker = Self {
+            prev_pos    : world_pos,
+            prev_angle  : world_angle,
+            reading     : TransformReading {
+                linear_pos      : Vector3::zeros(),
+                linear_speed    : Vector3::zeros(),
+                linear_acc      : Vector3::zeros(),
+                angular_pos     : UnitQuaternion::identity().into_inner(),
+                angular_speed   : Vector3::zeros(),
+                angular_acc     : Vector3::zeros(),
+            },
+        };
+        tracker.reading.linear_pos = to_local_point(world_pos, torso, z);
+        tracker.reading.angular_pos = z_rotation(world_angle - torso.angle);
+        tracker
+    }
+
+    // mirrors TransformReader.UpdateTransformReading
+    fn update(&mut self, world_pos: Vector2<f32>, world_angle: f32, z: f32, torso: &Body, dt: f32) {
+        let world_vel = (world_pos - self.prev_pos) / dt;
+        let world_ang_vel = (world_angle - self.prev_angle) / dt;
+
+        let linear_speed = to_3d(rotate(world_vel, -torso.angle), 0.0);
+        let angular_speed = Vector3::new(0.0, 0.0, world_ang_vel);
+
+        self.reading = TransformReading {
+            linear_pos      : to_local_point(world_pos, torso, z),
+            linear_acc      : (linear_speed - self.reading.linear_speed) / dt,
+            linear_speed,
+            angular_pos     : z_rotation(world_angle - torso.angle),
+            angular_acc     : (angular_speed - self.reading.angular_speed) / dt,
+            angular_speed,
+        };
+        self.prev_pos = world_pos;
+        self.prev_angle = world_angle;
+    }
+}
+
+#[derive(Clone, Debug)]
+struct LegTrackers {
+    links   : [TransformTracker; 3],
+    foot    : TransformTracker,
+    // joint readings as Motor.cs computes them, by differentiating positions
+    motors  : [MotorReading; 3],
+}
+
+#[derive(Clone, Debug)]
+struct RewardCalculator {
+    previous_time               : f32,
+    previous_distance_to_target : f32,
+}
+
+pub struct BipedSim {
+    config      : BipedSimConfig,
+    rng         : StdRng,
+    time        : f32,
+    torso       : Body,
+    legs        : [[Joint; 3]; 2],
+    target      : Vector2<f32>,
+    // replaces the random target of every episode once set
+    placed_target : Option<Vector2<f32>>,
+
+    prev_contact_points : Option<Vec<Vector2<f32>>>,
+    // where each touching point first gripped the floor, moved when it slips
+    contact_anchors     : Vec<Option<f32>>,
+    contacts            : Vec<(Vector2<f32>, Vector2<f32>)>,
+
+    head_reading        : AccelerometerReading,
+    leg_trackers        : Vec<LegTrackers>,
+    reward_calculator   : RewardCalculator,
+}
+
+impl BipedSim {
+    pub fn new(config: BipedSimConfig, seed: u64) -> Self {
+        let mut sim = Self {
+            config,
+            rng                 : StdRng::seed_from_u64(seed),
+            time                : 0.0,
+            torso               : Body::default(),
+            legs                : Default::default(),
+            target              : Vector2::zeros(),
+            placed_target       : None,
+            prev_contact_points : None,
+            contact_anchors     : Vec::new(),
+            contacts            : Vec::new(),
+            head_reading        : AccelerometerReading {
+                up              : Vector3::y(),
+                linear_speed    : Vector3::zeros(),
+                linear_acc      : Vector3::zeros(),
+                angular_speed   : Vector3::zeros(),
+                angular_acc     : Vector3::zeros(),
+            },
+            leg_trackers        : Vec::new(),
+            reward_calculator   : RewardCalculator { previous_time: 0.0, previous_distance_to_target: 0.0 },
+        };
+        sim.reset();
+        sim
+    }
+
+    pub fn config(&self) -> &BipedSimConfig {
+        &self.config
+    }
+
+    pub fn time(&self) -> f32 {
+        self.time
+    }
+
+    /// Restarts the random sequence the episodes to come are drawn from.
+    pub fn reseed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    pub fn set_episode_duration(&mut self, episode_duration: f32) {
+        self.config.episode_duration = episode_duration;
+    }
+
+    /// Moves the target right away and keeps it there in the next episodes.
+    pub fn place_target(&mut self, target: Vector2<f32>) {
+        self.target = target;
+        self.placed_target = Some(target);
+    }
+
+    pub fn reset(&mut self) -> GameState {
+        let noise = self.config.initial_pose_noise;
+        let spread = self.config.target_spread;
+
+        self.time = 0.0;
+        self.torso = Body::default();
+        self.legs = Default::default();
+        for joint in self.legs.iter_mut().flatten() {
+            joint.angle = self.rng.random_range(-noise..=noise);
+            joint.target = joint.angle;
+        }
+        let random_target = Vector2::new(self.config.target_distance + self.rng.random_range(-spread..=spread), 0.0);
+        self.target = self.placed_target.unwrap_or(random_target);
+
+        // drop the robot so that its lowest point is touching the floor
+        let lowest = self.contact_points().iter().map(|p| p.world.y).fold(f32::INFINITY, f32::min);
+        self.torso.pos.y -= lowest;
+
+        self.prev_contact_points = None;
+        self.contact_anchors.clear();
+        self.contacts.clear();
+        self.head_reading = AccelerometerReading {
+            up              : to_3d(rotate(Vector2::y(), self.torso.angle), 0.0),
+            linear_speed    : Vector3::zeros(),
+            linear_acc      : Vector3::zeros(),
+            angular_speed   : Vector3::zeros(),
+            angular_acc     : Vector3::zeros(),
+        };
+        self.leg_trackers = (0..2)
+            .map(|leg| {
+                let z = self.leg_z(leg);
+                let pose = self.leg_pose(leg);
+                let links = [0, 1, 2].map(|link| {
+                    let mid = (pose.points[link] + pose.points[link + 1]) / 2.0;
+                    TransformTracker::new(mid, self.torso.angle + pose.rel_angles[link], z, &self.torso)
+                });
+                let foot = TransformTracker::new(pose.points[3], self.torso.angle + pose.rel_angles[2], z, &self.torso);
+                let motors = [0, 1, 2].map(|j| MotorReading { pos: self.legs[leg][j].angle, speed: 0.0, acc: 0.0, torque: 0.0 });
+                LegTrackers { links, foot, motors }
+            })
+            .collect();
+
+        self.reward_calculator = RewardCalculator {
+            previous_time               : 0.0,
+            previous_distance_to_target : self.target_local().norm(),
+        };
+        self.read_state()
+    }
+
+    /// Applies the action for one control period and returns the state reached and the reward for getting there.
+    pub fn step(&mut self, action: &GameAction) -> (GameState, Reward) {
+        self.apply_action(action);
+
+        let fixed_updates = (self.config.control_period / self.config.fixed_delta_time).round() as usize;
+        for _ in 0..fixed_updates {
+            self.fixed_update();
+        }
+
+        let state = self.read_state();
+        let reward = self.calculate_reward(&state);
+        (state, reward)
+    }
+
+    pub fn is_episode_over(&self) -> bool {
+        self.time >= self.config.episode_duration - self.config.fixed_delta_time / 2.0
+    }
+
+    /// Same as `Target.IsTouchingRobot`.
+    pub fn is_touching_target(&self) -> bool {
+        let radius = self.config.target_radius;
+        (self.torso.pos - self.target).norm() < radius + self.config.torso_half_size.y
+            || self.contact_points().iter().any(|p| (p.world - self.target).norm() < radius)
+    }
+
+    fn apply_action(&mut self, action: &GameAction) {
+        let limbs = &action.limbs_activation;
+        for (joints, activation) in self.legs.iter_mut().zip([&limbs.left, &limbs.right]) {
+            let LimbActivation { shoulder_activation, thigh_activation, shin_activation } = activation;
+            for (joint, input) in joints.iter_mut().zip([shoulder_activation, thigh_activation, shin_activation]) {
+                joint.input = input.clamp(-1.0, 1.0);
+            }
+        }
+    }
+
+    fn fixed_update(&mut self) {
+        let dt = self.config.physics_delta_time;
+        let substeps = (self.config.fixed_delta_time / dt).round() as usize;
+        for _ in 0..substeps {
+            self.physics_step(dt);
+        }
+        self.time += self.config.fixed_delta_time;
+        self.update_readings(self.config.fixed_delta_time);
+    }
+
+    fn physics_step(&mut self, dt: f32) {
+        let config = &self.config;
+        for joints in self.legs.iter_mut() {
+            for (joint, max_angle) in joints.iter_mut().zip(config.motor_max_angles) {
+                joint.drive(dt, max_angle, config);
+            }
+        }
+
+        let points = self.contact_points();
+        self.contact_anchors.resize(points.len(), None);
+        let pivots = [0, 1].map(|leg| self.leg_pose(leg).points);
+
+        let mut force = Vector2::new(0.0, -config.gravity * config.torso_mass);
+        let mut torque = 0.0;
+        let mut joint_torques = [[0.0f32; 3]; 2];
+        self.contacts.clear();
+
+        for (ix, point) in points.iter().enumerate() {
+            if point.world.y >= 0.0 {
+                self.contact_anchors[ix] = None;
+                continue;
+            }
+            let vel = match &self.prev_contact_points {
+                Some(prev) => (point.world - prev[ix]) / dt,
+                None => Vector2::zeros(),
+            };
+            let normal = (-config.ground_stiffness * point.world.y - config.ground_damping * vel.y).max(0.0);
+            let max_friction = config.ground_friction * normal;
+            let anchor = *self.contact_anchors[ix].get_or_insert(point.world.x);
+            let grip = -config.ground_grip_stiffness * (point.world.x - anchor) - config.ground_grip_damping * vel.x;
+            let tangential = grip.clamp(-max_friction, max_friction);
+            if tangential != grip {
+                // slipping, the point grips the floor again where the friction can hold it
+                self.contact_anchors[ix] = Some(point.world.x + tangential / config.ground_grip_stiffness);
+            }
+            let contact_force = Vector2::new(tangential, normal);
+
+            force += contact_force;
+            torque += cross(&(point.world - self.torso.pos), &contact_force);
+            if let Some((leg, held_by)) = point.leg {
+                for joint in 0..held_by {
+                    joint_torques[leg][joint] += cross(&(point.world - pivots[leg][joint]), &contact_force);
+                }
+            }
+            self.contacts.push((point.world, contact_force));
+        }
+        self.prev_contact_points = Some(points.iter().map(|p| p.world).collect());
+
+        let half = config.torso_half_size;
+        let inertia = config.torso_mass * 4.0 * (half.x * half.x + half.y * half.y) / 12.0;
+        self.torso.vel += force / config.torso_mass * dt;
+        self.torso.ang_vel += torque / inertia * dt;
+        self.torso.pos += self.torso.vel * dt;
+        self.torso.angle += self.torso.ang_vel * dt;
+
+        for (joints, torques) in self.legs.iter_mut().zip(joint_torques) {
+            for (joint, external_torque) in joints.iter_mut().zip(torques) {
+                joint.integrate(dt, external_torque, config);
+            }
+        }
+    }
+
+    fn update_readings(&mut self, dt: f32) {
+        let linear_speed = to_3d(rotate(self.torso.vel, -self.torso.angle), 0.0);
+        let angular_speed = Vector3::new(0.0, 0.0, self.torso.ang_vel);
+        self.head_reading = AccelerometerReading {
+            up          : to_3d(rotate(Vector2::y(), self.torso.angle), 0.0),
+            linear_acc  : (linear_speed - self.head_reading.linear_speed) / dt,
+            linear_speed,
+            angular_acc : (angular_speed - self.head_reading.angular_speed) / dt,
+            angular_speed,
+        };
+
+        for leg in 0..2 {
+            let z = self.leg_z(leg);
+            let pose = self.leg_pose(leg);
+            let torso = &self.torso;
+            let trackers = &mut self.leg_trackers[leg];
+            for link in 0..3 {
+                let mid = (pose.points[link] + pose.points[link + 1]) / 2.0;
+                trackers.links[link].update(mid, torso.angle + pose.rel_angles[link], z, torso, dt);
+            }
+            trackers.foot.update(pose.points[3], torso.angle + pose.rel_angles[2], z, torso, dt);
+
+            for (reading, joint) in trackers.motors.iter_mut().zip(&self.legs[leg]) {
+                let speed = (joint.angle - reading.pos) / dt;
+                *reading = MotorReading {
+                    pos     : joint.angle,
+                    acc     : (speed - reading.speed) / dt,
+                    speed,
+                    torque  : joint.torque,
+                };
+            }
+        }
+    }
+
+    pub fn read_state(&self) -> GameState {
+        let read_limb = |trackers: &LegTrackers| {
+            let read_link = |link: usize| LinkReading {
+                motor       : trackers.motors[link].clone(),
+                transform   : trackers.links[link].reading.clone(),
+            };
+            LimbReading {
+                shoulder: read_link(0),
+                thigh   : read_link(1),
+                shin    : read_link(2),
+                foot    : trackers.foot.reading.clone(),
+            }
+        };
+
+        GameState {
+            sensors_reading: SensorsReading {
+                target_pos      : self.target_local(),
+                floor_distance  : self.distance_to_floor(),
+                acc_reading     : self.head_reading.clone(),
+                forces          : self.contacts
+                    .iter()
+                    .map(|(pos, force)| Force {
+                        pos     : to_local_point(*pos, &self.torso, 0.0),
+                        // Unity reports the impulse of the last physics update
+                        force   : to_3d(rotate(*force * self.config.fixed_delta_time, -self.torso.angle), 0.0),
+                    })
+                    .collect(),
+            },
+            limbs_readings: BipedalLimbsReading {
+                left    : read_limb(&self.leg_trackers[0]),
+                right   : read_limb(&self.leg_trackers[1]),
+            },
+        }
+    }
+
+    // port of RewardCalculator in Game.cs
+    fn calculate_reward(&mut self, state: &GameState) -> Reward {
+        let config = &self.config;
+        let calculator = &mut self.reward_calculator;
+        let floor_distance = state.sensors_reading.floor_distance;
+
+        let reward_delta_time = self.time - calculator.previous_time;
+        let distance_from_floor_error =
+            ((floor_distance - config.correct_distance_to_floor).abs() / config.max_distance_to_floor_diff)
+            .min(1.0)
+            .powi(2);
+        let not_standing_punishment = distance_from_floor_error * config.reward_for_not_standing * reward_delta_time;
+
+        let current_distance_to_target = state.sensors_reading.target_pos.norm();
+        let delta_distance = calculator.previous_distance_to_target - current_distance_to_target;
+        let reward_for_walking_towards_target = delta_distance * config.reward_per_meter_closer_to_target;
+
+        let hitting_head_punishment =
+            if floor_distance < config.hitting_head_distance { config.reward_for_hitting_head * reward_delta_time }
+            else { 0.0 };
+
+        calculator.previous_distance_to_target = current_distance_to_target;
+        calculator.previous_time = self.time;
+
+        not_standing_punishment + reward_for_walking_towards_target + hitting_head_punishment
+    }
+
+    fn target_local(&self) -> Vector3<f32> {
+        to_local_point(self.target, &self.torso, 0.0)
+    }
+
+    fn distance_to_floor(&self) -> f32 {
+        self.torso.pos.y.max(0.0)
+    }
+
+    fn leg_z(&self, leg: usize) -> f32 {
+        if leg == 0 { self.config.hip_width / 2.0 } else { -self.config.hip_width / 2.0 }
+    }
+
+    fn leg_pose(&self, leg: usize) -> LegPose {
+        let torso = &self.torso;
+        let hip = torso.pos + rotate(Vector2::new(0.0, -self.config.torso_half_size.y), torso.angle);
+
+        let mut points = [hip; 4];
+        let mut rel_angles = [0.0; 3];
+        let mut rel_angle = 0.0;
+        for link in 0..3 {
+            rel_angle += self.legs[leg][link].angle;
+            rel_angles[link] = rel_angle;
+            // links hang down from the hip when every joint is at 0
+            let direction = direction(torso.angle + rel_angle - FRAC_PI_2);
+            points[link + 1] = points[link] + direction * self.config.link_lengths[link];
+        }
+        let foot_direction = direction(torso.angle + rel_angle);
+        let ankle = points[3];
+
+        LegPose {
+            points,
+            rel_angles,
+            heel: ankle - foot_direction * self.config.foot_back,
+            toe : ankle + foot_direction * self.config.foot_front,
+        }
+    }
+
+    fn contact_points(&self) -> Vec<ContactPoint> {
+        let mut points = Vec::with_capacity(10);
+        for leg in 0..2 {
+            let pose = self.leg_pose(leg);
+            points.push(ContactPoint { world: pose.points[2], leg: Some((leg, 2)) });
+            points.push(ContactPoint { world: pose.heel, leg: Some((leg, 3)) });
+            points.push(ContactPoint { world: pose.toe, leg: Some((leg, 3)) });
+        }
+        let half = self.config.torso_half_size;
+        for corner in [Vector2::new(half.x, half.y), Vector2::new(-half.x, half.y), Vector2::new(half.x, -half.y), Vector2::new(-half.x, -half.y)] {
+            points.push(ContactPoint { world: self.torso.pos + rotate(corner, self.torso.angle), leg: None });
+        }
+        points
+    }
+}
+
+fn direction(angle: f32) -> Vector2<f32> {
+    Vector2::new(angle.cos(), angle.sin())
+}
+
+fn rotate(v: Vector2<f32>, angle: f32) -> Vector2<f32> {
+    Rotation2::new(angle) * v
+}
+
+fn cross(a: &Vector2<f32>, b: &Vector2<f32>) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+fn to_3d(v: Vector2<f32>, z: f32) -> Vector3<f32> {
+    Vector3::new(v.x, v.y, z)
+}
+
+fn to_local_point(world: Vector2<f32>, torso: &Body, z: f32) -> Vector3<f32> {
+    to_3d(rotate(world - torso.pos, -torso.angle), z)
+}
+
+fn z_rotation(angle: f32) -> nalgebra::Quaternion<f32> {
+    UnitQuaternion::from_axis_angle(&Vector3::z_axis(), angle).into_inner()
+}
+
+// the episode ends like it does in Unity: the last step only tells that it's over
+impl Environment for BipedSim {
+    type Error = Infallible;
+
+    fn reset(&mut self) -> impl Future<Output = Result<GameState, Infallible>> {
+        ready(Ok(BipedSim::reset(self)))
+    }
+
+    fn step(&mut self, action: &GameAction) -> impl Future<Output = Result<(GameState, Reward, StepFlags), Infallible>> {
+        let (state, reward) = BipedSim::step(self, action);
+        let flags = if self.is_episode_over() {
+            StepFlags { truncated: true, ..Default::default() }
+        } else {
+            let success = self.is_touching_target();
+            StepFlags { terminal: success, success, truncated: false }
+        };
+        ready(Ok((state, reward, flags)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        procedures::run_simulation::RunEpisodeExt,
+        types::{action::GameAction, policy::{nil_policy::NilPolicy, FnPolicy}, state::GameState},
+    };
+
+    use super::{BipedSim, BipedSimConfig};
+
+    #[tokio::test]
+    async fn episodes_are_deterministic() {
+        let config = BipedSimConfig { episode_duration: 2.0, ..Default::default() };
+        let mut policy = FnPolicy(|_: &GameState| {
+            let mut action = GameAction::default();
+            action.limbs_activation.left.thigh_activation = 0.5;
+            action.limbs_activation.right.shin_activation = -0.5;
+            action
+        });
+
+        let first = BipedSim::new(config.clone(), 7).run_episode(&mut policy).await.unwrap();
+        let second = BipedSim::new(config.clone(), 7).run_episode(&mut policy).await.unwrap();
+
+        assert_eq!(first.states.len(), config.steps_per_episode() - 1);
+        assert_eq!(first.states, second.states);
+        assert!(first.truncated && !first.success);
+        assert_eq!(first.rewards, second.rewards);
+    }
+
+    #[tokio::test]
+    async fn standing_still_stays_up() {
+        let config = BipedSimConfig { episode_duration: 3.0, ..Default::default() };
+        let mut sim = BipedSim::new(config, 0);
+        let history = sim.run_episode(&mut NilPolicy).await.unwrap();
+
+        let last = history.states.last().unwrap();
+        assert!(last.sensors_reading.floor_distance > 0.5);
+        assert!(last.sensors_reading.acc_reading.up.y > 0.9);
+        assert!(!last.sensors_reading.forces.is_empty());
+        assert!(history.states.iter().flat_map(|s| s.sensors_reading.forces.iter()).all(|f| f.force.iter().all(|v| v.is_finite())));
+    }
+    #[tokio::test]
+    async fn reaching_the_target_is_terminal() {
+        let config = BipedSimConfig { target_distance: 0.0, target_spread: 0.0, ..Default::default() };
+        let history = BipedSim::new(config, 0).run_episode(&mut NilPolicy).await.unwrap();
+
+        assert!(history.success && !history.truncated);
+        assert_eq!(history.terminals, [true]);
+    }
+}
@@ -0,0 +1,343 @@
+use std::net::SocketAddr;
+
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use tokio::net::TcpStream;
+use tracing::info;
+
+use crate::{
+    comm::{open_simulation_stream, recv_frame, send_frame, ConnectionRole, DEFAULT_SIMULATION_ADDR},
+    error::SimulationError,
+    types::{
+        action::GameAction,
+        protocol::{HelloReply, SimulationCommand, SimulationHello, WireFormat},
+        state::{EpisodeEndReason, GameUpdate, StepFlags},
+    },
+    wire::{decode_action, decode_json, encode_step, BINARY_ACTION_TAG},
+};
+
+use super::biped::{BipedSim, BipedSimConfig};
+
+/// Stand-in for the Unity scene. It talks to `SimulationConnector` exactly like `AgentEndpoint` in Game.cs does,
+/// so the binaries can run on machines without a desktop. The states come from a `BipedSim`.
+#[derive(Clone, Debug)]
+pub struct MockSimulatorConfig {
+    pub addr    : SocketAddr,
+    // the role of the simulator, so the opposite of the brain's
+    pub role    : ConnectionRole,
+    pub seed    : u64,
+    pub sim     : BipedSimConfig,
+    // offered in the handshake
+    pub wire_formats : Vec<WireFormat>,
+}
+
+impl Default for MockSimulatorConfig {
+    fn default() -> Self {
+        Self {
+            addr    : DEFAULT_SIMULATION_ADDR,
+            role    : ConnectionRole::Connect,
+            seed    : 0,
+            sim     : BipedSimConfig::default(),
+            wire_formats : vec![WireFormat::Json, WireFormat::Binary],
+        }
+    }
+}
+
+pub struct MockSimulator {
+    stream      : TcpStream,
+    sim         : BipedSim,
+    wire_format : WireFormat,
+}
+
+impl MockSimulator {
+    pub async fn connect(config: MockSimulatorConfig) -> Result<Self, SimulationError> {
+        let stream = open_simulation_stream(config.addr, config.role).await?;
+        let mut simulator = Self { stream, sim: BipedSim::new(config.sim, config.seed), wire_format: WireFormat::Json };
+
+        let hello = SimulationHello {
+            wire_formats: config.wire_formats,
+            ..SimulationHello::expected(simulator.sim.config().control_period, simulator.sim.config().episode_duration)
+        };
+        simulator.send(&hello).await?;
+        match simulator.recv::<HelloReply>().await? {
+            HelloReply::Accept { wire_format } => {
+                simulator.wire_format = wire_format;
+                Ok(simulator)
+            }
+            HelloReply::Reject { reason } => Err(SimulationError::Incompatible { reason }),
+        }
+    }
+
+    async fn send(&mut self, message: &impl Serialize) -> Result<(), SimulationError> {
+        send_frame(&mut self.stream, &serde_json::to_vec(message)?).await
+    }
+
+    async fn recv<T: DeserializeOwned>(&mut self) -> Result<T, SimulationError> {
+        decode_json(&recv_frame(&mut self.stream).await?)
+    }
+
+    async fn send_update(&mut self, update: &GameUpdate) -> Result<(), SimulationError> {
+        match (self.wire_format, update) {
+            (WireFormat::Binary, GameUpdate::GameStep { state, reward, flags }) => {
+                send_frame(&mut self.stream, &encode_step(state, *reward, *flags)).await
+            }
+            _ => self.send(update).await,
+        }
+    }
+
+    // applies the commands coming before the action, `None` when one of them ends the episode
+    async fn recv_action(&mut self) -> Result<Option<GameAction>, SimulationError> {
+        loop {
+            let frame = recv_frame(&mut self.stream).await?;
+            if frame.first() == Some(&BINARY_ACTION_TAG) {
+                return Ok(Some(decode_action(&frame)?));
+            }
+            let json: Value = decode_json(&frame)?;
+            if json.get("Type").is_none() {
+                return Ok(Some(serde_json::from_value(json)?));
+            }
+            let command: SimulationCommand = serde_json::from_value(json)?;
+            info!("mock simulation got {command:?}");
+            match command {
+                SimulationCommand::ResetEpisode => return Ok(None),
+                SimulationCommand::SetSeed { seed } => self.sim.reseed(seed),
+                // there is no real time to scale, the mock always runs as fast as it can
+                SimulationCommand::SetTimeScale { .. } => {}
+                SimulationCommand::SetEpisodeDuration { episode_duration } => self.sim.set_episode_duration(episode_duration),
+                SimulationCommand::PlaceTarget { position } => self.sim.place_target(position.xy()),
+            }
+        }
+    }
+
+    pub async fn run(mut self, episodes: Option<usize>) -> Result<(), SimulationError> {
+        let mut episode = 0;
+        while episodes.is_none_or(|episodes| episode < episodes) {
+            self.run_episode().await?;
+            episode += 1;
+        }
+        Ok(())
+    }
+
+    pub async fn run_episode(&mut self) -> Result<(), SimulationError> {
+        info!("mock simulation is starting an episode");
+        let mut update = GameUpdate::GameStep { state: self.sim.reset(), reward: 0.0, flags: StepFlags::default() };
+        self.send(&GameUpdate::EpisodeStarted).await?;
+
+        let mut reason = EpisodeEndReason::TimeLimit;
+        let mut step = 0;
+        // the episode duration can change in the middle of it
+        while step < self.sim.config().steps_per_episode() {
+            step += 1;
+            self.send_update(&update).await?;
+
+            // like unity, a terminal state still gets an action before the episode ends
+            let Some(action) = self.recv_action().await? else {
+                reason = EpisodeEndReason::Stopped;
+                break;
+            };
+            if let GameUpdate::GameStep { flags: StepFlags { terminal: true, .. }, .. } = update {
+                reason = EpisodeEndReason::Success;
+                break;
+            }
+            let (state, reward) = self.sim.step(&action);
+            let success = self.sim.is_touching_target();
+            update = GameUpdate::GameStep { state, reward, flags: StepFlags { terminal: success, success, truncated: false } };
+        }
+        self.send(&GameUpdate::EpisodeEnded { reason }).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::SocketAddr;
+
+    use burn::backend::NdArray;
+    use nalgebra::Vector3;
+
+    use crate::{
+        comm::{connect_retrying, recv_frame, send_frame, ConnectionRole, SimulationConnector},
+        error::SimulationError,
+        procedures::run_simulation::{RunEpisodeExt, RunEpisodesBatchedExt},
+        simulation::biped::{BipedSim, BipedSimConfig},
+        types::{
+            policy::nil_policy::NilPolicy,
+            protocol::{HelloReply, SimulationCommand, SimulationHello, WireFormat},
+            state::GameUpdate,
+        },
+    };
+
+    use super::{MockSimulator, MockSimulatorConfig};
+
+    // every test gets its own port, so that they can run in parallel
+    fn config(port: u16) -> MockSimulatorConfig {
+        MockSimulatorConfig {
+            addr: SocketAddr::from(([127, 0, 0, 1], port)),
+            sim: BipedSimConfig { episode_duration: 1.0, ..Default::default() },
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn run_episode_against_mock() {
+        let config = config(18081);
+        let connection = tokio::spawn(SimulationConnector::new().with_addr(config.addr).connect());
+
+        let steps = config.sim.steps_per_episode();
+        let mut in_process = BipedSim::new(config.sim.clone(), config.seed);
+        tokio::spawn(MockSimulator::connect(config).await.unwrap().run(Some(1)));
+
+        let mut simulation = connection.await.unwrap().unwrap();
+        let history = simulation.run_episode(&mut NilPolicy).await.unwrap();
+
+        // the last state of an episode has no reward following it
+        assert_eq!(history.states.len(), steps - 1);
+        assert_eq!(history.actions.len(), steps - 1);
+        assert_eq!(history.rewards.len(), steps - 1);
+
+        // going through TCP doesn't change the episode, up to the precision json keeps
+        let expected = in_process.run_episode(&mut NilPolicy).await.unwrap();
+        for (state, expected_state) in history.states.iter().zip(&expected.states) {
+            assert!((state.sensors_reading.floor_distance - expected_state.sensors_reading.floor_distance).abs() < 1e-5);
+        }
+        for (reward, expected_reward) in history.rewards.iter().zip(&expected.rewards) {
+            assert!((reward - expected_reward).abs() < 1e-3);
+        }
+    }
+
+    #[tokio::test]
+    async fn brain_can_connect_to_a_listening_simulator() {
+        let config = MockSimulatorConfig { role: ConnectionRole::Listen, ..config(18082) };
+        let steps = config.sim.steps_per_episode();
+        let connector = SimulationConnector::new().with_addr(config.addr).with_role(ConnectionRole::Connect);
+        tokio::spawn(async move { MockSimulator::connect(config).await.unwrap().run(Some(1)).await });
+
+        let mut simulation = connector.connect().await.unwrap();
+        let history = simulation.run_episode(&mut NilPolicy).await.unwrap();
+        assert_eq!(history.states.len(), steps - 1);
+    }
+
+    #[tokio::test]
+    async fn reconnects_after_disconnect() {
+        let config = config(18083);
+        let connection = tokio::spawn(SimulationConnector::new().with_addr(config.addr).connect());
+        let steps = config.sim.steps_per_episode();
+
+        // leaves after three states, like unity exiting play mode
+        let mut crashing = MockSimulator::connect(config.clone()).await.unwrap();
+        tokio::spawn(async move {
+            let update = GameUpdate::GameStep { state: crashing.sim.reset(), reward: 0.0, flags: Default::default() };
+            crashing.send(&GameUpdate::EpisodeStarted).await.unwrap();
+            for _ in 0..3 {
+                crashing.send(&update).await.unwrap();
+                crashing.recv::<serde_json::Value>().await.unwrap();
+            }
+        });
+
+        let mut simulation = connection.await.unwrap().unwrap();
+        let history = simulation.run_episode(&mut NilPolicy).await.unwrap();
+        assert!(history.truncated);
+        assert_eq!(history.states.len(), 2);
+
+        // the handshake only happens once the endpoint is reset
+        tokio::spawn(async move { MockSimulator::connect(config).await.unwrap().run(Some(1)).await });
+        let history = simulation.run_episode(&mut NilPolicy).await.unwrap();
+        assert!(history.truncated && !history.success);
+        assert_eq!(history.states.len(), steps - 1);
+    }
+
+    #[tokio::test]
+    async fn batched_episodes_over_many_connections() {
+        let config = config(18084);
+        let steps = config.sim.steps_per_episode();
+        let connections = tokio::spawn(SimulationConnector::new().with_addr(config.addr).connect_many(2));
+        for seed in 0..2 {
+            let config = MockSimulatorConfig { seed, ..config.clone() };
+            tokio::spawn(MockSimulator::connect(config).await.unwrap().run(Some(1)));
+        }
+
+        let mut simulations = connections.await.unwrap().unwrap();
+        let histories = simulations.run_episodes_batched::<NdArray>(&mut NilPolicy, &Default::default()).await.unwrap();
+        assert_eq!(histories.len(), 2);
+        assert!(histories.iter().all(|history| history.states.len() == steps - 1 && history.truncated));
+    }
+
+    #[tokio::test]
+    async fn episodes_end_on_reaching_the_target() {
+        let mut config = config(18086);
+        config.sim.target_distance = 0.0;
+        config.sim.target_spread = 0.0;
+        let connection = tokio::spawn(SimulationConnector::new().with_addr(config.addr).connect());
+        tokio::spawn(MockSimulator::connect(config).await.unwrap().run(Some(2)));
+
+        // the second episode only starts once the terminal state of the first one got its action
+        let mut simulation = connection.await.unwrap().unwrap();
+        for _ in 0..2 {
+            let history = simulation.run_episode(&mut NilPolicy).await.unwrap();
+            assert!(history.success && !history.truncated);
+            assert_eq!(history.terminals, [true]);
+        }
+    }
+
+    #[tokio::test]
+    async fn commands_reach_the_simulation() {
+        let config = config(18087);
+        let connection = tokio::spawn(SimulationConnector::new().with_addr(config.addr).connect());
+        tokio::spawn(MockSimulator::connect(config).await.unwrap().run(None));
+        let mut simulation = connection.await.unwrap().unwrap();
+
+        // the episode already started when the commands get queued, the reset drops it
+        simulation.queue_command(SimulationCommand::SetEpisodeDuration { episode_duration: 0.5 });
+        simulation.queue_command(SimulationCommand::ResetEpisode);
+        let history = simulation.run_episode(&mut NilPolicy).await.unwrap();
+        let steps = BipedSimConfig { episode_duration: 0.5, ..Default::default() }.steps_per_episode();
+        assert_eq!(history.states.len(), steps - 1);
+
+        simulation.queue_command(SimulationCommand::PlaceTarget { position: Vector3::zeros() });
+        simulation.queue_command(SimulationCommand::ResetEpisode);
+        let history = simulation.run_episode(&mut NilPolicy).await.unwrap();
+        assert!(history.success);
+        assert_eq!(history.terminals, [true]);
+    }
+
+    #[tokio::test]
+    async fn binary_wire_format_keeps_states_exact() {
+        let config = config(18088);
+        let connection = tokio::spawn(SimulationConnector::new().with_addr(config.addr).with_wire_format(WireFormat::Binary).connect());
+        let mut in_process = BipedSim::new(config.sim.clone(), config.seed);
+        tokio::spawn(MockSimulator::connect(config).await.unwrap().run(Some(1)));
+
+        let mut simulation = connection.await.unwrap().unwrap();
+        let history = simulation.run_episode(&mut NilPolicy).await.unwrap();
+        let expected = in_process.run_episode(&mut NilPolicy).await.unwrap();
+        assert_eq!(history.states, expected.states);
+        assert_eq!(history.rewards, expected.rewards);
+    }
+
+    #[tokio::test]
+    async fn wire_format_falls_back_to_json() {
+        let config = MockSimulatorConfig { wire_formats: vec![WireFormat::Json], ..config(18089) };
+        let steps = config.sim.steps_per_episode();
+        let connection = tokio::spawn(SimulationConnector::new().with_addr(config.addr).with_wire_format(WireFormat::Binary).connect());
+        tokio::spawn(MockSimulator::connect(config).await.unwrap().run(Some(1)));
+
+        let mut simulation = connection.await.unwrap().unwrap();
+        assert_eq!(simulation.hello().unwrap().negotiate(WireFormat::Binary), WireFormat::Json);
+        let history = simulation.run_episode(&mut NilPolicy).await.unwrap();
+        assert_eq!(history.states.len(), steps - 1);
+    }
+
+    #[tokio::test]
+    async fn incompatible_simulation_is_rejected() {
+        let config = config(18085);
+        let connection = tokio::spawn(SimulationConnector::new().with_addr(config.addr).connect());
+
+        let mut stream = connect_retrying(config.addr).await;
+        let hello = SimulationHello { protocol_version: 0, ..SimulationHello::expected(0.1, 10.0) };
+        send_frame(&mut stream, &serde_json::to_vec(&hello).unwrap()).await.unwrap();
+        let reply = serde_json::from_slice(&recv_frame(&mut stream).await.unwrap());
+
+        assert!(matches!(reply, Ok(HelloReply::Reject { .. })));
+        assert!(matches!(connection.await.unwrap(), Err(SimulationError::Incompatible { .. })));
+    }
+}
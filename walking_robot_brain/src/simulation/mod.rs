@@ -0,0 +1,3 @@
+pub mod mock;
+pub mod biped;
+pub mod replay;
@@ -0,0 +1,296 @@
+use std::{net::SocketAddr, time::Duration};
+
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::{net::TcpStream, time::timeout};
+use tracing::{info, warn};
+
+use crate::{
+    comm::{open_simulation_stream, recv_frame, send_frame, ConnectionRole},
+    error::SimulationError,
+    recording::{Direction, RecordedFrame},
+    tensor_conversion::TensorConvertible,
+    types::{
+        action::GameAction,
+        environment::Environment,
+        state::{GameState, GameUpdate, Reward, StepFlags},
+    },
+    wire::{decode_json, decode_update, reply_as_json, BINARY_STEP_TAG},
+};
+
+/// Json keeps a few digits only, actions that went through it are not bit for bit the ones the policy picked.
+pub const DEFAULT_ACTION_TOLERANCE: f32 = 1e-4;
+
+/// A reply of the brain that is not the recorded one.
+#[derive(Clone, PartialEq, Debug)]
+pub struct Divergence {
+    // index of the recorded reply in the session
+    pub frame       : usize,
+    pub recorded    : Value,
+    pub received    : Value,
+}
+
+// actions are compared value by value, anything else has to be the same json
+fn replies_match(recorded: &Value, received: &Value, tolerance: f32) -> bool {
+    match (GameAction::deserialize(recorded), GameAction::deserialize(received)) {
+        (Ok(recorded), Ok(received)) => recorded
+            .iterate_values()
+            .zip(received.iterate_values())
+            .all(|(recorded, received)| (recorded - received).abs() <= tolerance),
+        _ => recorded == received,
+    }
+}
+
+/// Plays a recorded session back to `run_episode` in process: the states and rewards are the recorded ones, whatever
+/// the actions, and the actions that differ from the recorded ones end up in `divergences`.
+pub struct ReplayEnvironment {
+    frames          : Vec<RecordedFrame>,
+    // next frame to look at
+    position        : usize,
+    tolerance       : f32,
+    episode_started : bool,
+    last_state      : Option<GameState>,
+    divergences     : Vec<Divergence>,
+}
+
+impl ReplayEnvironment {
+    pub fn new(frames: Vec<RecordedFrame>) -> Self {
+        Self {
+            frames,
+            position        : 0,
+            tolerance       : DEFAULT_ACTION_TOLERANCE,
+            episode_started : false,
+            last_state      : None,
+            divergences     : Vec::new(),
+        }
+    }
+
+    pub fn with_tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn divergences(&self) -> &[Divergence] {
+        &self.divergences
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.position >= self.frames.len()
+    }
+
+    // the next message of the simulation, what the brain answered before it is skipped
+    fn next_update(&mut self) -> Result<GameUpdate, SimulationError> {
+        loop {
+            let frame = self.frames.get(self.position).ok_or(SimulationError::Disconnected)?;
+            self.position += 1;
+            if frame.direction == Direction::ToBrain {
+                // the handshake happens once per connection, a session may hold several
+                if frame.payload.first() != Some(&BINARY_STEP_TAG) && decode_json::<Value>(&frame.payload)?["Type"] == "Hello" {
+                    continue;
+                }
+                return decode_update(&frame.payload);
+            }
+        }
+    }
+
+    // compares `action` with the recorded action answering the last state, commands sent with it are left alone
+    fn check_action(&mut self, action: &GameAction) -> Result<(), SimulationError> {
+        let replies = self.frames[self.position..].iter().enumerate().take_while(|(_, frame)| frame.direction == Direction::ToSimulation);
+        for (offset, frame) in replies {
+            let recorded = reply_as_json(&frame.payload)?;
+            if recorded.get("Type").is_some() {
+                continue;
+            }
+            let received = serde_json::to_value(action)?;
+            if !replies_match(&recorded, &received, self.tolerance) {
+                let frame = self.position + offset;
+                warn!("action diverges from frame {frame}: recorded {recorded}, received {received}");
+                self.divergences.push(Divergence { frame, recorded, received });
+            }
+            break;
+        }
+        Ok(())
+    }
+}
+
+impl Environment for ReplayEnvironment {
+    type Error = SimulationError;
+
+    // the end of the session is the simulation going away
+    async fn reset(&mut self) -> Result<GameState, SimulationError> {
+        loop {
+            match self.next_update()? {
+                GameUpdate::EpisodeStarted => self.episode_started = true,
+                GameUpdate::EpisodeEnded { .. } => self.episode_started = false,
+                GameUpdate::GameStep { state, .. } if self.episode_started => {
+                    self.episode_started = false;
+                    self.last_state = Some(state.clone());
+                    return Ok(state);
+                }
+                GameUpdate::GameStep { .. } => {}
+            }
+        }
+    }
+
+    async fn step(&mut self, action: &GameAction) -> Result<(GameState, Reward, StepFlags), SimulationError> {
+        self.check_action(action)?;
+        let truncated = StepFlags { truncated: true, ..Default::default() };
+        match self.next_update()? {
+            GameUpdate::EpisodeEnded { .. } => {
+                Ok((self.last_state.clone().ok_or(SimulationError::NotReset)?, 0.0, truncated))
+            }
+            GameUpdate::EpisodeStarted => {
+                self.episode_started = true;
+                Ok((self.last_state.clone().ok_or(SimulationError::NotReset)?, 0.0, truncated))
+            }
+            GameUpdate::GameStep { state, reward, flags } => {
+                self.last_state = Some(state.clone());
+                Ok((state, reward, flags))
+            }
+        }
+    }
+}
+
+/// Plays the simulation's side of a recorded session to a brain over TCP, sending the recorded frames byte for byte
+/// and checking the brain's replies against the recorded ones.
+pub struct ReplaySimulator {
+    stream      : TcpStream,
+    frames      : Vec<RecordedFrame>,
+    tolerance   : f32,
+}
+
+impl ReplaySimulator {
+    /// Unlike `MockSimulator`, no handshake happens here, the recorded one is replayed with the rest.
+    pub async fn connect(addr: SocketAddr, role: ConnectionRole, frames: Vec<RecordedFrame>) -> Result<Self, SimulationError> {
+        let stream = open_simulation_stream(addr, role).await?;
+        Ok(Self { stream, frames, tolerance: DEFAULT_ACTION_TOLERANCE })
+    }
+
+    pub fn with_tolerance(mut self, tolerance: f32) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Goes through the whole session and returns where the brain diverged from it.
+    pub async fn run(mut self) -> Result<Vec<Divergence>, SimulationError> {
+        let mut divergences = Vec::new();
+        for (ix, frame) in self.frames.iter().enumerate() {
+            match frame.direction {
+                Direction::ToBrain => send_frame(&mut self.stream, &frame.payload).await?,
+                Direction::ToSimulation => {
+                    // a brain that has nothing to say where the recording says it answered diverged for good
+                    let received = timeout(Duration::from_secs(10), recv_frame(&mut self.stream))
+                        .await
+                        .map_err(|_| SimulationError::Io(std::io::ErrorKind::TimedOut.into()))??;
+                    let recorded = reply_as_json(&frame.payload)?;
+                    let received = reply_as_json(&received)?;
+                    if !replies_match(&recorded, &received, self.tolerance) {
+                        warn!("brain diverges at frame {ix}: recorded {recorded}, received {received}");
+                        divergences.push(Divergence { frame: ix, recorded, received });
+                    }
+                }
+            }
+        }
+        info!("replayed {} frames, {} divergences", self.frames.len(), divergences.len());
+        Ok(divergences)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{net::SocketAddr, time::Duration};
+
+    use serde::Serialize;
+
+    use crate::{
+        comm::{ConnectionRole, SimulationConnector},
+        procedures::run_simulation::RunEpisodeExt,
+        recording::{Direction, RecordedFrame},
+        simulation::biped::{BipedSim, BipedSimConfig},
+        types::{
+            action::GameAction,
+            policy::{nil_policy::NilPolicy, FnPolicy},
+            protocol::{HelloReply, SimulationHello, WireFormat},
+            state::{EpisodeEndReason, GameState, GameUpdate, StepFlags},
+        },
+    };
+
+    use super::{ReplayEnvironment, ReplaySimulator};
+
+    fn json(message: &impl Serialize) -> Vec<u8> {
+        serde_json::to_vec(message).unwrap()
+    }
+
+    // what a simulation talking to a brain with `NilPolicy` would have recorded, without going through TCP
+    fn session(config: &BipedSimConfig) -> Vec<RecordedFrame> {
+        let mut frames = Vec::new();
+        let mut push = |direction, payload: Vec<u8>| {
+            let elapsed = Duration::from_millis(frames.len() as u64);
+            frames.push(RecordedFrame { elapsed, direction, payload });
+        };
+        push(Direction::ToBrain, json(&SimulationHello::expected(config.control_period, config.episode_duration)));
+        push(Direction::ToSimulation, json(&HelloReply::Accept { wire_format: WireFormat::Json }));
+
+        let mut sim = BipedSim::new(config.clone(), 0);
+        push(Direction::ToBrain, json(&GameUpdate::EpisodeStarted));
+        let mut update = GameUpdate::GameStep { state: sim.reset(), reward: 0.0, flags: StepFlags::default() };
+        for _ in 0..config.steps_per_episode() {
+            push(Direction::ToBrain, json(&update));
+            push(Direction::ToSimulation, json(&GameAction::default()));
+            let (state, reward) = sim.step(&GameAction::default());
+            update = GameUpdate::GameStep { state, reward, flags: StepFlags::default() };
+        }
+        push(Direction::ToBrain, json(&GameUpdate::EpisodeEnded { reason: EpisodeEndReason::TimeLimit }));
+        frames
+    }
+
+    fn config() -> BipedSimConfig {
+        BipedSimConfig { episode_duration: 1.0, ..Default::default() }
+    }
+
+    #[tokio::test]
+    async fn replaying_the_same_policy_does_not_diverge() {
+        let config = config();
+        let mut replay = ReplayEnvironment::new(session(&config));
+        let history = replay.run_episode(&mut NilPolicy).await.unwrap();
+
+        assert!(replay.divergences().is_empty());
+        assert!(replay.is_finished());
+        assert_eq!(history.states.len(), config.steps_per_episode() - 1);
+        let expected = BipedSim::new(config, 0).run_episode(&mut NilPolicy).await.unwrap();
+        for (state, expected_state) in history.states.iter().zip(&expected.states) {
+            assert!((state.sensors_reading.floor_distance - expected_state.sensors_reading.floor_distance).abs() < 1e-5);
+        }
+    }
+
+    #[tokio::test]
+    async fn other_actions_are_reported() {
+        let config = config();
+        let mut replay = ReplayEnvironment::new(session(&config));
+        let mut policy = FnPolicy(|_: &GameState| {
+            let mut action = GameAction::default();
+            action.limbs_activation.left.thigh_activation = 0.5;
+            action
+        });
+        let history = replay.run_episode(&mut policy).await.unwrap();
+
+        // the first action is not the policy's, it's picked before the first state is known
+        assert_eq!(history.states.len(), config.steps_per_episode() - 1);
+        assert_eq!(replay.divergences().len(), config.steps_per_episode() - 1);
+    }
+
+    #[tokio::test]
+    async fn replays_over_tcp() {
+        let config = config();
+        let addr = SocketAddr::from(([127, 0, 0, 1], 18093));
+        let connection = tokio::spawn(SimulationConnector::new().with_addr(addr).connect());
+        let replay = tokio::spawn(async move {
+            ReplaySimulator::connect(addr, ConnectionRole::Connect, session(&config)).await.unwrap().run().await
+        });
+
+        let mut simulation = connection.await.unwrap().unwrap();
+        let history = simulation.run_episode(&mut NilPolicy).await.unwrap();
+        assert_eq!(history.states.len(), self::config().steps_per_episode() - 1);
+        assert!(replay.await.unwrap().unwrap().is_empty());
+    }
+}
@@ -6,15 +6,10 @@ use burn::{
 use itertools::Itertools;
 use nalgebra::{Quaternion, Transform, Vector2, Vector3};
 
-use crate::{
-    tools::UsedInTrait,
-    types::{
-        action::{BipedalLimbsActivation, GameAction, LimbActivation},
-        state::{
-            AccelerometerReading, BipedalLimbsReading, Force, GameState, LimbReading, LinkReading, MotorReading, SensorsReading, TransformReading
-        },
-    },
-};
+use crate::tools::UsedInTrait;
+
+pub use walking_robot_brain_macros::TensorConvertible;
+
 pub const FORCES_COUNT:usize = 20;
 
 #[derive(Debug, Clone, Copy, Default)]
@@ -31,6 +26,11 @@ pub trait TensorConvertible: Sized {
     fn from_values(values: &[f32]) -> Self;
     fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a;
 
+    /// Pushes the dotted names of the values, in the order of `iterate_values`, each starting with `prefix`.
+    fn value_names(prefix: &str, names: &mut Vec<String>) {
+        names.extend((0..Self::VALUES_COUNT).map(|i| format!("{prefix}[{i}]")));
+    }
+
     fn take_from_iter(iter: &mut impl Iterator<Item = f32>) -> Self {
         let vals = iter.take(Self::VALUES_COUNT).collect_vec();
         if vals.len() == Self::VALUES_COUNT {
@@ -104,6 +104,10 @@ impl TensorConvertible for Quaternion<f32>{
         ]
         .into_iter()
     }
+
+    fn value_names(prefix: &str, names: &mut Vec<String>) {
+        names.extend(["w", "i", "j", "k"].map(|component| join_name(prefix, component)));
+    }
 }
 impl TensorConvertible for f32{
     const VALUES_COUNT: usize = 1;
@@ -115,40 +119,9 @@ impl TensorConvertible for f32{
     fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
         std::iter::once(*self)
     }
-}
 
-impl TensorConvertible for GameAction {
-    const VALUES_COUNT: usize = 6;
-
-    fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
-        let left = &self.limbs_activation.left;
-        let right = &self.limbs_activation.right;
-        [
-            left.shoulder_activation,
-            left.thigh_activation,
-            left.shin_activation,
-            right.shoulder_activation,
-            right.thigh_activation,
-            right.shin_activation,
-        ]
-        .into_iter()
-    }
-
-    fn from_values(values: &[f32]) -> Self {
-        Self {
-            limbs_activation: BipedalLimbsActivation {
-                left: LimbActivation {
-                    shoulder_activation: values[0],
-                    thigh_activation: values[1],
-                    shin_activation: values[2],
-                },
-                right: LimbActivation {
-                    shoulder_activation: values[3],
-                    thigh_activation: values[4],
-                    shin_activation: values[5],
-                },
-            },
-        }
+    fn value_names(prefix: &str, names: &mut Vec<String>) {
+        names.push(prefix.to_string());
     }
 }
 
@@ -168,225 +141,253 @@ where
 }
 
 
-impl TensorConvertible for GameState {
-    const VALUES_COUNT: usize = SensorsReading::VALUES_COUNT + BipedalLimbsReading::VALUES_COUNT;
-    fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
-        self.sensors_reading
-            .iterate_values()
-            .chain(self.limbs_readings.iterate_values())
-    }
-
-    fn from_values(values: &[f32]) -> Self {
-        let mut values_iter = values.iter().cloned();
-        Self {
-            sensors_reading: SensorsReading::take_from_iter(&mut values_iter),
-            limbs_readings: BipedalLimbsReading::take_from_iter(&mut values_iter),
-        }
-    }
+/// Item type of the `Vec`s that `#[tensor(pad = ..)]` applies to.
+pub trait PaddedItems {
+    type Item: TensorConvertible;
+}
+impl<T: TensorConvertible> PaddedItems for Vec<T> {
+    type Item = T;
 }
 
-impl TensorConvertible for SensorsReading{
-    const VALUES_COUNT: usize = 
-    	Vector3::VALUES_COUNT + 
-        1 +
-        AccelerometerReading::VALUES_COUNT +
-        FORCES_COUNT * Force::VALUES_COUNT
+/// Values of `items` padded up to `len` items for `#[tensor(pad = ..)]`, the padding goes first and items past `len`
+/// are left out.
+pub fn padded_values<T: TensorConvertible>(items: &[T], len: usize) -> impl Iterator<Item = f32> + '_ {
+    std::iter::repeat_n(0f32, len.saturating_sub(items.len()) * T::VALUES_COUNT)
+        .chain(items.iter().take(len).flat_map(|item| item.iterate_values()))
+}
 
-        ;
+/// Like `padded_values`, but when there are more than `len` items the `len` ones with the largest `key` are the ones
+/// kept, in their order.
+pub fn padded_values_keeping<T: TensorConvertible>(items: &[T], len: usize, key: impl Fn(&T) -> f32) -> impl Iterator<Item = f32> + '_ {
+    let mut kept = (0..items.len()).collect::<Vec<_>>();
+    if items.len() > len {
+        // ties go to the first ones
+        kept.sort_by(|&a, &b| key(&items[b]).total_cmp(&key(&items[a])).then(a.cmp(&b)));
+        kept.truncate(len);
+        kept.sort_unstable();
+    }
+    std::iter::repeat_n(0f32, len.saturating_sub(items.len()) * T::VALUES_COUNT)
+        .chain(kept.into_iter().flat_map(|index| items[index].iterate_values()))
+}
 
-    fn from_values(values: &[f32]) -> Self {
-        let mut values_iter = values.iter().cloned();
-        SensorsReading{
-            target_pos      : Vector3::take_from_iter(&mut values_iter),
-            floor_distance  : values_iter.next().unwrap(),
-            acc_reading     : AccelerometerReading::take_from_iter(&mut values_iter),
-            forces          : {
-                let default = Force::default();
-                let mut forces = Vec::new();
-                for _ in 0..(FORCES_COUNT){
-                    let force = Force::take_from_iter(&mut values_iter);
-                    if force != default{
-                        forces.push(force);
-                    }
-                }
-                forces
-            }
-        }
+/// Names of the padded items, by slot rather than by item since the padding comes first.
+pub fn padded_value_names<T: TensorConvertible>(prefix: &str, len: usize, names: &mut Vec<String>) {
+    for slot in 0..len {
+        T::value_names(&format!("{prefix}[{slot}]"), names);
     }
+}
 
-    fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
-        let forces_iterator = 
-            std::iter::repeat_n(
-                0f32, 
-                (FORCES_COUNT - self.forces.len()) * Force::VALUES_COUNT 
-            )
-            .chain( 
-                self.forces.iter()
-                .map(|f| f.iterate_values())
-                .flatten()
-            );
-
-        self.target_pos.iterate_values()
-        .chain(self.floor_distance.iterate_values()) 
-        .chain(self.acc_reading.iterate_values())
-        .chain(forces_iterator)
+/// `prefix.name`, or `name` alone at the root.
+pub fn join_name(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{prefix}.{name}")
     }
 }
-impl TensorConvertible for AccelerometerReading {
-    const VALUES_COUNT: usize = 5 * 3;
 
-    fn from_values(values: &[f32]) -> Self {
-        let mut values_iter = values.iter().cloned();
-        Self{
-            up              : Vector3::take_from_iter(&mut values_iter),
-            linear_speed    : Vector3::take_from_iter(&mut values_iter),
-            linear_acc      : Vector3::take_from_iter(&mut values_iter),
-            angular_speed   : Vector3::take_from_iter(&mut values_iter),
-            angular_acc     : Vector3::take_from_iter(&mut values_iter),
-        }
-    }
+/// Reads back what `padded_values` wrote, the padding is whatever is left at its default.
+pub fn take_padded<T: TensorConvertible + Default + PartialEq>(iter: &mut impl Iterator<Item = f32>, len: usize) -> Vec<T> {
+    let default = T::default();
+    (0..len)
+        .map(|_| T::take_from_iter(iter))
+        .filter(|item| *item != default)
+        .collect()
+}
+
+impl TensorConvertible for Vector2<f32> {
+    const VALUES_COUNT: usize = 2;
 
     fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
-        self.up.iterate_values()
-        .chain(self.linear_speed.iterate_values())
-        .chain(self.linear_acc.iterate_values())
-        .chain(self.angular_speed.iterate_values())
-        .chain(self.angular_acc.iterate_values())
+        [self.x, self.y].into_iter()
     }
-}
-impl TensorConvertible for Force{
-    const VALUES_COUNT: usize = 6;
 
     fn from_values(values: &[f32]) -> Self {
-        let mut values_iter = values.iter().cloned();
-        Force{
-            pos     : Vector3::take_from_iter(&mut values_iter),
-            force   : Vector3::take_from_iter(&mut values_iter),
-        }
+        Vector2::new(values[0], values[1])
     }
 
-    fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
-        self.pos.iterate_values().chain(self.force.iterate_values())
+    fn value_names(prefix: &str, names: &mut Vec<String>) {
+        names.extend(["x", "y"].map(|component| join_name(prefix, component)));
     }
 }
-impl TensorConvertible for BipedalLimbsReading {
-    const VALUES_COUNT: usize = 2 * <LimbReading as TensorConvertible>::VALUES_COUNT;
+impl TensorConvertible for Vector3<f32> {
+    const VALUES_COUNT: usize = 3;
+
+    fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
+        [self.x, self.y, self.z].into_iter()
+    }
 
     fn from_values(values: &[f32]) -> Self {
-        let mut values_iter = values.iter().cloned();
-        Self {
-            left: LimbReading::take_from_iter(&mut values_iter),
-            right: LimbReading::take_from_iter(&mut values_iter),
-        }
+        Vector3::new(values[0], values[1], values[2])
     }
 
-    fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
-        self.left
-            .iterate_values()
-            .chain(self.right.iterate_values())
+    fn value_names(prefix: &str, names: &mut Vec<String>) {
+        names.extend(["x", "y", "z"].map(|component| join_name(prefix, component)));
     }
 }
-impl TensorConvertible for LimbReading {
-    const VALUES_COUNT: usize = 3 * LinkReading::VALUES_COUNT + TransformReading::VALUES_COUNT;
 
-    fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
-        self.shoulder.iterate_values().chain(self.thigh.iterate_values()).chain(self.shin.iterate_values()).chain(self.foot.iterate_values())
+#[cfg(test)]
+mod test {
+    use std::fmt::Debug;
+
+    use nalgebra::{Quaternion, Vector3};
+    use proptest::{collection::vec, prelude::*};
+
+    use crate::types::{
+        action::{BipedalLimbsActivation, GameAction, LimbActivation},
+        state::{
+            AccelerometerReading, BipedalLimbsReading, Force, GameState, LimbReading, LinkReading, MotorReading, SensorsReading, TransformReading
+        },
+    };
+
+    use super::{TensorConvertible, FORCES_COUNT};
+
+    fn value() -> impl Strategy<Value = f32> {
+        -1e3f32..1e3
     }
 
-    fn from_values(values: &[f32]) -> Self {
-        let mut values_iter = values.iter().cloned();
-        Self {
-            shoulder: LinkReading::take_from_iter(&mut values_iter),
-            thigh   : LinkReading::take_from_iter(&mut values_iter), 
-            shin: LinkReading::take_from_iter(&mut values_iter),
-            foot: TransformReading::take_from_iter(&mut values_iter),
-        }
+    fn vector3() -> impl Strategy<Value = Vector3<f32>> {
+        (value(), value(), value()).prop_map(|(x, y, z)| Vector3::new(x, y, z))
     }
-}
 
-impl TensorConvertible for LinkReading{
-    const VALUES_COUNT: usize = MotorReading::VALUES_COUNT + TransformReading::VALUES_COUNT;
+    fn quaternion() -> impl Strategy<Value = Quaternion<f32>> {
+        (value(), value(), value(), value()).prop_map(|(w, i, j, k)| Quaternion::new(w, i, j, k))
+    }
 
-    fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
-        self.motor.iterate_values().chain(self.transform.iterate_values())
+    fn motor() -> impl Strategy<Value = MotorReading> {
+        (value(), value(), value(), value()).prop_map(|(pos, speed, acc, torque)| MotorReading { pos, speed, acc, torque })
     }
 
-    fn from_values(values: &[f32]) -> Self {
-        let mut values_iter = values.iter().cloned(); 
-        LinkReading{
-            motor     : MotorReading::take_from_iter(&mut values_iter),
-            transform: TransformReading::take_from_iter(&mut values_iter),
-        } 
+    fn transform() -> impl Strategy<Value = TransformReading> {
+        (vector3(), vector3(), vector3(), quaternion(), vector3(), vector3()).prop_map(
+            |(linear_pos, linear_speed, linear_acc, angular_pos, angular_speed, angular_acc)| TransformReading {
+                linear_pos, linear_speed, linear_acc, angular_pos, angular_speed, angular_acc,
+            },
+        )
     }
-}
 
-impl TensorConvertible for MotorReading{
-    const VALUES_COUNT: usize = 4;
+    fn link() -> impl Strategy<Value = LinkReading> {
+        (motor(), transform()).prop_map(|(motor, transform)| LinkReading { motor, transform })
+    }
 
-    fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
-        [
-            self.pos,
-            self.speed,
-            self.acc,
-            self.torque
-        ]
-        .into_iter()
+    fn limb() -> impl Strategy<Value = LimbReading> {
+        (link(), link(), link(), transform()).prop_map(|(shoulder, thigh, shin, foot)| LimbReading { shoulder, thigh, shin, foot })
     }
 
-    fn from_values(values: &[f32]) -> Self {
-        MotorReading{
-            pos     : values[0],
-            speed   : values[1],
-            acc     : values[2],
-            torque  : values[3],
-        } 
+    fn limbs() -> impl Strategy<Value = BipedalLimbsReading> {
+        (limb(), limb()).prop_map(|(left, right)| BipedalLimbsReading { left, right })
     }
-}
-impl TensorConvertible for TransformReading{
-    const VALUES_COUNT: usize = 5 * 3 + 4;
 
-    fn from_values(values: &[f32]) -> Self {
-        let mut values_iter = values.iter().cloned();
-        TransformReading{
-            linear_pos      : Vector3::take_from_iter(&mut values_iter),
-            linear_speed    : Vector3::take_from_iter(&mut values_iter), 
-            linear_acc      : Vector3::take_from_iter(&mut values_iter), 
-
-            angular_pos     : Quaternion::take_from_iter(&mut values_iter),
-            angular_speed   : Vector3::take_from_iter(&mut values_iter), 
-            angular_acc     : Vector3::take_from_iter(&mut values_iter),  
-        }
+    fn accelerometer() -> impl Strategy<Value = AccelerometerReading> {
+        (vector3(), vector3(), vector3(), vector3(), vector3()).prop_map(
+            |(up, linear_speed, linear_acc, angular_speed, angular_acc)| AccelerometerReading {
+                up, linear_speed, linear_acc, angular_speed, angular_acc,
+            },
+        )
     }
 
-    fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
-        self.linear_pos             .iterate_values()
-        .chain(self.linear_speed    .iterate_values())
-        .chain(self.linear_acc      .iterate_values())
-        .chain(self.angular_pos     .iterate_values())
-        .chain(self.angular_speed   .iterate_values())
-        .chain(self.angular_acc     .iterate_values())
+    // a force that is all zeros can't be told apart from the padding
+    fn force() -> impl Strategy<Value = Force> {
+        (vector3(), vector3())
+            .prop_map(|(pos, force)| Force { pos, force })
+            .prop_filter("not padding", |force| *force != Force::default())
     }
-}
 
-impl TensorConvertible for Vector2<f32> {
-    const VALUES_COUNT: usize = 2;
+    fn sensors() -> impl Strategy<Value = SensorsReading> {
+        (vector3(), value(), accelerometer(), vec(force(), 0..=FORCES_COUNT)).prop_map(
+            |(target_pos, floor_distance, acc_reading, forces)| SensorsReading { target_pos, floor_distance, acc_reading, forces },
+        )
+    }
 
-    fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
-        [self.x, self.y].into_iter()
+    fn game_state() -> impl Strategy<Value = GameState> {
+        (sensors(), limbs()).prop_map(|(sensors_reading, limbs_readings)| GameState { sensors_reading, limbs_readings })
     }
 
-    fn from_values(values: &[f32]) -> Self {
-        Vector2::new(values[0], values[1])
+    fn action() -> impl Strategy<Value = GameAction> {
+        let limb = || (value(), value(), value()).prop_map(|(shoulder_activation, thigh_activation, shin_activation)| {
+            LimbActivation { shoulder_activation, thigh_activation, shin_activation }
+        });
+        (limb(), limb()).prop_map(|(left, right)| GameAction { limbs_activation: BipedalLimbsActivation { left, right } })
     }
-}
-impl TensorConvertible for Vector3<f32> {
-    const VALUES_COUNT: usize = 3;
 
-    fn iterate_values<'a>(&'a self) -> impl Iterator<Item = f32> + 'a {
-        [self.x, self.y, self.z].into_iter()
+    fn round_trips<T: TensorConvertible + PartialEq + Debug>(value: &T) -> Result<(), TestCaseError> {
+        let values = value.iterate_values().collect::<Vec<_>>();
+        prop_assert_eq!(values.len(), T::VALUES_COUNT);
+        prop_assert_eq!(&T::from_values(&values), value);
+        Ok(())
     }
 
-    fn from_values(values: &[f32]) -> Self {
-        Vector3::new(values[0], values[1], values[2])
+    proptest! {
+        #[test]
+        fn motor_readings_round_trip(reading in motor()) { round_trips(&reading)?; }
+
+        #[test]
+        fn transform_readings_round_trip(reading in transform()) { round_trips(&reading)?; }
+
+        #[test]
+        fn link_readings_round_trip(reading in link()) { round_trips(&reading)?; }
+
+        #[test]
+        fn limb_readings_round_trip(reading in limb()) { round_trips(&reading)?; }
+
+        #[test]
+        fn limbs_readings_round_trip(reading in limbs()) { round_trips(&reading)?; }
+
+        #[test]
+        fn accelerometer_readings_round_trip(reading in accelerometer()) { round_trips(&reading)?; }
+
+        #[test]
+        fn forces_round_trip(force in force()) { round_trips(&force)?; }
+
+        #[test]
+        fn sensors_readings_round_trip(reading in sensors()) { round_trips(&reading)?; }
+
+        #[test]
+        fn game_states_round_trip(state in game_state()) { round_trips(&state)?; }
+
+        #[test]
+        fn actions_round_trip(action in action()) { round_trips(&action)?; }
+
+        // the weakest forces past `FORCES_COUNT` are dropped rather than shifting everything after them
+        #[test]
+        fn extra_forces_are_dropped(mut reading in sensors(), extra in vec(force(), 1..4)) {
+            reading.forces.resize(FORCES_COUNT, Force { pos: Vector3::x(), force: Vector3::y() });
+            let kept = reading.clone();
+            // weaker than any of the others, wherever they come
+            let weak = extra.into_iter().map(|force| Force { force: Vector3::y() * 0.5, ..force });
+            reading.forces.splice(0..0, weak);
+            prop_assert_eq!(reading.iterate_values().count(), SensorsReading::VALUES_COUNT);
+            prop_assert_eq!(SensorsReading::from_values(&reading.iterate_values().collect::<Vec<_>>()), kept);
+        }
+    }
+
+    #[test]
+    fn layout_is_unchanged() {
+        // the order the models were trained with and the binary wire format rely on
+        assert_eq!(GameAction::VALUES_COUNT, 6);
+        assert_eq!(TransformReading::VALUES_COUNT, 5 * 3 + 4);
+        assert_eq!(SensorsReading::VALUES_COUNT, 3 + 1 + 5 * 3 + FORCES_COUNT * 6);
+        assert_eq!(GameState::VALUES_COUNT, SensorsReading::VALUES_COUNT + 2 * (3 * (4 + 19) + 19));
+
+        let reading = SensorsReading {
+            target_pos      : Vector3::new(1.0, 2.0, 3.0),
+            floor_distance  : 4.0,
+            acc_reading     : AccelerometerReading {
+                up              : Vector3::repeat(5.0),
+                linear_speed    : Vector3::zeros(),
+                linear_acc      : Vector3::zeros(),
+                angular_speed   : Vector3::zeros(),
+                angular_acc     : Vector3::zeros(),
+            },
+            forces          : vec![Force { pos: Vector3::repeat(6.0), force: Vector3::repeat(7.0) }],
+        };
+        let values = reading.iterate_values().collect::<Vec<_>>();
+        assert_eq!(values[..7], [1.0, 2.0, 3.0, 4.0, 5.0, 5.0, 5.0]);
+        // forces are padded at the front
+        assert!(values[19..values.len() - 6].iter().all(|&value| value == 0.0));
+        assert_eq!(values[values.len() - 6..], [6.0; 3].into_iter().chain([7.0; 3]).collect::<Vec<_>>()[..]);
+
+        let quaternion = Quaternion::new(1.0, 2.0, 3.0, 4.0);
+        assert_eq!(quaternion.iterate_values().collect::<Vec<_>>(), [1.0, 2.0, 3.0, 4.0]);
     }
 }
@@ -1,6 +1,10 @@
 use rand::{distr::uniform::{UniformFloat, UniformSampler}, Rng};
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, PartialEq, Debug, Default)]
+use crate::tensor_conversion::TensorConvertible;
+
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize, TensorConvertible)]
+#[serde(rename_all = "PascalCase")]
 pub struct GameAction {
     pub limbs_activation: BipedalLimbsActivation,
 }
@@ -26,7 +30,8 @@ impl GameAction {
     }
 }
 
-#[derive(Clone, PartialEq, Debug, Default)]
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize, TensorConvertible)]
+#[serde(rename_all = "PascalCase")]
 pub struct BipedalLimbsActivation {
     pub left: LimbActivation,
     pub right: LimbActivation,
@@ -45,9 +50,12 @@ impl BipedalLimbsActivation {
     }
 }
 
-#[derive(Clone, PartialEq, Debug, Default)]
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize, TensorConvertible)]
 pub struct LimbActivation {
+    #[serde(rename = "Shoulder")]
     pub shoulder_activation: f32,
+    #[serde(rename = "Thigh")]
     pub thigh_activation: f32,
+    #[serde(rename = "Shin")]
     pub shin_activation: f32,
 }
@@ -0,0 +1,26 @@
+use std::{convert::Infallible, future::Future};
+
+use super::{action::GameAction, state::{GameState, Reward, StepFlags}};
+
+/// Anything an episode can be played against: the Unity scene over TCP, a replay or an in-process simulation.
+pub trait Environment{
+	type Error: EnvironmentError;
+
+	/// Waits for a fresh episode and returns its first state.
+	fn reset(&mut self) -> impl Future<Output = Result<GameState, Self::Error>>;
+
+	/// Applies `action` and returns the next state and the reward for reaching it. A `terminal` step is the last
+	/// one of its episode, a `truncated` one means the episode ended before that step could be taken.
+	fn step(&mut self, action: &GameAction) -> impl Future<Output = Result<(GameState, Reward, StepFlags), Self::Error>>;
+}
+
+pub trait EnvironmentError: std::error::Error{
+	/// The environment went away, the episode in progress is lost but `reset` can wait for it to come back.
+	fn is_disconnect(&self) -> bool;
+}
+
+impl EnvironmentError for Infallible{
+	fn is_disconnect(&self) -> bool {
+		match *self {}
+	}
+}
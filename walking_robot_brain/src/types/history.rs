@@ -1,6 +1,6 @@
 use burn::{prelude::Backend, tensor::Tensor};
 
-use crate::tensor_conversion::TensorConvertibleIterExts;
+use crate::tensor_conversion::{TensorConvertible, TensorConvertibleIterExts};
 
 use super::{action::GameAction, state::{GameState, Reward}};
 
@@ -8,7 +8,13 @@ use super::{action::GameAction, state::{GameState, Reward}};
 pub struct History {
 	pub states : Vec<GameState>	,
 	pub actions: Vec<GameAction>, 
-	pub rewards: Vec<Reward>
+	pub rewards: Vec<Reward>,
+	// whether the state reached by each step ends the episode, nothing is to be expected past those
+	pub terminals: Vec<bool>,
+	// the episode stopped without reaching a terminal state, out of time or because the environment went away
+	pub truncated: bool,
+	// the episode ended on the target
+	pub success: bool,
 }
 
 impl History{
@@ -16,7 +22,8 @@ impl History{
 		TensorHistory{
 			actions	: self.actions.iter().many_to_tensor(dev),
 			states	: self.states.iter().many_to_tensor(dev),
-			rewards	: self.rewards.iter().many_to_tensor(dev)
+			rewards	: self.rewards.iter().many_to_tensor(dev),
+			terminals: self.terminals.iter().map(|&terminal| if terminal { 1.0 } else { 0.0 }).collect::<Vec<f32>>().iter().many_to_tensor(dev),
 		}
 	}
 }
@@ -24,7 +31,22 @@ impl History{
 pub struct TensorHistory<B: Backend>{
 	pub states 			: Tensor<B, 2>,
 	pub actions			: Tensor<B, 2>,
-	pub rewards			: Tensor<B, 2>
+	pub rewards			: Tensor<B, 2>,
+	// 1 where the step reached a terminal state, 0 elsewhere
+	pub terminals		: Tensor<B, 2>,
+}
+
+impl<B: Backend> TensorHistory<B>{
+	/// Back to the steps, the tensors don't tell whether the episode was truncated or a success.
+	pub fn to_history(&self) -> History{
+		History{
+			states	: GameState::many_from_tensor(self.states.clone()),
+			actions	: GameAction::many_from_tensor(self.actions.clone()),
+			rewards	: Reward::many_from_tensor(self.rewards.clone()),
+			terminals: f32::many_from_tensor(self.terminals.clone()).into_iter().map(|terminal| terminal > 0.5).collect(),
+			..Default::default()
+		}
+	}
 }
 
 pub type HistoryStep 	= (GameState		, GameAction	 , Reward);
\ No newline at end of file
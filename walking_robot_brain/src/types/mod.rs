@@ -3,4 +3,10 @@ pub mod state;
 pub mod history;
 pub mod policy;
 pub mod sa_tensor_tree;
-pub mod tensor_types;
\ No newline at end of file
+pub mod tensor_types;
+pub mod environment;
+pub mod protocol;
+pub mod unity_serde;
+pub mod replay_buffer;
+pub mod sum_tree;
+pub mod prioritized_replay;
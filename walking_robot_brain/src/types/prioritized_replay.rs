@@ -0,0 +1,222 @@
+use burn::{config::Config, prelude::Backend, tensor::Tensor};
+use rand::Rng;
+
+use crate::models::builders::WINDOW_SIZE;
+
+use super::{
+    history::{History, TensorHistory},
+    replay_buffer::{ReplayBatch, ReplayBuffer},
+    sum_tree::SumTree,
+};
+
+#[derive(Config, Debug)]
+pub struct PrioritizedReplayConfig {
+    // how much the priorities count, 0 samples uniformly
+    #[config(default = 0.6)]
+    pub alpha           : f32,
+    // how much the importance sampling weights make up for the priorities, usually annealed up to 1
+    #[config(default = 0.4)]
+    pub beta            : f32,
+    // keeps the transitions that are already learnt in the draw
+    #[config(default = 1e-3)]
+    pub epsilon         : f32,
+    // leave out the terminal transitions, for the models that learn the next state
+    #[config(default = false)]
+    pub continuing_only : bool,
+}
+
+impl PrioritizedReplayConfig {
+    pub fn init(&self, capacity: usize) -> PrioritizedReplayBuffer {
+        PrioritizedReplayBuffer {
+            buffer      : ReplayBuffer::new(capacity),
+            priorities  : SumTree::new(capacity),
+            sampleable  : 0,
+            max_priority: 1.0,
+            config      : self.clone(),
+        }
+    }
+}
+
+/// A `ReplayBuffer` drawing transitions in proportion to their priority, the error the models last made on them
+/// raised to `alpha`. New transitions get the highest priority seen so far so that they are drawn at least once.
+pub struct PrioritizedReplayBuffer {
+    buffer      : ReplayBuffer,
+    // by step id modulo the capacity, 0 for the steps that can't be drawn
+    priorities  : SumTree,
+    sampleable  : usize,
+    max_priority: f64,
+    config      : PrioritizedReplayConfig,
+}
+
+/// Transitions drawn from a `PrioritizedReplayBuffer`, the batch has its importance sampling weights.
+pub struct PrioritizedBatch<B: Backend> {
+    pub batch       : ReplayBatch<B>,
+    // the ids of the transitions, by row, to report their errors with
+    pub transitions : Vec<u64>,
+}
+
+impl PrioritizedReplayBuffer {
+    pub fn buffer(&self) -> &ReplayBuffer {
+        &self.buffer
+    }
+
+    pub fn config(&self) -> &PrioritizedReplayConfig {
+        &self.config
+    }
+
+    pub fn set_beta(&mut self, beta: f32) {
+        self.config.beta = beta;
+    }
+
+    /// How many transitions can be drawn.
+    pub fn sampleable(&self) -> usize {
+        self.sampleable
+    }
+
+    pub fn priority(&self, id: u64) -> Option<f64> {
+        self.buffer.position_of(id).map(|_| self.priorities.get(self.slot(id)))
+    }
+
+    fn slot(&self, id: u64) -> usize {
+        (id % self.buffer.capacity() as u64) as usize
+    }
+
+    fn can_draw(&self, position: usize) -> bool {
+        self.buffer.is_transition(position) && (!self.config.continuing_only || self.buffer.has_next(position))
+    }
+
+    fn set_priority(&mut self, slot: usize, priority: f64) {
+        match (self.priorities.get(slot) > 0.0, priority > 0.0) {
+            (false, true) => self.sampleable += 1,
+            (true, false) => self.sampleable -= 1,
+            _ => {}
+        }
+        self.priorities.set(slot, priority);
+    }
+
+    pub fn push(&mut self, history: &History) {
+        self.buffer.push(history);
+        let len = self.buffer.len();
+        // the new steps took the slots of the ones that went, and those that went took windows with them
+        let pushed = history.states.len().min(len);
+        let front = (WINDOW_SIZE as usize - 1).min(len - pushed);
+        for position in (0..front).chain(len - pushed..len) {
+            let slot = self.slot(self.buffer.id_of(position));
+            let priority = if self.can_draw(position) { self.max_priority } else { 0.0 };
+            self.set_priority(slot, priority);
+        }
+    }
+
+    pub fn push_tensor_history<B: Backend>(&mut self, history: &TensorHistory<B>) {
+        self.push(&history.to_history());
+    }
+
+    /// `batch_size` transitions drawn in proportion to their priorities, one in each of `batch_size` equal stretches
+    /// of the total. `None` while there are none.
+    pub fn sample<B: Backend>(&self, batch_size: usize, rng: &mut impl Rng, dev: &<B as Backend>::Device) -> Option<PrioritizedBatch<B>> {
+        let total = self.priorities.total();
+        if self.sampleable == 0 || total <= 0.0 {
+            return None;
+        }
+        let mut positions = Vec::with_capacity(batch_size);
+        let mut transitions = Vec::with_capacity(batch_size);
+        let mut weights = Vec::with_capacity(batch_size);
+        for stretch in 0..batch_size {
+            let mass = (stretch as f64 + rng.random::<f64>()) * total / batch_size as f64;
+            let slot = self.priorities.find(mass);
+            let capacity = self.buffer.capacity() as u64;
+            let first = self.buffer.id_of(0);
+            // the kept step in that slot, ids go around the slots
+            let id = first + (slot as u64 + capacity - first % capacity) % capacity;
+            let position = self.buffer.position_of(id).expect("a drawn slot holds a kept step");
+            let probability = self.priorities.get(slot) / total;
+            positions.push(position);
+            transitions.push(id);
+            weights.push((self.sampleable as f64 * probability).powf(-self.config.beta as f64));
+        }
+        // only ever scaled down, the largest weight is 1
+        let largest = weights.iter().copied().fold(f64::MIN_POSITIVE, f64::max);
+        let weights = weights.into_iter().map(|weight| (weight / largest) as f32).collect::<Vec<_>>();
+
+        let mut batch = self.buffer.batch(&positions, dev);
+        batch.weights = Some(Tensor::<B, 1>::from_floats(weights.as_slice(), dev).unsqueeze_dim(1));
+        Some(PrioritizedBatch { batch, transitions })
+    }
+
+    /// New priorities from the errors the models made on the transitions of a batch, like the per sample absolute
+    /// errors the training procedures return, not their squares. Transitions that are gone since are skipped.
+    pub fn update_priorities(&mut self, transitions: &[u64], errors: &[f32]) {
+        assert_eq!(transitions.len(), errors.len(), "one error per transition");
+        for (&id, &error) in transitions.iter().zip(errors) {
+            if self.priority(id).is_none_or(|priority| priority <= 0.0) || !error.is_finite() {
+                continue;
+            }
+            let priority = (error.abs() as f64 + self.config.epsilon as f64).powf(self.config.alpha as f64);
+            self.max_priority = self.max_priority.max(priority);
+            self.set_priority(self.slot(id), priority);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use burn::backend::NdArray;
+
+    use crate::{
+        models::builders::WINDOW_SIZE,
+        procedures::run_simulation::RunEpisodeExt,
+        simulation::biped::{BipedSim, BipedSimConfig},
+        types::{history::History, policy::nil_policy::NilPolicy},
+    };
+
+    use super::PrioritizedReplayConfig;
+
+    type B = NdArray;
+
+    async fn episode(seed: u64) -> History {
+        let config = BipedSimConfig { episode_duration: 2.0, ..Default::default() };
+        BipedSim::new(config, seed).run_episode(&mut NilPolicy).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn high_errors_are_drawn_more() {
+        let dev = Default::default();
+        let mut rng = rand::rng();
+        let mut replay = PrioritizedReplayConfig::new().with_alpha(1.0).with_epsilon(0.0).init(1000);
+        replay.push(&episode(0).await);
+        assert_eq!(replay.sampleable(), replay.buffer().transition_count());
+
+        // every transition once, one of them made a hundred times the error of the others
+        let ids = (0..replay.buffer().len()).filter(|&position| replay.buffer().is_transition(position)).map(|position| replay.buffer().id_of(position)).collect::<Vec<_>>();
+        let errors = ids.iter().enumerate().map(|(index, _)| if index == 0 { 100.0 } else { 1.0 }).collect::<Vec<_>>();
+        replay.update_priorities(&ids, &errors);
+
+        let sample = replay.sample::<B>(64, &mut rng, &dev).unwrap();
+        let drawn = sample.transitions.iter().filter(|&&id| id == ids[0]).count();
+        let share = 100.0 / (100.0 + ids.len() as f32 - 1.0);
+        assert!((drawn as f32 - 64.0 * share).abs() <= 2.0, "drawn {drawn} times");
+
+        // the weights make up for it, the most drawn counts the least
+        let weights = sample.batch.weights.unwrap().into_data().to_vec::<f32>().unwrap();
+        let (high, low) = sample.transitions.iter().zip(&weights).partition::<Vec<_>, _>(|(&id, _)| id == ids[0]);
+        assert!(high.iter().all(|(_, &weight)| weight < 0.5));
+        assert!(low.iter().all(|(_, &weight)| weight == 1.0));
+    }
+
+    #[tokio::test]
+    async fn evicted_transitions_are_not_drawn() {
+        let dev = Default::default();
+        let first = episode(0).await;
+        let window = WINDOW_SIZE as usize;
+        let mut replay = PrioritizedReplayConfig::new().with_continuing_only(true).init(first.states.len() + window);
+        replay.push(&first);
+        let old = replay.buffer().id_of(window - 1);
+        replay.push(&episode(1).await);
+        assert_eq!(replay.sampleable(), replay.buffer().transition_count());
+        assert_eq!(replay.priority(old), None);
+
+        replay.update_priorities(&[old], &[1000.0]);
+        let sample = replay.sample::<B>(256, &mut rand::rng(), &dev).unwrap();
+        assert!(sample.transitions.iter().all(|&id| replay.buffer().position_of(id).is_some_and(|position| replay.buffer().is_transition(position))));
+    }
+}
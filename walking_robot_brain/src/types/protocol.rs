@@ -0,0 +1,104 @@
+use clap::ValueEnum;
+use nalgebra::Vector3;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    tensor_conversion::{TensorConvertible, FORCES_COUNT},
+    types::{action::GameAction, unity_serde::vector3},
+};
+
+/// Bumped whenever the messages exchanged with the simulation change.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// First message of the simulation on a new connection, telling what it is going to send and expects back.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "Type", rename = "Hello", rename_all = "PascalCase")]
+pub struct SimulationHello {
+    pub protocol_version    : u32,
+    // most contact forces a state can carry
+    pub forces_count        : usize,
+    pub action_values_count : usize,
+    pub control_period      : f32,
+    pub episode_duration    : f32,
+    // formats the simulation can send states and read actions in, only json when it doesn't say
+    #[serde(default = "json_only")]
+    pub wire_formats        : Vec<WireFormat>,
+}
+
+impl SimulationHello {
+    /// What a simulation matching this build of the brain says.
+    pub fn expected(control_period: f32, episode_duration: f32) -> Self {
+        Self {
+            protocol_version    : PROTOCOL_VERSION,
+            forces_count        : FORCES_COUNT,
+            action_values_count : GameAction::VALUES_COUNT,
+            control_period,
+            episode_duration,
+            wire_formats        : vec![WireFormat::Json, WireFormat::Binary],
+        }
+    }
+
+    pub fn check_compatible(&self) -> Result<(), String> {
+        if self.protocol_version != PROTOCOL_VERSION {
+            return Err(format!("protocol version is {} but the brain speaks {PROTOCOL_VERSION}", self.protocol_version));
+        }
+        // states are padded up to `FORCES_COUNT` forces, there is no room for more
+        if self.forces_count > FORCES_COUNT {
+            return Err(format!("up to {} forces are sent but the brain makes room for {FORCES_COUNT}", self.forces_count));
+        }
+        if self.action_values_count != GameAction::VALUES_COUNT {
+            return Err(format!(
+                "actions have {} values but the brain produces {}", 
+                self.action_values_count, 
+                GameAction::VALUES_COUNT
+            ));
+        }
+        Ok(())
+    }
+
+    /// `preferred` when the simulation knows it, json otherwise.
+    pub fn negotiate(&self, preferred: WireFormat) -> WireFormat {
+        if self.wire_formats.contains(&preferred) {
+            preferred
+        } else {
+            WireFormat::Json
+        }
+    }
+}
+
+fn json_only() -> Vec<WireFormat> {
+    vec![WireFormat::Json]
+}
+
+/// How states and actions are encoded once the handshake is done. The handshake, the episode boundaries and the
+/// commands are always json.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, ValueEnum, Serialize, Deserialize)]
+pub enum WireFormat {
+    #[default]
+    Json,
+    // little-endian f32s in the order of `TensorConvertible::iterate_values`, see `wire`
+    Binary,
+}
+
+/// Answer of the brain to a `SimulationHello`.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "Type", rename_all_fields = "PascalCase")]
+pub enum HelloReply {
+    // a brain that predates the negotiation doesn't say, and speaks json
+    Accept { #[serde(default)] wire_format: WireFormat },
+    Reject { reason: String },
+}
+
+/// Sent by the brain in place of an action, the simulation applies it and keeps waiting for the action.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(tag = "Type", rename_all_fields = "PascalCase")]
+pub enum SimulationCommand {
+    // ends the episode in progress, no action is expected for its last state
+    ResetEpisode,
+    // takes effect from the next episode, Unity folds it into the 32 bits it seeds with
+    SetSeed { seed: u64 },
+    SetTimeScale { time_scale: f32 },
+    SetEpisodeDuration { episode_duration: f32 },
+    // in scene coordinates, the target stays there in the episodes that follow
+    PlaceTarget { #[serde(with = "vector3")] position: Vector3<f32> },
+}
@@ -0,0 +1,344 @@
+use std::collections::VecDeque;
+
+use burn::{prelude::Backend, tensor::Tensor};
+use rand::Rng;
+
+use crate::{models::builders::WINDOW_SIZE, tensor_conversion::TensorConvertible};
+
+use super::{action::GameAction, history::{History, TensorHistory}, state::GameState};
+
+struct StoredStep {
+    state       : Vec<f32>,
+    action      : Vec<f32>,
+    reward      : f32,
+    terminal    : bool,
+    episode     : u64,
+    // position of the step in its episode
+    index       : usize,
+}
+
+/// The steps of the last episodes, up to `capacity` steps, the oldest go first. Every step with `WINDOW_SIZE - 1`
+/// steps of its episode before it and a step after it, or that is terminal, is a transition that can be sampled.
+pub struct ReplayBuffer {
+    capacity    : usize,
+    steps       : VecDeque<StoredStep>,
+    episodes    : u64,
+    // steps pushed so far, the ids of the kept ones are the last `len` below it
+    pushed      : u64,
+    // ids of the transitions, and of those with a next step, oldest first, kept along with the steps so sampling
+    // does not scan them
+    transitions : VecDeque<u64>,
+    continuing  : VecDeque<u64>,
+}
+
+impl ReplayBuffer {
+    pub fn new(capacity: usize) -> Self {
+        assert!(capacity > 0, "a replay buffer needs room for at least one step");
+        Self { capacity, steps: VecDeque::with_capacity(capacity), episodes: 0, pushed: 0, transitions: VecDeque::new(), continuing: VecDeque::new() }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// How many steps are kept.
+    pub fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    pub fn transition_count(&self) -> usize {
+        self.transitions.len()
+    }
+
+    pub fn push(&mut self, history: &History) {
+        let steps = history.states.iter().zip(&history.actions).zip(&history.rewards).zip(&history.terminals);
+        for (index, (((state, action), &reward), &terminal)) in steps.enumerate() {
+            self.push_step(StoredStep {
+                state   : state.iterate_values().collect(),
+                action  : action.iterate_values().collect(),
+                reward,
+                terminal,
+                episode : self.episodes,
+                index,
+            });
+        }
+        self.episodes += 1;
+    }
+
+    pub fn push_tensor_history<B: Backend>(&mut self, history: &TensorHistory<B>) {
+        self.push(&history.to_history());
+    }
+
+    fn push_step(&mut self, step: StoredStep) {
+        if self.steps.len() == self.capacity {
+            self.steps.pop_front();
+            // the windows that started with the dropped step go with it
+            let first_whole = self.id_of(0) + WINDOW_SIZE as u64 - 1;
+            for ids in [&mut self.transitions, &mut self.continuing] {
+                while ids.front().is_some_and(|&id| id < first_whole) {
+                    ids.pop_front();
+                }
+            }
+        }
+        self.steps.push_back(step);
+        self.pushed += 1;
+
+        // the previous step of the episode now has a next one, and a terminal step is a transition as it comes
+        let last = self.steps.len() - 1;
+        if last > 0 && self.has_next(last - 1) && self.is_transition(last - 1) {
+            let id = self.id_of(last - 1);
+            if !self.steps[last - 1].terminal {
+                self.transitions.push_back(id);
+            }
+            self.continuing.push_back(id);
+        }
+        if self.is_transition(last) {
+            self.transitions.push_back(self.id_of(last));
+        }
+    }
+
+    /// Identifies a step for as long as it is kept, unlike its position which moves as older steps go.
+    pub fn id_of(&self, position: usize) -> u64 {
+        self.pushed - self.steps.len() as u64 + position as u64
+    }
+
+    /// Where the step `id` is, `None` once it is gone.
+    pub fn position_of(&self, id: u64) -> Option<usize> {
+        let first = self.pushed - self.steps.len() as u64;
+        (first..self.pushed).contains(&id).then(|| (id - first) as usize)
+    }
+
+    pub(super) fn has_next(&self, position: usize) -> bool {
+        self.steps.get(position + 1).is_some_and(|next| next.episode == self.steps[position].episode)
+    }
+
+    // the oldest steps of an episode may be gone, taking the first windows with them
+    pub(super) fn is_transition(&self, position: usize) -> bool {
+        let before = WINDOW_SIZE as usize - 1;
+        let step = &self.steps[position];
+        step.index >= before
+            && position >= before
+            && self.steps[position - before].episode == step.episode
+            && (step.terminal || self.has_next(position))
+    }
+
+    #[cfg(test)]
+    fn transitions(&self) -> impl Iterator<Item = usize> + '_ {
+        self.transitions.iter().map(|&id| self.position_of(id).unwrap())
+    }
+
+    /// `batch_size` transitions drawn uniformly with replacement, `None` while there are none.
+    pub fn sample<B: Backend>(&self, batch_size: usize, rng: &mut impl Rng, dev: &<B as Backend>::Device) -> Option<ReplayBatch<B>> {
+        self.sample_from(&self.transitions, batch_size, rng, dev)
+    }
+
+    /// Like `sample` without the terminal transitions, for the models that learn the next state.
+    pub fn sample_continuing<B: Backend>(&self, batch_size: usize, rng: &mut impl Rng, dev: &<B as Backend>::Device) -> Option<ReplayBatch<B>> {
+        self.sample_from(&self.continuing, batch_size, rng, dev)
+    }
+
+    fn sample_from<B: Backend>(&self, ids: &VecDeque<u64>, batch_size: usize, rng: &mut impl Rng, dev: &<B as Backend>::Device) -> Option<ReplayBatch<B>> {
+        if ids.is_empty() {
+            return None;
+        }
+        let picked = (0..batch_size)
+            .map(|_| self.position_of(ids[rng.random_range(0..ids.len())]).unwrap())
+            .collect::<Vec<_>>();
+        Some(self.batch(&picked, dev))
+    }
+
+    pub(super) fn batch<B: Backend>(&self, transitions: &[usize], dev: &<B as Backend>::Device) -> ReplayBatch<B> {
+        let window = WINDOW_SIZE as usize;
+        let (mut states, mut actions, mut next_states, mut next_actions) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        let (mut rewards, mut dones) = (Vec::new(), Vec::new());
+        for &position in transitions {
+            let done = !self.has_next(position);
+            let first = position + 1 - window;
+            // a terminal transition has no next window, its own stands in for it
+            let next_first = if done { first } else { first + 1 };
+            for step in self.steps.range(first..first + window) {
+                states.extend_from_slice(&step.state);
+                actions.extend_from_slice(&step.action);
+            }
+            for step in self.steps.range(next_first..next_first + window) {
+                next_states.extend_from_slice(&step.state);
+                next_actions.extend_from_slice(&step.action);
+            }
+            rewards.push(self.steps[position].reward);
+            dones.push(if done { 1.0 } else { 0.0 });
+        }
+
+        let batch = transitions.len();
+        let tensor = |values: Vec<f32>, width: usize| Tensor::<B, 1>::from_floats(values.as_slice(), dev).reshape([batch, width]);
+        ReplayBatch {
+            states      : tensor(states, window * GameState::VALUES_COUNT),
+            actions     : tensor(actions, window * GameAction::VALUES_COUNT),
+            rewards     : tensor(rewards, 1),
+            next_states : tensor(next_states, window * GameState::VALUES_COUNT),
+            next_actions: tensor(next_actions, window * GameAction::VALUES_COUNT),
+            dones       : tensor(dones, 1),
+            weights     : None,
+        }
+    }
+}
+
+/// Transitions sampled from a `ReplayBuffer`, one per row. The windows are laid out like `WindowsExt::windows` lays
+/// them out, oldest step first.
+pub struct ReplayBatch<B: Backend> {
+    pub states      : Tensor<B, 2>,
+    pub actions     : Tensor<B, 2>,
+    pub rewards     : Tensor<B, 2>,
+    // the window one step later, the next action is the one that was taken
+    pub next_states : Tensor<B, 2>,
+    pub next_actions: Tensor<B, 2>,
+    // 1 where the step reached a terminal state, the next window is then the same as the window
+    pub dones       : Tensor<B, 2>,
+    // how much each transition counts in the loss, a prioritized sample has them to make up for its bias
+    pub weights     : Option<Tensor<B, 2>>,
+}
+
+impl<B: Backend> ReplayBatch<B> {
+    pub fn len(&self) -> usize {
+        self.rewards.dims()[0]
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// States and actions side by side, like the inputs of the `QEstimator` and the `RsEstimator`.
+    pub fn inputs(&self) -> Tensor<B, 2> {
+        Tensor::cat(vec![self.states.clone(), self.actions.clone()], 1)
+    }
+
+    pub fn next_inputs(&self) -> Tensor<B, 2> {
+        Tensor::cat(vec![self.next_states.clone(), self.next_actions.clone()], 1)
+    }
+
+    /// The state each action was taken from, the last of its window.
+    pub fn last_states(&self) -> Tensor<B, 2> {
+        last_of_window(&self.states, GameState::VALUES_COUNT)
+    }
+
+    pub fn last_actions(&self) -> Tensor<B, 2> {
+        last_of_window(&self.actions, GameAction::VALUES_COUNT)
+    }
+
+    /// The state each step reached, the last of the next window. Only meaningful for continuing transitions.
+    pub fn reached_states(&self) -> Tensor<B, 2> {
+        last_of_window(&self.next_states, GameState::VALUES_COUNT)
+    }
+
+    /// The reward and the state reached by each step, what the `RsEstimator` predicts.
+    pub fn rs_targets(&self) -> Tensor<B, 2> {
+        Tensor::cat(vec![self.rewards.clone(), self.reached_states()], 1)
+    }
+}
+
+fn last_of_window<B: Backend>(window: &Tensor<B, 2>, len: usize) -> Tensor<B, 2> {
+    let [batch, width] = window.dims();
+    window.clone().slice([0..batch, width - len..width])
+}
+
+#[cfg(test)]
+mod test {
+    use burn::{backend::NdArray, prelude::Tensor};
+
+    use crate::{
+        models::builders::WINDOW_SIZE,
+        procedures::{run_simulation::RunEpisodeExt, train::{q_estimator_monte_carlo::windowed_inputs, rs_estimator_train::training_windows}},
+        simulation::biped::{BipedSim, BipedSimConfig},
+        types::{history::History, policy::nil_policy::NilPolicy},
+    };
+
+    use super::ReplayBuffer;
+
+    type B = NdArray;
+
+    async fn episode(seed: u64) -> History {
+        let config = BipedSimConfig { episode_duration: 2.0, ..Default::default() };
+        BipedSim::new(config, seed).run_episode(&mut NilPolicy).await.unwrap()
+    }
+
+    fn rows(tensor: Tensor<B, 2>) -> Vec<Vec<f32>> {
+        let [_, width] = tensor.dims();
+        tensor.into_data().to_vec::<f32>().unwrap().chunks(width).map(<[f32]>::to_vec).collect()
+    }
+
+    #[tokio::test]
+    async fn batches_are_laid_out_like_the_histories() {
+        let dev = Default::default();
+        let history = episode(0).await;
+        let steps = history.states.len();
+        let mut buffer = ReplayBuffer::new(1000);
+        buffer.push(&history);
+        let window = WINDOW_SIZE as usize;
+        assert_eq!(buffer.transition_count(), steps - window);
+
+        let transitions = buffer.transitions().collect::<Vec<_>>();
+        let batch = buffer.batch::<B>(&transitions, &dev);
+        let tensors = history.to_tensor_history::<B>(&dev);
+        let (inputs, targets) = training_windows(&tensors.states, &tensors.actions, &tensors.rewards);
+        assert_eq!(rows(batch.inputs()), rows(inputs.clone()));
+        assert_eq!(rows(batch.rs_targets()), rows(targets));
+        assert_eq!(rows(batch.next_inputs()), rows(windowed_inputs(&tensors))[1..]);
+        assert!(rows(batch.dones).iter().all(|done| done == &[0.0]));
+        assert_eq!(buffer.sample::<B>(7, &mut rand::rng(), &dev).unwrap().len(), 7);
+    }
+
+    #[tokio::test]
+    async fn windows_stay_within_their_episode() {
+        let mut first = episode(0).await;
+        *first.terminals.last_mut().unwrap() = true;
+        let second = episode(1).await;
+        let window = WINDOW_SIZE as usize;
+        let mut buffer = ReplayBuffer::new(first.states.len() + second.states.len());
+        buffer.push(&first);
+        buffer.push(&second);
+        // the terminal step counts, the truncated one has nothing after it
+        let transitions = first.states.len() - window + 1 + second.states.len() - window;
+        assert_eq!(buffer.transition_count(), transitions);
+
+        let dev = Default::default();
+        let terminal = buffer.batch::<B>(&[first.states.len() - 1], &dev);
+        assert_eq!(rows(terminal.dones.clone()), [[1.0]]);
+        assert_eq!(rows(terminal.next_inputs()), rows(terminal.inputs()));
+        assert!(buffer.sample_continuing::<B>(64, &mut rand::rng(), &dev).unwrap().dones.sum().into_scalar() == 0.0);
+
+        // the oldest steps go first, along with the windows they were part of
+        let mut third = episode(2).await;
+        third.states.truncate(window);
+        third.actions.truncate(window);
+        third.rewards.truncate(window);
+        third.terminals.truncate(window);
+        buffer.push(&third);
+        assert_eq!(buffer.len(), buffer.capacity());
+        assert_eq!(buffer.transition_count(), transitions - window);
+    }
+
+    #[tokio::test]
+    async fn the_transitions_follow_the_steps() {
+        let mut first = episode(0).await;
+        *first.terminals.last_mut().unwrap() = true;
+        let histories = [first, episode(1).await, episode(2).await];
+        // small enough for every episode to push steps out, windows included
+        let mut buffer = ReplayBuffer::new(histories[0].states.len() - 3);
+        for history in &histories {
+            buffer.push(history);
+            let scanned = (0..buffer.len()).filter(|&position| buffer.is_transition(position)).collect::<Vec<_>>();
+            assert_eq!(buffer.transitions().collect::<Vec<_>>(), scanned);
+            let continuing = scanned.into_iter().filter(|&position| buffer.has_next(position)).collect::<Vec<_>>();
+            assert_eq!(buffer.continuing.iter().map(|&id| buffer.position_of(id).unwrap()).collect::<Vec<_>>(), continuing);
+        }
+    }
+
+    #[test]
+    fn empty_buffers_have_nothing_to_sample() {
+        let buffer = ReplayBuffer::new(10);
+        assert!(buffer.sample::<B>(4, &mut rand::rng(), &Default::default()).is_none());
+    }
+}
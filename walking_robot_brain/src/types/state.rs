@@ -1,84 +1,202 @@
-use nalgebra::{Quaternion, Vector, Vector2, Vector3};
+use nalgebra::{Quaternion, Vector3};
+use serde::{Deserialize, Serialize};
 
-use crate::traits::{JsonExts, TryFromJson};
+use crate::tensor_conversion::{TensorConvertible, FORCES_COUNT};
+
+use super::unity_serde::{float, quaternion, vector3};
 
 pub type G = f32;
 pub type Reward = f32;
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
 pub struct GameStateAndReward {
+    #[serde(rename = "State")]
     pub game_state: GameState,
+    #[serde(with = "float")]
     pub reward: f32,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, TensorConvertible)]
 pub struct GameState {
+    #[serde(rename = "SensorsReading")]
     pub sensors_reading: SensorsReading,
+    #[serde(rename = "LimbsReading")]
     pub limbs_readings: BipedalLimbsReading,
 }
 
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(from = "UpdateMessage", into = "UpdateMessage")]
 pub enum GameUpdate {
-    GameStarted,
-    GameStep { state: GameState, reward: f32 },
+    EpisodeStarted,
+    GameStep { state: GameState, reward: f32, flags: StepFlags },
+    EpisodeEnded { reason: EpisodeEndReason },
+}
+
+// `GameUpdate` as the simulation sends it
+#[allow(clippy::large_enum_variant)]
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "Type")]
+enum UpdateMessage {
+    EpisodeStart,
+    #[serde(rename_all = "PascalCase")]
+    Step { #[serde(with = "float")] reward: f32, state: StepState },
+    #[serde(rename_all = "PascalCase")]
+    EpisodeEnd { reason: EpisodeEndReason },
+}
+
+#[derive(Serialize, Deserialize)]
+struct StepState {
+    #[serde(rename = "IsFinished")]
+    is_finished: bool,
+    #[serde(flatten)]
+    state: GameState,
+}
+
+impl From<UpdateMessage> for GameUpdate {
+    fn from(message: UpdateMessage) -> Self {
+        match message {
+            UpdateMessage::EpisodeStart => GameUpdate::EpisodeStarted,
+            UpdateMessage::EpisodeEnd { reason } => GameUpdate::EpisodeEnded { reason },
+            // reaching the target is the only way an episode ends in unity
+            UpdateMessage::Step { reward, state: StepState { is_finished, state } } => {
+                let flags = StepFlags { terminal: is_finished, success: is_finished, truncated: false };
+                GameUpdate::GameStep { state, reward, flags }
+            }
+        }
+    }
+}
+
+impl From<GameUpdate> for UpdateMessage {
+    fn from(update: GameUpdate) -> Self {
+        match update {
+            GameUpdate::EpisodeStarted => UpdateMessage::EpisodeStart,
+            GameUpdate::EpisodeEnded { reason } => UpdateMessage::EpisodeEnd { reason },
+            GameUpdate::GameStep { state, reward, flags } => {
+                UpdateMessage::Step { reward, state: StepState { is_finished: flags.terminal, state } }
+            }
+        }
+    }
+}
+
+/// How a step relates to the end of its episode.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Default, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct StepFlags {
+    // the state reached ends the episode, nothing follows it
+    pub terminal    : bool,
+    // the state reached is the target
+    pub success     : bool,
+    // the episode stopped before the step could be taken, so it is not part of the episode
+    pub truncated   : bool,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Serialize, Deserialize)]
+pub enum EpisodeEndReason {
+    Success,
+    TimeLimit,
+    Stopped,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, TensorConvertible)]
 pub struct SensorsReading {
+    #[serde(rename = "TargetPos", with = "vector3")]
     pub target_pos      : Vector3<f32>,
+    // infinite when the raycast misses the floor
+    #[serde(rename = "FloorDist", with = "float")]
     pub floor_distance  : f32,
+    #[serde(rename = "AccelerometerReading")]
     pub acc_reading     : AccelerometerReading,
+    #[serde(rename = "Forces")]
+    // past `FORCES_COUNT` contacts the weakest are left out
+    #[tensor(pad = FORCES_COUNT, keep = Force::magnitude)]
     pub forces          : Vec<Force>,
 }
 
-#[derive(Clone, PartialEq, Debug, Default)]
+#[derive(Clone, PartialEq, Debug, Default, Serialize, Deserialize, TensorConvertible)]
+#[serde(rename_all = "PascalCase")]
 pub struct Force{
+    #[serde(rename = "Position", with = "vector3")]
     pub pos     : Vector3<f32>,
+    #[serde(with = "vector3")]
     pub force   : Vector3<f32>,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+impl Force {
+    pub fn magnitude(&self) -> f32 {
+        self.force.norm()
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, TensorConvertible)]
+#[serde(rename_all = "PascalCase")]
 pub struct BipedalLimbsReading {
     pub left: LimbReading,
     pub right: LimbReading,
 }
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, TensorConvertible)]
 pub struct LimbReading {
+	#[serde(rename = "ShoulderReading")]
 	pub shoulder: LinkReading,
+	#[serde(rename = "ThighReading")]
 	pub thigh	: LinkReading,
+	#[serde(rename = "ShinReading")]
 	pub shin 	: LinkReading,
+	#[serde(rename = "FootReading")]
 	pub foot	: TransformReading,
 }
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, TensorConvertible)]
 pub struct LinkReading {
+    #[serde(rename = "MotorReading")]
     pub motor		: MotorReading,
+    #[serde(rename = "TransformReading")]
     pub transform	: TransformReading,
 }
 
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, TensorConvertible)]
+#[serde(rename_all = "PascalCase")]
 pub struct TransformReading {
+    #[serde(with = "vector3")]
     pub linear_pos: Vector3<f32>,
+    #[serde(with = "vector3")]
     pub linear_speed: Vector3<f32>,
+    #[serde(with = "vector3")]
     pub linear_acc: Vector3<f32>,
 
+    #[serde(with = "quaternion")]
     pub angular_pos: Quaternion<f32>,
+    #[serde(with = "vector3")]
     pub angular_speed: Vector3<f32>,
+    #[serde(with = "vector3")]
     pub angular_acc: Vector3<f32>,
 }
 
 
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, TensorConvertible)]
+#[serde(rename_all = "PascalCase")]
 pub struct MotorReading {
+    #[serde(with = "float")]
     pub pos: f32,
+    #[serde(with = "float")]
     pub speed: f32,
+    #[serde(with = "float")]
     pub acc: f32,
+    #[serde(with = "float")]
     pub torque: f32,
 }
 
-#[derive(Clone, PartialEq, Debug)]
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize, TensorConvertible)]
+#[serde(rename_all = "PascalCase")]
 pub struct AccelerometerReading{
+	#[serde(rename = "UpOrientation", with = "vector3")]
 	pub up				: Vector3<f32>,
+	#[serde(with = "vector3")]
 	pub linear_speed	: Vector3<f32>,
+	#[serde(with = "vector3")]
 	pub linear_acc		: Vector3<f32>,
+	#[serde(with = "vector3")]
 	pub angular_speed	: Vector3<f32>,
+	#[serde(with = "vector3")]
 	pub angular_acc		: Vector3<f32>,
-}
\ No newline at end of file
+}
@@ -0,0 +1,86 @@
+/// Fixed number of non negative priorities, with their total and a draw proportional to them in `O(log n)`.
+#[derive(Clone, Debug)]
+pub struct SumTree {
+    len     : usize,
+    // a complete binary tree over the leaves, the root is at 1 and the leaves start at `leaves`
+    nodes   : Vec<f64>,
+    leaves  : usize,
+}
+
+impl SumTree {
+    pub fn new(len: usize) -> Self {
+        let leaves = len.next_power_of_two();
+        Self { len, nodes: vec![0.0; 2 * leaves], leaves }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn total(&self) -> f64 {
+        self.nodes[1]
+    }
+
+    pub fn get(&self, index: usize) -> f64 {
+        self.nodes[self.leaves + index]
+    }
+
+    pub fn set(&mut self, index: usize, priority: f64) {
+        assert!(index < self.len, "priority {index} of a tree of {}", self.len);
+        assert!(priority >= 0.0 && priority.is_finite(), "invalid priority {priority}");
+        let mut node = self.leaves + index;
+        self.nodes[node] = priority;
+        while node > 1 {
+            node /= 2;
+            self.nodes[node] = self.nodes[2 * node] + self.nodes[2 * node + 1];
+        }
+    }
+
+    /// The index whose priority covers `mass`, counting from the first one, `mass` being in `[0, total)`. Never
+    /// lands on a zero priority while the total isn't zero.
+    pub fn find(&self, mass: f64) -> usize {
+        let mut mass = mass.clamp(0.0, self.total());
+        let mut node = 1;
+        while node < self.leaves {
+            let left = self.nodes[2 * node];
+            // the right side when the mass is past the left one, or the left one is empty after rounding
+            if mass >= left && self.nodes[2 * node + 1] > 0.0 || left <= 0.0 {
+                mass -= left;
+                node = 2 * node + 1;
+            } else {
+                node *= 2;
+            }
+        }
+        node - self.leaves
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SumTree;
+
+    #[test]
+    fn draws_follow_the_priorities() {
+        let mut tree = SumTree::new(5);
+        for (index, priority) in [1.0, 0.0, 3.0, 0.5, 0.5].into_iter().enumerate() {
+            tree.set(index, priority);
+        }
+        assert_eq!(tree.total(), 5.0);
+        assert_eq!(tree.find(0.0), 0);
+        assert_eq!(tree.find(0.999), 0);
+        assert_eq!(tree.find(1.0), 2);
+        assert_eq!(tree.find(3.999), 2);
+        assert_eq!(tree.find(4.2), 3);
+        assert_eq!(tree.find(4.7), 4);
+        // past the total is the last priority, not the padding
+        assert_eq!(tree.find(5.0), 4);
+
+        tree.set(2, 0.0);
+        assert_eq!(tree.total(), 2.0);
+        assert_eq!(tree.find(1.0), 3);
+    }
+}
@@ -0,0 +1,110 @@
+// `with` modules for the nalgebra types, which Unity's `JsonUtility` writes as `{x, y, z}` objects, and for the
+// floats, which Newtonsoft writes as strings when they aren't finite.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+#[derive(Serialize, Deserialize)]
+struct UnityVector3 {
+    #[serde(with = "float")]
+    x: f32,
+    #[serde(with = "float")]
+    y: f32,
+    #[serde(with = "float")]
+    z: f32,
+}
+
+#[derive(Serialize, Deserialize)]
+struct UnityQuaternion {
+    #[serde(with = "float")]
+    x: f32,
+    #[serde(with = "float")]
+    y: f32,
+    #[serde(with = "float")]
+    z: f32,
+    #[serde(with = "float")]
+    w: f32,
+}
+
+pub mod float {
+    use std::fmt;
+
+    use serde::de::{self, Visitor};
+
+    use super::*;
+
+    // json has no infinities, Newtonsoft writes them the way .NET prints them
+    pub fn serialize<S: Serializer>(value: &f32, serializer: S) -> Result<S::Ok, S::Error> {
+        match *value {
+            value if value.is_finite() => serializer.serialize_f32(value),
+            value if value.is_nan() => serializer.serialize_str("NaN"),
+            value if value > 0.0 => serializer.serialize_str("Infinity"),
+            _ => serializer.serialize_str("-Infinity"),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f32, D::Error> {
+        deserializer.deserialize_any(FloatVisitor)
+    }
+
+    struct FloatVisitor;
+
+    impl Visitor<'_> for FloatVisitor {
+        type Value = f32;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a number")
+        }
+
+        fn visit_f64<E: de::Error>(self, value: f64) -> Result<f32, E> {
+            Ok(value as f32)
+        }
+
+        fn visit_i64<E: de::Error>(self, value: i64) -> Result<f32, E> {
+            Ok(value as f32)
+        }
+
+        fn visit_u64<E: de::Error>(self, value: u64) -> Result<f32, E> {
+            Ok(value as f32)
+        }
+
+        fn visit_str<E: de::Error>(self, value: &str) -> Result<f32, E> {
+            match value {
+                "Infinity" => Ok(f32::INFINITY),
+                "-Infinity" => Ok(f32::NEG_INFINITY),
+                "NaN" => Ok(f32::NAN),
+                _ => Err(E::invalid_type(de::Unexpected::Str(value), &self)),
+            }
+        }
+    }
+}
+
+pub mod vector3 {
+    use nalgebra::Vector3;
+
+    use super::*;
+
+    pub fn serialize<S: Serializer>(vector: &Vector3<f32>, serializer: S) -> Result<S::Ok, S::Error> {
+        UnityVector3 { x: vector.x, y: vector.y, z: vector.z }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vector3<f32>, D::Error> {
+        let UnityVector3 { x, y, z } = UnityVector3::deserialize(deserializer)?;
+        Ok(Vector3::new(x, y, z))
+    }
+}
+
+pub mod quaternion {
+    use nalgebra::Quaternion;
+
+    use super::*;
+
+    // the components are taken in the order unity sends them, `w` included, and written back the same way
+    pub fn serialize<S: Serializer>(quaternion: &Quaternion<f32>, serializer: S) -> Result<S::Ok, S::Error> {
+        UnityQuaternion { x: quaternion.w, y: quaternion.i, z: quaternion.j, w: quaternion.k }.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Quaternion<f32>, D::Error> {
+        let UnityQuaternion { x, y, z, w } = UnityQuaternion::deserialize(deserializer)?;
+        Ok(Quaternion::new(x, y, z, w))
+    }
+}
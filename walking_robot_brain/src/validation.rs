@@ -0,0 +1,435 @@
+use std::{collections::BTreeMap, fmt};
+
+use burn::config::Config;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+use tracing::warn;
+
+use crate::{
+    features::matches,
+    schema::ObservationSchema,
+    tensor_conversion::TensorConvertible,
+    types::{
+        action::GameAction,
+        environment::{Environment, EnvironmentError},
+        history::History,
+        state::{GameState, Reward, StepFlags},
+    },
+};
+
+/// What to do with a value that is not finite or out of its range.
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+pub enum Handling {
+    // into the range, NaN has no side to go to and becomes 0 clamped into the range
+    Clamp,
+    Replace(f32),
+    // the state is not used, see `Validated`
+    DropStep,
+    Error,
+}
+
+/// How the values under `path` are checked, `path` is matched against the names of the `ObservationSchema` like the
+/// paths of a `FeatureSelector`.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct FieldRule {
+    pub path    : String,
+    pub min     : f32,
+    pub max     : f32,
+    pub handling: Handling,
+}
+
+impl FieldRule {
+    /// Only NaN and infinities are caught.
+    pub fn finite(path: &str, handling: Handling) -> Self {
+        Self { path: path.to_string(), min: f32::NEG_INFINITY, max: f32::INFINITY, handling }
+    }
+
+    pub fn range(path: &str, min: f32, max: f32, handling: Handling) -> Self {
+        Self { path: path.to_string(), min, max, handling }
+    }
+
+    fn accepts(&self, value: f32) -> bool {
+        value.is_finite() && self.min <= value && value <= self.max
+    }
+}
+
+#[derive(Config, Debug)]
+pub struct ValidationConfig {
+    // the first rule matching a value applies to it, values no rule matches are not checked
+    #[config(default = "ValidationConfig::default_rules()")]
+    pub rules: Vec<FieldRule>,
+}
+
+impl ValidationConfig {
+    /// Unity reports an infinite floor distance when its raycast misses, anything else that isn't finite becomes 0.
+    pub fn default_rules() -> Vec<FieldRule> {
+        vec![
+            FieldRule::range("sensors_reading.floor_distance", 0.0, 5.0, Handling::Clamp),
+            FieldRule::finite("*", Handling::Replace(0.0)),
+        ]
+    }
+
+    /// Panics on a rule whose path matches no value, like a `FeatureSelector` would.
+    pub fn init(&self) -> StateValidator {
+        let schema = ObservationSchema::game_state();
+        for rule in &self.rules {
+            assert!(
+                schema.entries().iter().any(|entry| matches(&rule.path, &entry.name)),
+                "validation rule for `{}` matches none of the values", rule.path
+            );
+        }
+        let column_rules = schema
+            .entries()
+            .iter()
+            .map(|entry| self.rules.iter().position(|rule| matches(&rule.path, &entry.name)))
+            .collect();
+        StateValidator { rules: self.rules.clone(), column_rules, counters: Default::default() }
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Default)]
+pub struct ValidationCounters {
+    pub states          : usize,
+    // states with at least one value a rule caught
+    pub flagged_states  : usize,
+    pub dropped_states  : usize,
+    pub errors          : usize,
+    // how many times each value was caught, by name
+    pub by_field        : BTreeMap<String, usize>,
+}
+
+impl fmt::Display for ValidationCounters {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} of {} states had invalid values, {} dropped, {} errors",
+            self.flagged_states, self.states, self.dropped_states, self.errors
+        )?;
+        for (name, count) in &self.by_field {
+            write!(f, ", {name}: {count}")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, PartialEq, Debug, Error)]
+#[error("`{name}` is {value}")]
+pub struct InvalidValue {
+    pub name    : String,
+    pub value   : f32,
+}
+
+/// Checks the values of incoming states against the rules of a `ValidationConfig` and counts what it finds.
+#[derive(Clone, Debug)]
+pub struct StateValidator {
+    rules       : Vec<FieldRule>,
+    // the rule of each value of a state, by offset
+    column_rules: Vec<Option<usize>>,
+    counters    : ValidationCounters,
+}
+
+impl Default for StateValidator {
+    fn default() -> Self {
+        ValidationConfig::new().init()
+    }
+}
+
+impl StateValidator {
+    pub fn counters(&self) -> &ValidationCounters {
+        &self.counters
+    }
+
+    /// The state with its invalid values handled, `None` when it is to be dropped.
+    pub fn validate(&mut self, state: GameState) -> Result<Option<GameState>, InvalidValue> {
+        self.counters.states += 1;
+        let mut values = state.iterate_values().collect::<Vec<_>>();
+        let mut flagged = false;
+        let mut dropped = false;
+        for (offset, value) in values.iter_mut().enumerate() {
+            let Some(rule) = self.column_rules[offset].map(|rule| &self.rules[rule]) else { continue };
+            if rule.accepts(*value) {
+                continue;
+            }
+            let name = ObservationSchema::game_state().name_of(offset).unwrap();
+            if !self.counters.by_field.contains_key(name) {
+                warn!("first invalid `{name}` received: {value}");
+            }
+            *self.counters.by_field.entry(name.to_string()).or_default() += 1;
+            flagged = true;
+            match rule.handling {
+                Handling::Clamp => *value = if value.is_nan() { 0.0 } else { *value }.clamp(rule.min, rule.max),
+                Handling::Replace(replacement) => *value = replacement,
+                Handling::DropStep => dropped = true,
+                Handling::Error => {
+                    self.counters.flagged_states += 1;
+                    self.counters.errors += 1;
+                    return Err(InvalidValue { name: name.to_string(), value: *value });
+                }
+            }
+        }
+
+        if !flagged {
+            return Ok(Some(state));
+        }
+        self.counters.flagged_states += 1;
+        if dropped {
+            self.counters.dropped_states += 1;
+            return Ok(None);
+        }
+        Ok(Some(GameState::from_values(&values)))
+    }
+
+    /// A recorded episode with its states validated like `Validated` does those it receives, a dropped state cuts the
+    /// episode short before it.
+    pub fn validate_history(&mut self, mut history: History) -> Result<History, InvalidValue> {
+        let mut states = Vec::with_capacity(history.states.len());
+        for state in history.states.drain(..) {
+            match self.validate(state)? {
+                Some(state) => states.push(state),
+                None => break,
+            }
+        }
+        if states.len() < history.actions.len() {
+            history.actions.truncate(states.len());
+            history.rewards.truncate(states.len());
+            history.terminals.truncate(states.len());
+            history.truncated = true;
+            history.success = false;
+        }
+        history.states = states;
+        Ok(history)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ValidatedError<E> {
+    #[error(transparent)]
+    Environment(E),
+    #[error("invalid state received: {0}")]
+    Invalid(#[from] InvalidValue),
+    #[error("step called before any episode was reset")]
+    NotReset,
+}
+
+impl<E: EnvironmentError> EnvironmentError for ValidatedError<E> {
+    fn is_disconnect(&self) -> bool {
+        matches!(self, Self::Environment(err) if err.is_disconnect())
+    }
+}
+
+/// An environment whose states go through a `StateValidator`. A dropped first state waits for the next episode, a
+/// dropped state in the middle of an episode ends it like a truncation, the step is not part of the episode.
+pub struct Validated<E> {
+    environment : E,
+    validator   : StateValidator,
+    // what a dropped step returns
+    last_state  : Option<GameState>,
+}
+
+impl<E: Environment> Validated<E> {
+    pub fn new(environment: E, validator: StateValidator) -> Self {
+        Self { environment, validator, last_state: None }
+    }
+
+    pub fn counters(&self) -> &ValidationCounters {
+        self.validator.counters()
+    }
+
+    pub fn inner(&self) -> &E {
+        &self.environment
+    }
+
+    pub fn inner_mut(&mut self) -> &mut E {
+        &mut self.environment
+    }
+}
+
+impl<E: Environment> Environment for Validated<E> {
+    type Error = ValidatedError<E::Error>;
+
+    async fn reset(&mut self) -> Result<GameState, Self::Error> {
+        loop {
+            let state = self.environment.reset().await.map_err(ValidatedError::Environment)?;
+            if let Some(state) = self.validator.validate(state)? {
+                self.last_state = Some(state.clone());
+                return Ok(state);
+            }
+        }
+    }
+
+    async fn step(&mut self, action: &GameAction) -> Result<(GameState, Reward, StepFlags), Self::Error> {
+        let (state, reward, flags) = self.environment.step(action).await.map_err(ValidatedError::Environment)?;
+        match self.validator.validate(state)? {
+            Some(state) => {
+                self.last_state = Some(state.clone());
+                Ok((state, reward, flags))
+            }
+            None => {
+                let last_state = self.last_state.clone().ok_or(ValidatedError::NotReset)?;
+                Ok((last_state, 0.0, StepFlags { truncated: true, ..Default::default() }))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::Infallible;
+
+    use crate::{
+        error::SimulationError,
+        procedures::run_simulation::RunEpisodeExt,
+        simulation::biped::BipedSim,
+        types::{
+            action::GameAction,
+            environment::Environment,
+            policy::nil_policy::NilPolicy,
+            state::{GameState, GameUpdate, Reward, StepFlags},
+        },
+        wire::decode_update,
+    };
+
+    use super::{FieldRule, Handling, InvalidValue, StateValidator, Validated, ValidatedError, ValidationConfig};
+
+    fn state() -> GameState {
+        let mut sim = BipedSim::new(Default::default(), 0);
+        sim.reset();
+        sim.step(&GameAction::default()).0
+    }
+
+    #[test]
+    fn invalid_values_are_handled_per_field() {
+        let mut validator = ValidationConfig::new().init();
+        let mut raw = state();
+        raw.sensors_reading.floor_distance = f32::INFINITY;
+        raw.limbs_readings.left.shin.motor.speed = f32::NAN;
+        let valid = validator.validate(raw.clone()).unwrap().unwrap();
+        assert_eq!(valid.sensors_reading.floor_distance, 5.0);
+        assert_eq!(valid.limbs_readings.left.shin.motor.speed, 0.0);
+        assert_eq!(valid.limbs_readings.right, raw.limbs_readings.right);
+
+        let untouched = state();
+        assert_eq!(validator.validate(untouched.clone()), Ok(Some(untouched)));
+
+        let counters = validator.counters();
+        assert_eq!((counters.states, counters.flagged_states), (2, 1));
+        assert_eq!(counters.by_field["sensors_reading.floor_distance"], 1);
+        assert_eq!(counters.by_field["limbs_readings.left.shin.motor.speed"], 1);
+    }
+
+    #[test]
+    fn rules_can_drop_states_or_fail() {
+        let mut validator = ValidationConfig::new()
+            .with_rules(vec![
+                FieldRule::finite("sensors_reading.floor_distance", Handling::Error),
+                FieldRule::range("limbs_readings.*.*.motor.speed", -10.0, 10.0, Handling::DropStep),
+            ])
+            .init();
+        let mut fast = state();
+        fast.limbs_readings.right.thigh.motor.speed = 11.0;
+        assert_eq!(validator.validate(fast), Ok(None));
+
+        let mut lost = state();
+        lost.sensors_reading.floor_distance = f32::INFINITY;
+        let err = validator.validate(lost).unwrap_err();
+        assert_eq!(err, InvalidValue { name: "sensors_reading.floor_distance".to_string(), value: f32::INFINITY });
+        assert_eq!((validator.counters().dropped_states, validator.counters().errors), (1, 1));
+    }
+
+    #[tokio::test]
+    async fn recorded_episodes_are_validated_too() {
+        let mut history = BipedSim::new(Default::default(), 0).run_episode(&mut NilPolicy).await.unwrap();
+        let steps = history.states.len();
+        history.states[1].sensors_reading.floor_distance = f32::INFINITY;
+        let valid = StateValidator::default().validate_history(history).unwrap();
+        assert_eq!(valid.states.len(), steps);
+        assert_eq!(valid.states[1].sensors_reading.floor_distance, 5.0);
+
+        let mut history = BipedSim::new(Default::default(), 0).run_episode(&mut NilPolicy).await.unwrap();
+        history.states[2].limbs_readings.left.shin.motor.speed = 11.0;
+        let mut validator = ValidationConfig::new().with_rules(vec![FieldRule::range("limbs_readings.*.*.motor.speed", -10.0, 10.0, Handling::DropStep)]).init();
+        let cut = validator.validate_history(history).unwrap();
+        assert_eq!((cut.states.len(), cut.actions.len(), cut.rewards.len(), cut.terminals.len()), (2, 2, 2, 2));
+        assert!(cut.truncated);
+    }
+
+    // a simulation whose third state has lost track of the floor
+    struct Raycasts {
+        steps: usize,
+    }
+
+    impl Environment for Raycasts {
+        type Error = Infallible;
+
+        async fn reset(&mut self) -> Result<GameState, Infallible> {
+            self.steps = 0;
+            Ok(state())
+        }
+
+        async fn step(&mut self, _action: &GameAction) -> Result<(GameState, Reward, StepFlags), Infallible> {
+            self.steps += 1;
+            let mut state = state();
+            if self.steps == 3 {
+                state.sensors_reading.floor_distance = f32::INFINITY;
+            }
+            Ok((state, 1.0, StepFlags { terminal: self.steps == 5, ..Default::default() }))
+        }
+    }
+
+    #[tokio::test]
+    async fn dropped_steps_end_the_episode() {
+        let clamped = Validated::new(Raycasts { steps: 0 }, ValidationConfig::new().init()).run_episode(&mut NilPolicy).await.unwrap();
+        assert_eq!(clamped.states.len(), 5);
+        assert!(clamped.states.iter().all(|state| state.sensors_reading.floor_distance.is_finite()));
+
+        let config = ValidationConfig::new().with_rules(vec![FieldRule::finite("*", Handling::DropStep)]);
+        let mut env = Validated::new(Raycasts { steps: 0 }, config.init());
+        let dropped = env.run_episode(&mut NilPolicy).await.unwrap();
+        assert_eq!(dropped.states.len(), 2);
+        assert!(dropped.truncated);
+        assert_eq!(env.counters().dropped_states, 1);
+
+        let config = ValidationConfig::new().with_rules(vec![FieldRule::finite("*", Handling::Error)]);
+        let failed = Validated::new(Raycasts { steps: 0 }, config.init()).run_episode(&mut NilPolicy).await;
+        assert!(matches!(failed, Err(ValidatedError::Invalid(_))));
+    }
+
+    #[tokio::test]
+    async fn dropping_before_reset_is_an_error() {
+        let config = ValidationConfig::new().with_rules(vec![FieldRule::finite("*", Handling::DropStep)]);
+        // the next step is the one without a floor
+        let mut env = Validated::new(Raycasts { steps: 2 }, config.init());
+        assert!(matches!(env.step(&GameAction::default()).await, Err(ValidatedError::NotReset)));
+    }
+
+    // a simulation sending its steps the way Newtonsoft writes them when the raycast misses the floor
+    struct Newtonsoft;
+
+    impl Environment for Newtonsoft {
+        type Error = SimulationError;
+
+        async fn reset(&mut self) -> Result<GameState, SimulationError> {
+            Ok(state())
+        }
+
+        async fn step(&mut self, _action: &GameAction) -> Result<(GameState, Reward, StepFlags), SimulationError> {
+            let mut json = serde_json::to_value(GameUpdate::GameStep { state: state(), reward: 1.0, flags: Default::default() }).unwrap();
+            json["State"]["SensorsReading"]["FloorDist"] = "Infinity".into();
+            json["State"]["LimbsReading"]["Left"]["ShinReading"]["MotorReading"]["Speed"] = "NaN".into();
+            match decode_update(json.to_string().as_bytes())? {
+                GameUpdate::GameStep { state, reward, flags } => Ok((state, reward, flags)),
+                other => panic!("a step decodes to a step, not {other:?}"),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn non_finite_json_values_are_validated() {
+        let mut env = Validated::new(Newtonsoft, ValidationConfig::new().init());
+        env.reset().await.unwrap();
+        let (state, _, _) = env.step(&GameAction::default()).await.unwrap();
+        assert_eq!(state.sensors_reading.floor_distance, 5.0);
+        assert_eq!(state.limbs_readings.left.shin.motor.speed, 0.0);
+        assert_eq!(env.counters().flagged_states, 1);
+    }
+}
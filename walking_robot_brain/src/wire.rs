@@ -0,0 +1,148 @@
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::{
+    error::SimulationError,
+    tensor_conversion::TensorConvertible,
+    types::{
+        action::GameAction,
+        state::{GameState, GameUpdate, StepFlags},
+    },
+};
+
+// json messages start with `{`, so a leading byte tells the formats apart without knowing what was negotiated
+
+/// A binary step: the tag, the reward, the flags and the state values.
+pub const BINARY_STEP_TAG: u8 = 1;
+/// A binary action: the tag and the action values.
+pub const BINARY_ACTION_TAG: u8 = 2;
+
+const TERMINAL_BIT: u8 = 1;
+const SUCCESS_BIT: u8 = 2;
+
+pub const BINARY_STEP_LEN: usize = 1 + 4 + 1 + 4 * GameState::VALUES_COUNT;
+pub const BINARY_ACTION_LEN: usize = 1 + 4 * GameAction::VALUES_COUNT;
+
+fn push_values(bytes: &mut Vec<u8>, values: impl Iterator<Item = f32>) {
+    for value in values {
+        bytes.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn read_values(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes(chunk.try_into().unwrap()))
+        .collect()
+}
+
+fn check_frame(frame: &[u8], tag: u8, expected: usize) -> Result<(), SimulationError> {
+    match frame.first() {
+        Some(&first) if first != tag => Err(SimulationError::UnknownTag(first)),
+        _ if frame.len() != expected => Err(SimulationError::BinaryLength { len: frame.len(), expected }),
+        _ => Ok(()),
+    }
+}
+
+/// Forces are padded, and past `FORCES_COUNT` cut down to the strongest, like in the tensors. Those that are all zeros
+/// don't come back.
+pub fn encode_step(state: &GameState, reward: f32, flags: StepFlags) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(BINARY_STEP_LEN);
+    bytes.push(BINARY_STEP_TAG);
+    bytes.extend_from_slice(&reward.to_le_bytes());
+    let terminal = if flags.terminal { TERMINAL_BIT } else { 0 };
+    let success = if flags.success { SUCCESS_BIT } else { 0 };
+    bytes.push(terminal | success);
+    push_values(&mut bytes, state.iterate_values());
+    bytes
+}
+
+pub fn decode_step(frame: &[u8]) -> Result<GameUpdate, SimulationError> {
+    check_frame(frame, BINARY_STEP_TAG, BINARY_STEP_LEN)?;
+    let reward = f32::from_le_bytes(frame[1..5].try_into().unwrap());
+    let flags = StepFlags {
+        terminal    : frame[5] & TERMINAL_BIT != 0,
+        success     : frame[5] & SUCCESS_BIT != 0,
+        truncated   : false,
+    };
+    let state = GameState::from_values(&read_values(&frame[6..]));
+    Ok(GameUpdate::GameStep { state, reward, flags })
+}
+
+pub fn encode_action(action: &GameAction) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(BINARY_ACTION_LEN);
+    bytes.push(BINARY_ACTION_TAG);
+    push_values(&mut bytes, action.iterate_values());
+    bytes
+}
+
+pub fn decode_action(frame: &[u8]) -> Result<GameAction, SimulationError> {
+    check_frame(frame, BINARY_ACTION_TAG, BINARY_ACTION_LEN)?;
+    Ok(GameAction::from_values(&read_values(&frame[1..])))
+}
+
+pub fn decode_json<T: DeserializeOwned>(frame: &[u8]) -> Result<T, SimulationError> {
+    let mut deserializer = serde_json::Deserializer::from_slice(frame);
+    let value = serde_path_to_error::deserialize(&mut deserializer).map_err(SimulationError::from_json_at)?;
+    deserializer.end()?;
+    Ok(value)
+}
+
+/// A message of the simulation after the handshake, in either format.
+pub fn decode_update(frame: &[u8]) -> Result<GameUpdate, SimulationError> {
+    match frame.first() {
+        Some(&BINARY_STEP_TAG) => decode_step(frame),
+        _ => decode_json(frame),
+    }
+}
+
+/// A message of the brain as json, binary actions included, for comparing and logging.
+pub fn reply_as_json(frame: &[u8]) -> Result<Value, SimulationError> {
+    match frame.first() {
+        Some(&BINARY_ACTION_TAG) => Ok(serde_json::to_value(decode_action(frame)?)?),
+        _ => decode_json(frame),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        error::SimulationError,
+        simulation::biped::{BipedSim, BipedSimConfig},
+        types::{action::GameAction, state::{GameUpdate, StepFlags}},
+    };
+
+    use super::{decode_action, decode_step, decode_update, encode_action, encode_step, BINARY_STEP_LEN};
+
+    #[test]
+    fn binary_steps_round_trip() {
+        let mut sim = BipedSim::new(BipedSimConfig::default(), 3);
+        sim.reset();
+        let (state, reward) = sim.step(&GameAction::default());
+        let flags = StepFlags { terminal: true, success: true, truncated: false };
+
+        let frame = encode_step(&state, reward, flags);
+        assert_eq!(frame.len(), BINARY_STEP_LEN);
+        let GameUpdate::GameStep { state: decoded, reward: decoded_reward, flags: decoded_flags } = decode_step(&frame).unwrap() else {
+            panic!("a step decodes to a step");
+        };
+        assert_eq!(decoded, state);
+        assert_eq!(decoded_reward, reward);
+        assert_eq!(decoded_flags, flags);
+        assert!(matches!(decode_update(&frame), Ok(GameUpdate::GameStep { .. })));
+    }
+
+    #[test]
+    fn binary_actions_round_trip() {
+        let mut action = GameAction::default();
+        action.limbs_activation.right.shin_activation = -0.25;
+        assert_eq!(decode_action(&encode_action(&action)).unwrap(), action);
+    }
+
+    #[test]
+    fn truncated_binary_messages_are_rejected() {
+        let frame = encode_action(&GameAction::default());
+        assert!(matches!(decode_action(&frame[..frame.len() - 1]), Err(SimulationError::BinaryLength { .. })));
+        assert!(matches!(decode_step(&frame), Err(SimulationError::UnknownTag(2))));
+    }
+}
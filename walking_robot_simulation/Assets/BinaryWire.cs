@@ -0,0 +1,105 @@
+using System.IO;
+using System.Linq;
+
+// the binary wire format of the brain (wire.rs): little-endian floats in the order of its TensorConvertible::iterate_values
+public static class BinaryWire
+{
+    public const byte StepTag = 1;
+    public const byte ActionTag = 2;
+    const byte TerminalBit = 1;
+    const byte SuccessBit = 2;
+
+    public static byte[] EncodeStep(GameState state, float reward)
+    {
+        var stream = new MemoryStream();
+        var writer = new BinaryWriter(stream);
+        writer.Write(StepTag);
+        writer.Write(reward);
+        // touching the target is the only way an episode ends
+        writer.Write(state.IsFinished ? (byte)(TerminalBit | SuccessBit) : (byte)0);
+        Write(writer, state.SensorsReading);
+        Write(writer, state.LimbsReading.Left);
+        Write(writer, state.LimbsReading.Right);
+        writer.Flush();
+        return stream.ToArray();
+    }
+
+    public static GameAction DecodeAction(byte[] frame)
+    {
+        var reader = new BinaryReader(new MemoryStream(frame));
+        if (reader.ReadByte() != ActionTag)
+            throw new InvalidDataException("not a binary action");
+        LimbActivation ReadLimb() => new LimbActivation {
+            Shoulder = reader.ReadSingle(),
+            Thigh = reader.ReadSingle(),
+            Shin = reader.ReadSingle(),
+        };
+        var left = ReadLimb();
+        var right = ReadLimb();
+        return new GameAction { LimbsActivation = new BipedalLimbsActivation { Left = left, Right = right } };
+    }
+
+    static void Write(BinaryWriter writer, SerdeVector3 v)
+    {
+        writer.Write(v.x);
+        writer.Write(v.y);
+        writer.Write(v.z);
+    }
+    static void Write(BinaryWriter writer, SerdeQuaternion q)
+    {
+        writer.Write(q.x);
+        writer.Write(q.y);
+        writer.Write(q.z);
+        writer.Write(q.w);
+    }
+    static void Write(BinaryWriter writer, SensorsReading reading)
+    {
+        Write(writer, reading.TargetPos);
+        writer.Write(reading.FloorDist);
+        Write(writer, reading.AccelerometerReading.UpOrientation);
+        Write(writer, reading.AccelerometerReading.LinearSpeed);
+        Write(writer, reading.AccelerometerReading.LinearAcc);
+        Write(writer, reading.AccelerometerReading.AngularSpeed);
+        Write(writer, reading.AccelerometerReading.AngularAcc);
+
+        // the brain has room for MaxForcesCount forces, the padding comes first. Past that the strongest are kept, in
+        // their order and ties to the first ones, like padded_values_keeping does with the forces of a json state
+        var kept = Enumerable.Range(0, reading.Forces.Count)
+            .OrderByDescending(i => SquaredMagnitude(reading.Forces[i].Force))
+            .Take(AgentEndpoint.MaxForcesCount)
+            .OrderBy(i => i)
+            .ToList();
+        for (int i = 0; i < (AgentEndpoint.MaxForcesCount - kept.Count) * 6; i++)
+            writer.Write(0.0f);
+        foreach (var i in kept)
+        {
+            Write(writer, reading.Forces[i].Position);
+            Write(writer, reading.Forces[i].Force);
+        }
+    }
+    static float SquaredMagnitude(SerdeVector3 v) => v.x * v.x + v.y * v.y + v.z * v.z;
+    static void Write(BinaryWriter writer, LimbReading reading)
+    {
+        Write(writer, reading.ShoulderReading);
+        Write(writer, reading.ThighReading);
+        Write(writer, reading.ShinReading);
+        Write(writer, reading.FootReading);
+    }
+    static void Write(BinaryWriter writer, LimbLinkReading reading)
+    {
+        writer.Write(reading.MotorReading.Pos);
+        writer.Write(reading.MotorReading.Speed);
+        writer.Write(reading.MotorReading.Acc);
+        writer.Write(reading.MotorReading.Torque);
+        Write(writer, reading.TransformReading);
+    }
+    static void Write(BinaryWriter writer, TransformReading reading)
+    {
+        Write(writer, reading.LinearPos);
+        Write(writer, reading.LinearSpeed);
+        Write(writer, reading.LinearAcc);
+        Write(writer, reading.AngularPos);
+        Write(writer, reading.AngularSpeed);
+        Write(writer, reading.AngularAcc);
+    }
+}
@@ -1,4 +1,5 @@
 using Newtonsoft.Json;
+using Newtonsoft.Json.Linq;
 using System;
 using System.IO;
 using System.Linq;
@@ -36,23 +37,43 @@ public class Game : MonoBehaviour
         this.GameSpeedSlider.value = SimulationSpeed;
 
         StartPreparingScene();
+        ApplyAgentSettings();
 
         stepCount = 0;
     }
+    // what the brain asked for with its commands
+    void ApplyAgentSettings(){
+        if (this.agent.PendingSeed is int seed)
+        {
+            UnityEngine.Random.InitState(seed);
+            this.agent.PendingSeed = null;
+        }
+        if (this.agent.TimeScale is float timeScale)
+            this.GameSpeedSlider.value = timeScale;
+        if (this.agent.EpisodeDuration is float episodeDuration)
+            this.EpisodeDuration = episodeDuration;
+        if (this.agent.TargetPosition is Vector3 targetPosition)
+            this.Target.transform.position = targetPosition;
+    }
     static void SetupRendering(){
         Screen.SetResolution(640, 640, FullScreenMode.Windowed, new RefreshRate(){numerator = 30, denominator = 1});
     }
     static void StartPreparingScene(){
         ;
     }
-    void Restart(){
+    void Restart(string reason){
+        if (stepCount > 0)
+        {
+            this.agent.InformGameHasEnded(reason);
+        }
         SceneManager.LoadScene(SceneManager.GetActiveScene().buildIndex);
     }
     
     AgentEndpoint       agent;
     RewardCalculator    rewardCalculator;
     int                 stepCount = 0;
-    bool PlayerUpdate()
+    // why the episode ends, null while it goes on
+    string PlayerUpdate()
     {
         var timeScale = Time.timeScale;
         Time.timeScale = 0.01f;
@@ -75,7 +96,7 @@ public class Game : MonoBehaviour
                 this.agent.InformGameHasStarted();
             }
 
-            GameAction TalkToAgentAboutStep(GameState state, float reward)
+            GameAction? TalkToAgentAboutStep(GameState state, float reward)
             {
                 return this.agent.SendStateAndRecvAction(state, reward);
                 //return new GameAction {
@@ -94,7 +115,7 @@ public class Game : MonoBehaviour
 
             if (stepCount == 0)
             {
-                this.agent.Connect();
+                this.agent.Connect(this.DurationBetweenUpdates, this.EpisodeDuration);
             }
 
             Debug.Log($"Step {stepCount}");
@@ -110,9 +131,16 @@ public class Game : MonoBehaviour
             Debug.Log($"Reward: {reward}");
             var action = TalkToAgentAboutStep(state, reward);
             Debug.Log($"Action: {action}");
-
-            ApplyAction(action);
+            ApplyAgentSettings();
             stepCount += 1;
+            if (action is not GameAction a)
+            {
+                return "Stopped";
+            }
+
+            ApplyAction(a);
+            // the brain got the terminal state and answered it, the episode can end
+            return state.IsFinished ? "Success" : null;
         }
         catch
         {
@@ -122,7 +150,6 @@ public class Game : MonoBehaviour
         {
             Time.timeScale = timeScale;
         }
-        return true;
     }
 
     float   nextUpdate;
@@ -134,12 +161,16 @@ public class Game : MonoBehaviour
         Time.timeScale = SimulationSpeed;
         if (now >= nextUpdate)
         {
-            PlayerUpdate();
             nextUpdate = now + DurationBetweenUpdates;
+            if (PlayerUpdate() is string reason)
+            {
+                this.Restart(reason);
+                return;
+            }
         }
         if (Time.time -  startTime > EpisodeDuration) 
         {
-            this.Restart();
+            this.Restart("TimeLimit");
         } 
     }
 }
@@ -172,9 +203,51 @@ $@"GameState{{
 [Serializable]
 public struct GameStateAndReward
 {
+    public string Type;
     public float Reward;
     public GameState State;
 }
+
+// first message on a new connection, the brain answers with a BrainReply
+[Serializable]
+public struct SimulationHello
+{
+    public string Type;
+    public int ProtocolVersion;
+    public int ForcesCount;
+    public int ActionValuesCount;
+    public float ControlPeriod;
+    public float EpisodeDuration;
+    public string[] WireFormats;
+}
+
+[Serializable]
+public struct BrainReply
+{
+    public string Type;
+    public string Reason;
+    // what states and actions are encoded in from now on, "Json" or "Binary"
+    public string WireFormat;
+}
+
+[Serializable]
+public struct BrainCommand
+{
+    // "ResetEpisode", "SetSeed", "SetTimeScale", "SetEpisodeDuration" or "PlaceTarget"
+    public string Type;
+    public ulong Seed;
+    public float TimeScale;
+    public float EpisodeDuration;
+    public SerdeVector3 Position;
+}
+
+[Serializable]
+public struct ControlMessage
+{
+    public string Type;
+    // why the episode ended: "Success", "TimeLimit" or "Stopped"
+    public string Reason;
+}
 public class RewardCalculator
 {
     float previousTime = 0.0f; 
@@ -216,19 +289,93 @@ public class AgentEndpoint
     public static AgentEndpoint Singleton = new AgentEndpoint(); 
 
     Socket socket; 
+    // set with "-brainAddr <ip:port>" and "-listen" on the player command line,
+    // "-listen" is for a brain started with "--role connect"
+    IPEndPoint brainEndPoint = new IPEndPoint(IPAddress.Loopback, 8080);
+    bool listen = false;
+    // negotiated in the handshake
+    bool binary = false;
+
+    // set by the brain's commands, they outlive the scene reloads between episodes
+    public int?     PendingSeed;
+    public float?   TimeScale;
+    public float?   EpisodeDuration;
+    public Vector3? TargetPosition;
     private AgentEndpoint() {
         this.socket = new Socket(SocketType.Stream, ProtocolType.Tcp);
+        var args = System.Environment.GetCommandLineArgs();
+        for (int i = 0; i < args.Length; i++)
+        {
+            if (args[i] == "-brainAddr" && i + 1 < args.Length)
+            {
+                this.brainEndPoint = IPEndPoint.Parse(args[i + 1]);
+            }
+            if (args[i] == "-listen")
+            {
+                this.listen = true;
+            }
+        }
     }
-    public void Connect()
+    public const int ProtocolVersion = 2;
+    // the brain makes room for this many contact forces in its tensors
+    public const int MaxForcesCount = 20;
+    public const int ActionValuesCount = 6;
+
+    public void Connect(float controlPeriod, float episodeDuration)
     {
         if (!this.socket.Connected)
         {
-            this.socket.Connect(IPAddress.Loopback, 8080);
+            if (this.listen)
+            {
+                var listener = new Socket(SocketType.Stream, ProtocolType.Tcp);
+                listener.Bind(this.brainEndPoint);
+                listener.Listen(1);
+                this.socket = listener.Accept();
+                listener.Close();
+            }
+            else
+            {
+                this.socket.Connect(this.brainEndPoint);
+            }
+            this.Handshake(controlPeriod, episodeDuration);
         }
     }
+    void Handshake(float controlPeriod, float episodeDuration)
+    {
+        this.SendJson(new SimulationHello{
+            Type = "Hello",
+            ProtocolVersion = ProtocolVersion,
+            ForcesCount = MaxForcesCount,
+            ActionValuesCount = ActionValuesCount,
+            ControlPeriod = controlPeriod,
+            EpisodeDuration = episodeDuration,
+            WireFormats = new[] { "Json", "Binary" },
+        });
+        var reply = (BrainReply)JsonConvert.DeserializeObject(UTF8Encoding.UTF8.GetString(this.Recv()), typeof(BrainReply));
+        if (reply.Type != "Accept")
+        {
+            Debug.LogError($"the brain rejected this simulation: {reply.Reason}");
+            this.socket.Close();
+#if UNITY_EDITOR
+            UnityEditor.EditorApplication.ExitPlaymode();
+#else
+            Application.Quit();
+#endif
+            throw new InvalidOperationException(reply.Reason);
+        }
+        this.binary = reply.WireFormat == "Binary";
+    }
     public void InformGameHasStarted()
     {
-        this.Send(UTF8Encoding.UTF8.GetBytes("GAME STARTED"));
+        this.SendJson(new ControlMessage{ Type = "EpisodeStart" });
+    }
+    public void InformGameHasEnded(string reason)
+    {
+        this.SendJson(new ControlMessage{ Type = "EpisodeEnd", Reason = reason });
+    }
+    void SendJson(object message)
+    {
+        this.Send(UTF8Encoding.UTF8.GetBytes(JsonConvert.SerializeObject(message)));
     }
     void Send(byte[] bytes)
     {
@@ -268,25 +415,66 @@ public class AgentEndpoint
         return msgBuf;
     }
 
-    public GameAction SendStateAndRecvAction(GameState state, float reward)
+    // null when the brain resets the episode instead of acting
+    public GameAction? SendStateAndRecvAction(GameState state, float reward)
     {
         void sendState()
         {
+            if (this.binary)
+            {
+                this.Send(BinaryWire.EncodeStep(state, reward));
+                return;
+            }
             var toSend = new GameStateAndReward(){
+                Type = "Step",
                 Reward = reward,
                 State = state
             };
             var toSendBytes = UTF8Encoding.UTF8.GetBytes( JsonConvert.SerializeObject(toSend));
             this.Send(toSendBytes);
         }
-        GameAction recvAction()
+        GameAction? recvAction()
         {
-            var msgBuf = this.Recv();
-            var msgStr = UTF8Encoding.UTF8.GetString(msgBuf);
-            Debug.Log($"input message is: {msgStr}");
-            var parsedValue = (GameAction)JsonConvert.DeserializeObject(msgStr, typeof(GameAction));
-            Debug.Log($"parsed input is: {parsedValue}");
-            return parsedValue;
+            while (true)
+            {
+                var msgBuf = this.Recv();
+                if (msgBuf.Length > 0 && msgBuf[0] == BinaryWire.ActionTag)
+                {
+                    return BinaryWire.DecodeAction(msgBuf);
+                }
+                var msgStr = UTF8Encoding.UTF8.GetString(msgBuf);
+                Debug.Log($"input message is: {msgStr}");
+                var message = JObject.Parse(msgStr);
+                // commands come before the action, only actions have no type
+                if (!message.ContainsKey("Type"))
+                {
+                    var parsedValue = message.ToObject<GameAction>();
+                    Debug.Log($"parsed input is: {parsedValue}");
+                    return parsedValue;
+                }
+                var command = message.ToObject<BrainCommand>();
+                switch (command.Type)
+                {
+                    case "ResetEpisode":
+                        return null;
+                    case "SetSeed":
+                        // Unity seeds with an int, both halves count so seeds differing in their high bits stay apart
+                        this.PendingSeed = unchecked((int)(command.Seed ^ (command.Seed >> 32)));
+                        break;
+                    case "SetTimeScale":
+                        this.TimeScale = command.TimeScale;
+                        break;
+                    case "SetEpisodeDuration":
+                        this.EpisodeDuration = command.EpisodeDuration;
+                        break;
+                    case "PlaceTarget":
+                        this.TargetPosition = (Vector3)command.Position;
+                        break;
+                    default:
+                        Debug.LogError($"unknown command from the brain: {command.Type}");
+                        break;
+                }
+            }
         }
         try
         {